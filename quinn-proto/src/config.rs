@@ -9,7 +9,8 @@ use crate::{
     cid_generator::{ConnectionIdGenerator, RandomConnectionIdGenerator},
     congestion,
     crypto::{self, HandshakeTokenKey, HmacKey},
-    VarInt, VarIntBoundsExceeded, DEFAULT_SUPPORTED_VERSIONS, INITIAL_MTU, MAX_UDP_PAYLOAD,
+    VarInt, VarIntBoundsExceeded, DEFAULT_SUPPORTED_VERSIONS, INITIAL_MTU, MAX_DSCP,
+    MAX_UDP_PAYLOAD,
 };
 
 /// Parameters governing the core QUIC state machine
@@ -28,13 +29,18 @@ pub struct TransportConfig {
     pub(crate) max_concurrent_bidi_streams: VarInt,
     pub(crate) max_concurrent_uni_streams: VarInt,
     pub(crate) max_idle_timeout: Option<VarInt>,
+    pub(crate) handshake_timeout: Option<Duration>,
     pub(crate) stream_receive_window: VarInt,
     pub(crate) receive_window: VarInt,
+    pub(crate) receive_window_auto_tuning: bool,
+    pub(crate) max_stream_receive_window: VarInt,
+    pub(crate) max_receive_window: VarInt,
     pub(crate) send_window: u64,
 
     pub(crate) max_tlps: u32,
     pub(crate) packet_threshold: u32,
     pub(crate) time_threshold: f32,
+    pub(crate) adaptive_reordering_threshold: bool,
     pub(crate) initial_rtt: Duration,
     pub(crate) initial_mtu: u16,
     pub(crate) min_mtu: u16,
@@ -44,6 +50,11 @@ pub struct TransportConfig {
     pub(crate) keep_alive_interval: Option<Duration>,
     pub(crate) crypto_buffer_size: usize,
     pub(crate) allow_spin: bool,
+    pub(crate) pacing_offload: bool,
+    pub(crate) max_send_rate: Option<u64>,
+    pub(crate) send_rate_limit: Option<u64>,
+    pub(crate) dscp: Option<u8>,
+    pub(crate) send_ect1: bool,
     pub(crate) datagram_receive_buffer_size: Option<usize>,
     pub(crate) datagram_send_buffer_size: usize,
 
@@ -95,6 +106,20 @@ impl TransportConfig {
         self
     }
 
+    /// Maximum duration of the handshake
+    ///
+    /// Connections still handshaking this long after they were created are abandoned with
+    /// [`ConnectionError::TimedOut`](crate::ConnectionError::TimedOut), even if the peer keeps the
+    /// idle timeout from expiring, and report
+    /// [`HandshakeStats::timed_out`](crate::HandshakeStats::timed_out). Bounds how long a slow or
+    /// malicious client can hold on to a server's resources without completing the handshake.
+    ///
+    /// `None` to disable, which is the default.
+    pub fn handshake_timeout(&mut self, value: Option<Duration>) -> &mut Self {
+        self.handshake_timeout = value;
+        self
+    }
+
     /// Maximum number of bytes the peer may transmit without acknowledgement on any one stream
     /// before becoming blocked.
     ///
@@ -119,6 +144,37 @@ impl TransportConfig {
         self
     }
 
+    /// Whether to grow receive windows as the bandwidth-delay product of the connection increases
+    ///
+    /// When enabled, `stream_receive_window` and `receive_window` are only the initial windows.
+    /// Each is grown to four times the rate at which the application reads multiplied by the
+    /// round-trip time, as measured whenever flow control credit is issued, up to
+    /// `max_stream_receive_window` and `max_receive_window` respectively. This allows small
+    /// initial windows, bounding memory use by idle or slow connections, without capping the
+    /// throughput of fast ones. Windows are never shrunk. Disabled by default.
+    pub fn receive_window_auto_tuning(&mut self, value: bool) -> &mut Self {
+        self.receive_window_auto_tuning = value;
+        self
+    }
+
+    /// Largest window any one stream's receive window may be grown to by auto-tuning
+    ///
+    /// See [`receive_window_auto_tuning`](Self::receive_window_auto_tuning). Has no effect if no
+    /// larger than `stream_receive_window`.
+    pub fn max_stream_receive_window(&mut self, value: VarInt) -> &mut Self {
+        self.max_stream_receive_window = value;
+        self
+    }
+
+    /// Largest window the connection's receive window may be grown to by auto-tuning
+    ///
+    /// See [`receive_window_auto_tuning`](Self::receive_window_auto_tuning). Has no effect if no
+    /// larger than `receive_window`.
+    pub fn max_receive_window(&mut self, value: VarInt) -> &mut Self {
+        self.max_receive_window = value;
+        self
+    }
+
     /// Maximum number of bytes to transmit to a peer without acknowledgment
     ///
     /// Provides an upper bound on memory when communicating with peers that issue large amounts of
@@ -150,6 +206,23 @@ impl TransportConfig {
         self
     }
 
+    /// Whether to raise the reordering thresholds of a path when packets deemed lost are later
+    /// acknowledged
+    ///
+    /// When enabled, each spurious loss raises the path's `packet_threshold` and `time_threshold`
+    /// to values which would have avoided it, up to a limit. They decay back towards the
+    /// configured values after a run of congestion events without spurious losses. The
+    /// congestion controller is also told when every packet lost in its most recent congestion
+    /// event has turned out to have been delivered, so that it may undo its response. Useful on
+    /// paths which reorder heavily, such as those spread over several links. Spurious losses are
+    /// counted in [`PathStats`](crate::PathStats) either way.
+    ///
+    /// Defaults to false.
+    pub fn adaptive_reordering_threshold(&mut self, value: bool) -> &mut Self {
+        self.adaptive_reordering_threshold = value;
+        self
+    }
+
     /// The RTT used before an RTT sample is taken
     pub fn initial_rtt(&mut self, value: Duration) -> &mut Self {
         self.initial_rtt = value;
@@ -273,6 +346,78 @@ impl TransportConfig {
         self
     }
 
+    /// Whether to delegate pacing to the I/O layer by stamping transmits with departure times
+    ///
+    /// When enabled, datagrams which would otherwise be held back by the pacer are emitted early,
+    /// with [`Transmit::departure_time`](crate::Transmit::departure_time) set to the time they
+    /// should leave the host. This avoids frequent pacing timer wakeups at high send rates, but
+    /// needs departure times to be honored, e.g. via `SO_TXTIME` in combination with the `fq`
+    /// queueing discipline on Linux. Connections keep pacing with timers unless the I/O layer
+    /// reports support, see [`Connection::set_departure_times_supported()`], and for departure
+    /// times too far in the future.
+    ///
+    /// [`Connection::set_departure_times_supported()`]: crate::Connection::set_departure_times_supported
+    ///
+    /// Defaults to `false`.
+    pub fn pacing_offload(&mut self, value: bool) -> &mut Self {
+        self.pacing_offload = value;
+        self
+    }
+
+    /// Maximum rate at which a connection may send, in bytes per second
+    ///
+    /// Packets are paced no faster than this, even if the congestion controller would allow it.
+    /// Counts QUIC packets including their headers, but not UDP or IP overhead.
+    ///
+    /// Defaults to `None`, for no limit.
+    pub fn max_send_rate(&mut self, value: Option<u64>) -> &mut Self {
+        self.max_send_rate = value;
+        self
+    }
+
+    /// Initial limit on the rate at which a connection sends stream data, in bytes per second
+    ///
+    /// Unlike [`max_send_rate`](Self::max_send_rate), only stream payload is counted, and the
+    /// limit may be changed at runtime with
+    /// [`Connection::set_send_rate_limit()`](crate::Connection::set_send_rate_limit).
+    ///
+    /// Defaults to `None`, for no limit.
+    pub fn send_rate_limit(&mut self, value: Option<u64>) -> &mut Self {
+        self.send_rate_limit = value;
+        self
+    }
+
+    /// Differentiated services code point to mark outgoing packets with, if any
+    ///
+    /// Allows connections sharing a socket to receive different treatment by the network, e.g.
+    /// Expedited Forwarding (46) for interactive traffic or CS1 (8) for bulk transfers. May be
+    /// overridden for data of individual streams using
+    /// [`SendStream::set_dscp()`](crate::SendStream::set_dscp). The ECN bits are set independently.
+    ///
+    /// Must be less than 64. Defaults to `None`, leaving the packets unmarked.
+    pub fn dscp(&mut self, value: Option<u8>) -> Result<&mut Self, ConfigError> {
+        if value.map_or(false, |x| x > MAX_DSCP) {
+            return Err(ConfigError::OutOfBounds);
+        }
+
+        self.dscp = value;
+        Ok(self)
+    }
+
+    /// Whether to mark outgoing packets ECT(1) rather than ECT(0)
+    ///
+    /// ECT(1) identifies traffic as using L4S, which scalable network queues respond to by marking
+    /// packets Congestion Experienced at a much shallower queue depth. This should only be enabled
+    /// in combination with a congestion controller which responds to such marks proportionally,
+    /// such as [`congestion::PragueConfig`], since classic controllers will severely underutilize
+    /// such paths.
+    ///
+    /// Defaults to `false`.
+    pub fn send_ect1(&mut self, value: bool) -> &mut Self {
+        self.send_ect1 = value;
+        self
+    }
+
     /// How to construct new `congestion::Controller`s
     ///
     /// Typically the refcounted configuration of a `congestion::Controller`,
@@ -305,13 +450,18 @@ impl Default for TransportConfig {
             max_concurrent_bidi_streams: 100u32.into(),
             max_concurrent_uni_streams: 100u32.into(),
             max_idle_timeout: Some(VarInt(10_000)),
+            handshake_timeout: None,
             stream_receive_window: STREAM_RWND.into(),
             receive_window: VarInt::MAX,
+            receive_window_auto_tuning: false,
+            max_stream_receive_window: (16 * STREAM_RWND).into(),
+            max_receive_window: VarInt::MAX,
             send_window: (8 * STREAM_RWND).into(),
 
             max_tlps: 2,
             packet_threshold: 3,
             time_threshold: 9.0 / 8.0,
+            adaptive_reordering_threshold: false,
             initial_rtt: Duration::from_millis(333), // per spec, intentionally distinct from EXPECTED_RTT
             initial_mtu: INITIAL_MTU,
             min_mtu: INITIAL_MTU,
@@ -321,6 +471,11 @@ impl Default for TransportConfig {
             keep_alive_interval: None,
             crypto_buffer_size: 16 * 1024,
             allow_spin: true,
+            pacing_offload: false,
+            max_send_rate: None,
+            send_rate_limit: None,
+            dscp: None,
+            send_ect1: false,
             datagram_receive_buffer_size: Some(STREAM_RWND as usize),
             datagram_send_buffer_size: 1024 * 1024,
 
@@ -341,12 +496,23 @@ impl fmt::Debug for TransportConfig {
                 &self.max_concurrent_uni_streams,
             )
             .field("max_idle_timeout", &self.max_idle_timeout)
+            .field("handshake_timeout", &self.handshake_timeout)
             .field("stream_receive_window", &self.stream_receive_window)
             .field("receive_window", &self.receive_window)
+            .field(
+                "receive_window_auto_tuning",
+                &self.receive_window_auto_tuning,
+            )
+            .field("max_stream_receive_window", &self.max_stream_receive_window)
+            .field("max_receive_window", &self.max_receive_window)
             .field("send_window", &self.send_window)
             .field("max_tlps", &self.max_tlps)
             .field("packet_threshold", &self.packet_threshold)
             .field("time_threshold", &self.time_threshold)
+            .field(
+                "adaptive_reordering_threshold",
+                &self.adaptive_reordering_threshold,
+            )
             .field("initial_rtt", &self.initial_rtt)
             .field(
                 "persistent_congestion_threshold",
@@ -355,6 +521,11 @@ impl fmt::Debug for TransportConfig {
             .field("keep_alive_interval", &self.keep_alive_interval)
             .field("crypto_buffer_size", &self.crypto_buffer_size)
             .field("allow_spin", &self.allow_spin)
+            .field("pacing_offload", &self.pacing_offload)
+            .field("max_send_rate", &self.max_send_rate)
+            .field("send_rate_limit", &self.send_rate_limit)
+            .field("dscp", &self.dscp)
+            .field("send_ect1", &self.send_ect1)
             .field(
                 "datagram_receive_buffer_size",
                 &self.datagram_receive_buffer_size,
@@ -486,6 +657,9 @@ pub struct EndpointConfig {
         Arc<dyn Fn() -> Box<dyn ConnectionIdGenerator> + Send + Sync>,
     pub(crate) supported_versions: Vec<u32>,
     pub(crate) grease_quic_bit: bool,
+    pub(crate) path_cache_capacity: usize,
+    pub(crate) path_cache_lifetime: Duration,
+    pub(crate) stream_memory_budget: Option<u64>,
 }
 
 impl EndpointConfig {
@@ -499,6 +673,9 @@ impl EndpointConfig {
             connection_id_generator_factory: Arc::new(cid_factory),
             supported_versions: DEFAULT_SUPPORTED_VERSIONS.to_vec(),
             grease_quic_bit: true,
+            path_cache_capacity: 256,
+            path_cache_lifetime: Duration::from_secs(600),
+            stream_memory_budget: None,
         }
     }
 
@@ -569,6 +746,45 @@ impl EndpointConfig {
         self.grease_quic_bit = value;
         self
     }
+
+    /// Maximum number of remote addresses for which to remember path properties
+    ///
+    /// When a connection closes, the path MTU, smoothed RTT and congestion window it learned are
+    /// remembered, and used as a starting point by new connections to the same address: the MTU is
+    /// probed first once the handshake completes, the RTT replaces
+    /// [`TransportConfig::initial_rtt`], and the congestion window is raised to at most four times
+    /// its initial value. When the cache is full, the least recently updated entry is evicted.
+    ///
+    /// Defaults to 256. Set to 0 to disable the cache.
+    pub fn path_cache_capacity(&mut self, value: usize) -> &mut Self {
+        self.path_cache_capacity = value;
+        self
+    }
+
+    /// How long remembered path properties remain usable by new connections
+    ///
+    /// Defaults to 10 minutes.
+    pub fn path_cache_lifetime(&mut self, value: Duration) -> &mut Self {
+        self.path_cache_lifetime = value;
+        self
+    }
+
+    /// Upper bound on stream data buffered across all of the endpoint's connections, in bytes
+    ///
+    /// Counts data written by the application but not yet acknowledged by the peer, and data
+    /// received from the peer but not yet read by the application. As the budget runs out,
+    /// connections advertise smaller receive windows and stream writes are held back, so that
+    /// even many connections with slow readers or peers stay within it. Each connection may
+    /// buffer a few kilobytes in each direction regardless, so that it can always make progress.
+    /// Current usage is reported by [`Endpoint::stream_memory_usage()`].
+    ///
+    /// [`Endpoint::stream_memory_usage()`]: crate::Endpoint::stream_memory_usage
+    ///
+    /// Unlimited by default.
+    pub fn stream_memory_budget(&mut self, value: Option<u64>) -> &mut Self {
+        self.stream_memory_budget = value;
+        self
+    }
 }
 
 impl fmt::Debug for EndpointConfig {
@@ -579,6 +795,9 @@ impl fmt::Debug for EndpointConfig {
             .field("cid_generator_factory", &"[ elided ]")
             .field("supported_versions", &self.supported_versions)
             .field("grease_quic_bit", &self.grease_quic_bit)
+            .field("path_cache_capacity", &self.path_cache_capacity)
+            .field("path_cache_lifetime", &self.path_cache_lifetime)
+            .field("stream_memory_budget", &self.stream_memory_budget)
             .finish()
     }
 }
@@ -627,6 +846,9 @@ pub struct ServerConfig {
     /// Improves behavior for clients that move between different internet connections or suffer NAT
     /// rebinding. Enabled by default.
     pub(crate) migration: bool,
+
+    /// Share of the endpoint's egress bandwidth given to incoming connections
+    pub(crate) egress_weight: u32,
 }
 
 impl ServerConfig {
@@ -646,6 +868,8 @@ impl ServerConfig {
             concurrent_connections: 100_000,
 
             migration: true,
+
+            egress_weight: 1,
         }
     }
 
@@ -692,6 +916,17 @@ impl ServerConfig {
         self.migration = value;
         self
     }
+
+    /// Weight of incoming connections when sharing the endpoint's egress bandwidth
+    ///
+    /// Has no effect on its own. I/O layers which limit the endpoint's aggregate send rate divide
+    /// it among busy connections in proportion to their weights. Zero is treated as one. Can be
+    /// changed per connection with
+    /// [`Connection::set_egress_weight()`](crate::Connection::set_egress_weight). Defaults to 1.
+    pub fn egress_weight(&mut self, value: u32) -> &mut Self {
+        self.egress_weight = value;
+        self
+    }
 }
 
 #[cfg(feature = "rustls")]
@@ -733,6 +968,7 @@ impl fmt::Debug for ServerConfig {
             .field("retry_token_lifetime", &self.retry_token_lifetime)
             .field("concurrent_connections", &self.concurrent_connections)
             .field("migration", &self.migration)
+            .field("egress_weight", &self.egress_weight)
             .finish()
     }
 }
@@ -5,12 +5,22 @@ use std::any::Any;
 use std::time::Instant;
 
 mod bbr;
+mod bbr3;
 mod cubic;
+mod delivery_rate;
+mod hystart;
+mod ledbat;
 mod new_reno;
+mod prague;
 
 pub use bbr::{Bbr, BbrConfig};
+pub use bbr3::{Bbr3, Bbr3Config};
 pub use cubic::{Cubic, CubicConfig};
+pub use delivery_rate::RateSample;
+pub(crate) use delivery_rate::{DeliveryRateSampler, SentDeliveryState};
+pub use ledbat::{Ledbat, LedbatConfig};
 pub use new_reno::{NewReno, NewRenoConfig};
+pub use prague::{Prague, PragueConfig};
 
 /// Common interface for different congestion controllers
 pub trait Controller: Send {
@@ -33,6 +43,14 @@ pub trait Controller: Send {
     ) {
     }
 
+    /// A batch of acknowledgements yielded a delivery rate sample
+    ///
+    /// Called once per batch of acknowledgements which newly acknowledged an ack-eliciting
+    /// packet, after all of the batch's calls to [`on_ack`](Self::on_ack) and before
+    /// [`on_end_acks`](Self::on_end_acks).
+    #[allow(unused_variables)]
+    fn on_rate_sample(&mut self, now: Instant, sample: &RateSample) {}
+
     /// Packets are acked in batches, all with the same `now` argument. This indicates one of those batches has completed.
     #[allow(unused_variables)]
     fn on_end_acks(
@@ -58,12 +76,80 @@ pub trait Controller: Send {
         lost_bytes: u64,
     );
 
+    /// Every packet deemed lost in the most recent congestion event has since been acknowledged
+    ///
+    /// Only called when
+    /// [`TransportConfig::adaptive_reordering_threshold`](crate::TransportConfig::adaptive_reordering_threshold)
+    /// is enabled, and never after a congestion event triggered by ECN. Controllers may restore the
+    /// state they had before responding to the event.
+    fn on_spurious_congestion_event(&mut self) {}
+
+    /// An acknowledgement carrying validated ECN counts was received
+    ///
+    /// `newly_acked` packets were acknowledged, `newly_ce` of which were marked Congestion
+    /// Experienced. `sent` is the time the largest newly acknowledged packet was sent. By default,
+    /// any CE mark is treated as a congestion event without loss.
+    #[allow(unused_variables)]
+    fn on_ecn(&mut self, now: Instant, sent: Instant, newly_acked: u64, newly_ce: u64) {
+        if newly_ce != 0 {
+            self.on_congestion_event(now, sent, false, 0);
+        }
+    }
+
     /// The known MTU for the current network path has been updated
     fn on_mtu_update(&mut self, new_mtu: u16);
 
+    /// Start from a congestion window learned by an earlier connection on the same path, or used
+    /// by the controller this one replaces
+    ///
+    /// Called before this controller is used. Controllers which cannot make use of it may ignore
+    /// it.
+    #[allow(unused_variables)]
+    fn seed_window(&mut self, window: u64) {}
+
+    /// Continue from the state of the controller this one replaces
+    ///
+    /// `window`, `ssthresh` and `recovery_start` are the replaced controller's
+    /// [`window`](Self::window), [`ssthresh`](Self::ssthresh) and
+    /// [`recovery_start`](Self::recovery_start). Called before this controller is used. By
+    /// default, only the window is carried over.
+    #[allow(unused_variables)]
+    fn seed(&mut self, window: u64, ssthresh: u64, recovery_start: Option<Instant>) {
+        self.seed_window(window);
+    }
+
     /// Number of ack-eliciting bytes that may be in flight
     fn window(&self) -> u64;
 
+    /// Window size above which growth slows from slow start to congestion avoidance
+    ///
+    /// `u64::MAX` until the initial slow start ends.
+    fn ssthresh(&self) -> u64 {
+        u64::MAX
+    }
+
+    /// Start of the current recovery period, if any
+    ///
+    /// Acknowledgements of packets sent before this time do not grow the window.
+    fn recovery_start(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Rate at which packets should be paced, in bytes per second
+    ///
+    /// By default, packets are paced at a rate slightly faster than one congestion window per
+    /// round trip.
+    fn pacing_rate(&self) -> Option<u64> {
+        None
+    }
+
+    /// Number of bytes which may be sent in a single burst when pacing
+    ///
+    /// By default, the burst size is derived from the pacing rate.
+    fn send_quantum(&self) -> Option<u64> {
+        None
+    }
+
     /// Duplicate the controller's state
     fn clone_box(&self) -> Box<dyn Controller>;
 
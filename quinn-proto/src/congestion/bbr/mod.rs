@@ -476,6 +476,10 @@ impl Controller for Bbr {
         self.cwnd = self.cwnd.max(self.min_cwnd);
     }
 
+    fn seed_window(&mut self, window: u64) {
+        self.cwnd = window.max(self.min_cwnd);
+    }
+
     fn window(&self) -> u64 {
         if self.mode == Mode::ProbeRtt {
             return self.get_probe_rtt_cwnd();
@@ -485,6 +489,21 @@ impl Controller for Bbr {
         self.cwnd
     }
 
+    fn ssthresh(&self) -> u64 {
+        match self.mode {
+            Mode::Startup => u64::MAX,
+            _ => self.cwnd,
+        }
+    }
+
+    fn pacing_rate(&self) -> Option<u64> {
+        // Zero until the bandwidth has been estimated
+        match self.pacing_rate {
+            0 => None,
+            rate => Some(rate),
+        }
+    }
+
     fn clone_box(&self) -> Box<dyn Controller> {
         Box::new(self.clone())
     }
@@ -0,0 +1,1105 @@
+use std::any::Any;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng};
+
+use super::{Controller, ControllerFactory, RateSample, BASE_DATAGRAM_SIZE};
+
+/// Experimental! Use at your own risk.
+///
+/// Version 3 of BBR <https://datatracker.ietf.org/doc/draft-ietf-ccwg-bbr/>. Like [`Bbr`](super::Bbr),
+/// it paces data according to a model of the path's bandwidth and round-trip time, but it also
+/// bounds the amount of data in flight in response to packet loss and ECN marks. This avoids
+/// excessive retransmissions on paths with shallow buffers, and makes it coexist more fairly with
+/// loss-based controllers such as [`Cubic`](super::Cubic).
+#[derive(Debug, Clone)]
+pub struct Bbr3 {
+    config: Arc<Bbr3Config>,
+    current_mtu: u64,
+    state: State,
+    pacing_gain: f64,
+    cwnd_gain: f64,
+    cwnd: u64,
+    /// Congestion window to restore on leaving ProbeRtt
+    prior_cwnd: u64,
+    /// Bytes per second
+    pacing_rate: u64,
+    /// Total bytes delivered, as of the latest rate sample
+    delivered: u64,
+    /// Most recent valid delivery rate sample
+    latest_rate: u64,
+    /// Data in flight when the latest rate sample's packet was sent
+    tx_in_flight: u64,
+    /// Whether the latest rate sample was limited by the application
+    app_limited: bool,
+    round_count: u64,
+    /// Total bytes delivered at which the current round trip ends
+    next_round_delivered: u64,
+    round_start: bool,
+    /// Windowed maximum delivery rate over the current and previous ProbeBw cycles
+    max_bw: [u64; 2],
+    /// Short-term lower bound on bandwidth, from recent congestion, or `u64::MAX`
+    bw_lo: u64,
+    /// Bandwidth used by the model, bounded by `bw_lo`
+    bw: u64,
+    /// Long-term upper bound on data in flight, from loss or ECN marks when probing, or `u64::MAX`
+    inflight_hi: u64,
+    /// Short-term lower bound on data in flight, from recent congestion, or `u64::MAX`
+    inflight_lo: u64,
+    /// Maximum delivery rate sampled in the current round trip
+    bw_latest: u64,
+    /// Maximum volume of data delivered in a single sample in the current round trip
+    inflight_latest: u64,
+    min_rtt: Option<Duration>,
+    min_rtt_stamp: Instant,
+    probe_rtt_min_delay: Option<Duration>,
+    probe_rtt_min_stamp: Instant,
+    probe_rtt_expired: bool,
+    probe_rtt_done_stamp: Option<Instant>,
+    probe_rtt_round_done: bool,
+    full_bw: u64,
+    full_bw_count: u32,
+    /// Whether the bandwidth plateaued recently
+    full_bw_now: bool,
+    /// Whether the bandwidth ever plateaued, ending Startup
+    full_bw_reached: bool,
+    /// When the current ProbeBw cycle started
+    cycle_stamp: Instant,
+    rounds_since_bw_probe: u64,
+    bw_probe_wait: Duration,
+    /// Bytes to acknowledge per MTU of growth in `inflight_hi` while probing for bandwidth
+    bw_probe_up_cnt: u64,
+    bw_probe_up_acks: u64,
+    bw_probe_up_rounds: u32,
+    /// Whether congestion signals currently indicate the limits of a bandwidth probe
+    bw_probe_samples: bool,
+    loss_in_round: bool,
+    ce_in_round: bool,
+    acked_packets_in_round: u64,
+    ce_packets_in_round: u64,
+    /// Moving average of the fraction of packets marked CE per round trip
+    ecn_alpha: f64,
+    /// Maxima of excess data acknowledged over the expected delivery rate, over alternating
+    /// windows of round trips
+    extra_acked: [u64; 2],
+    extra_acked_idx: usize,
+    extra_acked_round: u64,
+    extra_acked_interval_start: Instant,
+    extra_acked_delivered: u64,
+    random_number_generator: rand::rngs::StdRng,
+}
+
+impl Bbr3 {
+    /// Construct a state using the given `config` and current time `now`
+    pub fn new(config: Arc<Bbr3Config>, now: Instant, current_mtu: u16) -> Self {
+        let initial_window = config.initial_window;
+        let random_number_generator = match config.rng_seed {
+            Some(seed) => rand::rngs::StdRng::seed_from_u64(seed),
+            None => rand::rngs::StdRng::from_entropy(),
+        };
+        let mut bbr = Self {
+            config,
+            current_mtu: current_mtu as u64,
+            state: State::Startup,
+            pacing_gain: STARTUP_PACING_GAIN,
+            cwnd_gain: STARTUP_CWND_GAIN,
+            cwnd: initial_window,
+            prior_cwnd: 0,
+            pacing_rate: 0,
+            delivered: 0,
+            latest_rate: 0,
+            tx_in_flight: 0,
+            app_limited: false,
+            round_count: 0,
+            next_round_delivered: 0,
+            round_start: false,
+            max_bw: [0; 2],
+            bw_lo: u64::MAX,
+            bw: 0,
+            inflight_hi: u64::MAX,
+            inflight_lo: u64::MAX,
+            bw_latest: 0,
+            inflight_latest: 0,
+            min_rtt: None,
+            min_rtt_stamp: now,
+            probe_rtt_min_delay: None,
+            probe_rtt_min_stamp: now,
+            probe_rtt_expired: false,
+            probe_rtt_done_stamp: None,
+            probe_rtt_round_done: false,
+            full_bw: 0,
+            full_bw_count: 0,
+            full_bw_now: false,
+            full_bw_reached: false,
+            cycle_stamp: now,
+            rounds_since_bw_probe: 0,
+            bw_probe_wait: Duration::ZERO,
+            bw_probe_up_cnt: u64::MAX,
+            bw_probe_up_acks: 0,
+            bw_probe_up_rounds: 0,
+            bw_probe_samples: false,
+            loss_in_round: false,
+            ce_in_round: false,
+            acked_packets_in_round: 0,
+            ce_packets_in_round: 0,
+            ecn_alpha: 0.0,
+            extra_acked: [0; 2],
+            extra_acked_idx: 0,
+            extra_acked_round: 0,
+            extra_acked_interval_start: now,
+            extra_acked_delivered: 0,
+            random_number_generator,
+        };
+        bbr.init_pacing_rate();
+        bbr
+    }
+
+    fn init_pacing_rate(&mut self) {
+        // Assume a round-trip time of 1ms until one is measured
+        let nominal_bandwidth = self.config.initial_window * 1000;
+        self.pacing_rate = (STARTUP_PACING_GAIN * nominal_bandwidth as f64) as u64;
+    }
+
+    fn enter_startup(&mut self) {
+        self.state = State::Startup;
+        self.pacing_gain = STARTUP_PACING_GAIN;
+        self.cwnd_gain = STARTUP_CWND_GAIN;
+    }
+
+    fn enter_drain(&mut self) {
+        self.state = State::Drain;
+        self.pacing_gain = DRAIN_PACING_GAIN;
+        self.cwnd_gain = STARTUP_CWND_GAIN;
+    }
+
+    fn start_probe_bw_down(&mut self, now: Instant) {
+        self.reset_congestion_signals();
+        self.bw_probe_up_cnt = u64::MAX;
+        // Randomize the time until the next probe, to desynchronize competing flows
+        self.rounds_since_bw_probe = self.random_number_generator.gen_range(0..2);
+        self.bw_probe_wait = PROBE_BW_MIN_WAIT
+            + Duration::from_millis(self.random_number_generator.gen_range(0..1000));
+        self.cycle_stamp = now;
+        // Each cycle starts a new slot of the bandwidth filter
+        self.max_bw[0] = self.max_bw[1];
+        self.max_bw[1] = 0;
+        self.start_round();
+        self.state = State::ProbeBwDown;
+        self.pacing_gain = PROBE_BW_DOWN_PACING_GAIN;
+        self.cwnd_gain = PROBE_BW_CWND_GAIN;
+    }
+
+    fn start_probe_bw_cruise(&mut self) {
+        self.state = State::ProbeBwCruise;
+        self.pacing_gain = 1.0;
+        self.cwnd_gain = PROBE_BW_CWND_GAIN;
+    }
+
+    fn start_probe_bw_refill(&mut self) {
+        self.reset_lower_bounds();
+        self.bw_probe_up_rounds = 0;
+        self.bw_probe_up_acks = 0;
+        self.start_round();
+        self.state = State::ProbeBwRefill;
+        self.pacing_gain = 1.0;
+        self.cwnd_gain = PROBE_BW_CWND_GAIN;
+    }
+
+    fn start_probe_bw_up(&mut self, sample: &RateSample) {
+        self.start_round();
+        self.reset_full_bw();
+        self.full_bw = sample.delivery_rate.unwrap_or(0);
+        self.state = State::ProbeBwUp;
+        self.pacing_gain = PROBE_BW_UP_PACING_GAIN;
+        self.cwnd_gain = PROBE_BW_UP_CWND_GAIN;
+        self.raise_inflight_hi_slope();
+    }
+
+    fn enter_probe_rtt(&mut self) {
+        self.prior_cwnd = self.prior_cwnd.max(self.cwnd);
+        self.probe_rtt_done_stamp = None;
+        self.probe_rtt_round_done = false;
+        self.start_round();
+        self.state = State::ProbeRtt;
+        self.pacing_gain = 1.0;
+        self.cwnd_gain = PROBE_RTT_CWND_GAIN;
+    }
+
+    fn exit_probe_rtt(&mut self, now: Instant) {
+        self.reset_lower_bounds();
+        if self.full_bw_reached {
+            self.start_probe_bw_down(now);
+            self.start_probe_bw_cruise();
+        } else {
+            self.enter_startup();
+        }
+    }
+
+    fn start_round(&mut self) {
+        self.next_round_delivered = self.delivered;
+    }
+
+    fn update_round(&mut self, sample: &RateSample) {
+        self.round_start = sample.prior_delivered >= self.next_round_delivered;
+        if self.round_start {
+            self.start_round();
+            self.round_count += 1;
+            self.rounds_since_bw_probe += 1;
+        }
+    }
+
+    fn max_bw(&self) -> u64 {
+        self.max_bw[0].max(self.max_bw[1])
+    }
+
+    fn update_max_bw(&mut self, sample: &RateSample) {
+        let rate = match sample.delivery_rate {
+            Some(rate) => rate,
+            None => return,
+        };
+        // App-limited samples underestimate the bandwidth, unless they exceed the current estimate
+        if rate >= self.max_bw() || !sample.is_app_limited {
+            self.max_bw[1] = self.max_bw[1].max(rate);
+        }
+    }
+
+    fn update_congestion_signals(&mut self, sample: &RateSample) {
+        self.update_max_bw(sample);
+        if !self.round_start {
+            return;
+        }
+
+        if self.acked_packets_in_round > 0 {
+            let ce_fraction = self.ce_packets_in_round as f64 / self.acked_packets_in_round as f64;
+            self.ecn_alpha = (1.0 - ECN_ALPHA_GAIN) * self.ecn_alpha + ECN_ALPHA_GAIN * ce_fraction;
+        }
+        self.adapt_lower_bounds();
+        self.loss_in_round = false;
+        self.ce_in_round = false;
+        self.acked_packets_in_round = 0;
+        self.ce_packets_in_round = 0;
+    }
+
+    /// Reduce the short-term model bounds in response to congestion in the last round trip
+    fn adapt_lower_bounds(&mut self) {
+        // Congestion is expected while probing for bandwidth, and handled via `inflight_hi`
+        if self.is_probing_bw() {
+            return;
+        }
+
+        if self.loss_in_round {
+            self.init_lower_bounds();
+            self.bw_lo = self.bw_latest.max((self.bw_lo as f64 * BETA) as u64);
+            self.inflight_lo = self
+                .inflight_latest
+                .max((self.inflight_lo as f64 * BETA) as u64);
+        }
+
+        if self.ce_in_round && self.ecn_alpha > 0.0 {
+            self.init_lower_bounds();
+            let cap = (self.cwnd as f64 * (1.0 - self.ecn_alpha * ECN_FACTOR)) as u64;
+            self.inflight_lo = self.inflight_lo.min(cap);
+        }
+    }
+
+    fn init_lower_bounds(&mut self) {
+        if self.bw_lo == u64::MAX {
+            self.bw_lo = self.max_bw();
+        }
+        if self.inflight_lo == u64::MAX {
+            self.inflight_lo = self.cwnd;
+        }
+    }
+
+    fn reset_lower_bounds(&mut self) {
+        self.bw_lo = u64::MAX;
+        self.inflight_lo = u64::MAX;
+    }
+
+    fn reset_congestion_signals(&mut self) {
+        self.loss_in_round = false;
+        self.ce_in_round = false;
+        self.bw_latest = 0;
+        self.inflight_latest = 0;
+    }
+
+    fn reset_full_bw(&mut self) {
+        self.full_bw = 0;
+        self.full_bw_count = 0;
+        self.full_bw_now = false;
+    }
+
+    /// Whether the data in flight has exceeded what the path can hold without congestion
+    fn handle_inflight_too_high(&mut self, now: Instant, tx_in_flight: u64) {
+        self.bw_probe_samples = false;
+        if !self.app_limited {
+            let target = (self.target_inflight() as f64 * BETA) as u64;
+            self.inflight_hi = tx_in_flight.max(target);
+        }
+        match self.state {
+            State::Startup => {
+                self.full_bw_reached = true;
+                self.inflight_hi = self.inflight_hi.max(self.bdp_multiple(self.bw, 1.0));
+            }
+            State::ProbeBwUp => self.start_probe_bw_down(now),
+            _ => {}
+        }
+    }
+
+    /// Whether the congestion signals seen in the current round trip can be attributed to probing
+    fn congestion_signals_apply(&self) -> bool {
+        self.bw_probe_samples || self.state == State::Startup
+    }
+
+    fn update_ack_aggregation(&mut self, now: Instant, sample: &RateSample) {
+        let interval = now.saturating_duration_since(self.extra_acked_interval_start);
+        let mut expected_delivered = bytes_in(self.bw, interval);
+        if self.extra_acked_delivered <= expected_delivered {
+            // Acks arrived no faster than expected, so start a new sampling interval
+            self.extra_acked_delivered = 0;
+            self.extra_acked_interval_start = now;
+            expected_delivered = 0;
+        }
+        self.extra_acked_delivered += sample.newly_acked;
+        let extra = (self.extra_acked_delivered - expected_delivered).min(self.cwnd);
+
+        if self.round_start && self.round_count - self.extra_acked_round >= EXTRA_ACKED_ROUNDS {
+            self.extra_acked_idx ^= 1;
+            self.extra_acked[self.extra_acked_idx] = 0;
+            self.extra_acked_round = self.round_count;
+        }
+        let slot = &mut self.extra_acked[self.extra_acked_idx];
+        *slot = (*slot).max(extra);
+    }
+
+    fn check_full_bw_reached(&mut self, sample: &RateSample) {
+        let rate = match sample.delivery_rate {
+            Some(rate) => rate,
+            None => return,
+        };
+        if self.full_bw_now || sample.is_app_limited {
+            return;
+        }
+        if rate as f64 >= self.full_bw as f64 * STARTUP_GROWTH_TARGET {
+            self.reset_full_bw();
+            self.full_bw = rate;
+            return;
+        }
+        if !self.round_start {
+            return;
+        }
+        self.full_bw_count += 1;
+        self.full_bw_now = self.full_bw_count >= STARTUP_FULL_BW_ROUNDS;
+        if self.full_bw_now {
+            self.full_bw_reached = true;
+        }
+    }
+
+    fn update_probe_bw_cycle_phase(&mut self, now: Instant, sample: &RateSample, in_flight: u64) {
+        if !self.full_bw_reached || !self.state.is_probe_bw() {
+            return;
+        }
+        self.adapt_upper_bounds(sample, in_flight);
+
+        let state = self.state;
+        match state {
+            State::ProbeBwDown => {
+                if self.check_time_to_probe_bw(now) {
+                    return;
+                }
+                if self.check_time_to_cruise(in_flight) {
+                    self.start_probe_bw_cruise();
+                }
+            }
+            State::ProbeBwCruise => {
+                self.check_time_to_probe_bw(now);
+            }
+            // After refilling the pipe for a round trip, congestion can be attributed to probing
+            State::ProbeBwRefill if self.round_start => {
+                self.bw_probe_samples = true;
+                self.start_probe_bw_up(sample);
+            }
+            State::ProbeBwUp if self.check_time_to_go_down(in_flight) => {
+                self.start_probe_bw_down(now);
+            }
+            _ => {}
+        }
+    }
+
+    /// Raise `inflight_hi` if more data was delivered without congestion than it permits
+    fn adapt_upper_bounds(&mut self, sample: &RateSample, in_flight: u64) {
+        if self.inflight_hi == u64::MAX || self.loss_in_round || self.ce_in_round {
+            return;
+        }
+        if sample.tx_in_flight > self.inflight_hi {
+            self.inflight_hi = sample.tx_in_flight;
+        }
+        if self.state == State::ProbeBwUp {
+            self.probe_inflight_hi_upward(sample, in_flight);
+        }
+    }
+
+    fn probe_inflight_hi_upward(&mut self, sample: &RateSample, in_flight: u64) {
+        if !self.is_cwnd_limited(in_flight) || self.cwnd < self.inflight_hi {
+            return;
+        }
+        self.bw_probe_up_acks += sample.newly_acked;
+        if self.bw_probe_up_acks >= self.bw_probe_up_cnt {
+            let delta = self.bw_probe_up_acks / self.bw_probe_up_cnt;
+            self.bw_probe_up_acks -= delta * self.bw_probe_up_cnt;
+            self.inflight_hi += delta * self.current_mtu;
+        }
+        if self.round_start {
+            self.raise_inflight_hi_slope();
+        }
+    }
+
+    /// Grow `inflight_hi` exponentially faster in each round trip spent probing
+    fn raise_inflight_hi_slope(&mut self) {
+        let growth_packets = 1 << self.bw_probe_up_rounds;
+        self.bw_probe_up_rounds = (self.bw_probe_up_rounds + 1).min(30);
+        self.bw_probe_up_cnt = (self.cwnd / growth_packets).max(1);
+    }
+
+    fn check_time_to_probe_bw(&mut self, now: Instant) -> bool {
+        if now.saturating_duration_since(self.cycle_stamp) > self.bw_probe_wait
+            || self.is_reno_coexistence_probe_time()
+        {
+            self.start_probe_bw_refill();
+            return true;
+        }
+        false
+    }
+
+    /// Whether a loss-based controller would have grown its window enough to warrant a probe
+    fn is_reno_coexistence_probe_time(&self) -> bool {
+        let reno_rounds = self.target_inflight() / self.current_mtu;
+        self.rounds_since_bw_probe >= reno_rounds.min(63)
+    }
+
+    fn check_time_to_cruise(&self, in_flight: u64) -> bool {
+        if in_flight > self.inflight_with_headroom() {
+            return false;
+        }
+        in_flight <= self.inflight(self.max_bw(), 1.0)
+    }
+
+    fn check_time_to_go_down(&mut self, in_flight: u64) -> bool {
+        if self.is_cwnd_limited(in_flight) && self.cwnd >= self.inflight_hi {
+            // Bandwidth can't be measured while limited by `inflight_hi`, so keep probing
+            self.reset_full_bw();
+            self.full_bw = self.latest_rate;
+        } else if self.full_bw_now {
+            return true;
+        }
+        false
+    }
+
+    fn update_min_rtt(&mut self, now: Instant, rtt: Duration) {
+        self.probe_rtt_expired =
+            now.saturating_duration_since(self.probe_rtt_min_stamp) > PROBE_RTT_INTERVAL;
+        if self.probe_rtt_min_delay.map_or(true, |x| rtt < x) || self.probe_rtt_expired {
+            self.probe_rtt_min_delay = Some(rtt);
+            self.probe_rtt_min_stamp = now;
+        }
+
+        let min_rtt_expired =
+            now.saturating_duration_since(self.min_rtt_stamp) > MIN_RTT_FILTER_LEN;
+        if self
+            .min_rtt
+            .map_or(true, |x| self.probe_rtt_min_delay < Some(x))
+            || min_rtt_expired
+        {
+            self.min_rtt = self.probe_rtt_min_delay;
+            self.min_rtt_stamp = self.probe_rtt_min_stamp;
+        }
+    }
+
+    fn check_probe_rtt(&mut self, now: Instant, in_flight: u64) {
+        if self.state != State::ProbeRtt && self.probe_rtt_expired {
+            self.enter_probe_rtt();
+        }
+        if self.state != State::ProbeRtt {
+            return;
+        }
+
+        match self.probe_rtt_done_stamp {
+            None => {
+                if in_flight <= self.probe_rtt_cwnd() {
+                    self.probe_rtt_done_stamp = Some(now + PROBE_RTT_DURATION);
+                    self.probe_rtt_round_done = false;
+                    self.start_round();
+                }
+            }
+            Some(done) => {
+                if self.round_start {
+                    self.probe_rtt_round_done = true;
+                }
+                if self.probe_rtt_round_done && now > done {
+                    self.probe_rtt_min_stamp = now;
+                    self.cwnd = self.cwnd.max(self.prior_cwnd);
+                    self.prior_cwnd = 0;
+                    self.exit_probe_rtt(now);
+                }
+            }
+        }
+    }
+
+    fn set_pacing_rate(&mut self) {
+        let rate = (self.pacing_gain * self.bw as f64 * (1.0 - PACING_MARGIN)) as u64;
+        if rate != 0 && (self.full_bw_reached || rate > self.pacing_rate) {
+            self.pacing_rate = rate;
+        }
+    }
+
+    fn set_cwnd(&mut self, sample: &RateSample) {
+        let max_inflight = self.max_inflight();
+        if self.full_bw_reached {
+            self.cwnd = (self.cwnd + sample.newly_acked).min(max_inflight);
+        } else if self.cwnd < max_inflight || self.delivered < self.config.initial_window {
+            self.cwnd += sample.newly_acked;
+        }
+        self.cwnd = self.cwnd.max(self.min_pipe_cwnd());
+
+        if self.state == State::ProbeRtt {
+            self.cwnd = self.cwnd.min(self.probe_rtt_cwnd());
+        }
+
+        // Bound by the model of the path's capacity
+        let mut cap = u64::MAX;
+        if self.state.is_probe_bw() && self.state != State::ProbeBwCruise {
+            cap = self.inflight_hi;
+        } else if self.state == State::ProbeRtt || self.state == State::ProbeBwCruise {
+            cap = self.inflight_with_headroom();
+        }
+        if self.pacing_gain < 1.0 {
+            // Drain the queue even if the pacing rate isn't what's limiting the sending rate
+            cap = cap.min(self.inflight(self.bw, self.pacing_gain));
+        }
+        cap = cap.min(self.inflight_lo).max(self.min_pipe_cwnd());
+        self.cwnd = self.cwnd.min(cap);
+    }
+
+    /// Volume of data to allow in flight, including allowances for delayed and aggregated acks
+    fn max_inflight(&self) -> u64 {
+        let mut inflight = self.inflight(self.bw, self.cwnd_gain);
+        inflight += self.extra_acked[0].max(self.extra_acked[1]);
+        if self.state == State::ProbeBwUp {
+            inflight += 2 * self.current_mtu;
+        }
+        inflight
+    }
+
+    fn bdp_multiple(&self, bw: u64, gain: f64) -> u64 {
+        match self.min_rtt {
+            Some(min_rtt) => (gain * bytes_in(bw, min_rtt) as f64) as u64,
+            None => self.config.initial_window,
+        }
+    }
+
+    /// Data in flight needed to fully use a path with bandwidth `bw`, scaled by `gain`
+    fn inflight(&self, bw: u64, gain: f64) -> u64 {
+        self.bdp_multiple(bw, gain) + 3 * self.send_quantum()
+    }
+
+    fn inflight_with_headroom(&self) -> u64 {
+        if self.inflight_hi == u64::MAX {
+            return u64::MAX;
+        }
+        let headroom = ((HEADROOM * self.inflight_hi as f64) as u64).max(self.current_mtu);
+        self.inflight_hi
+            .saturating_sub(headroom)
+            .max(self.min_pipe_cwnd())
+    }
+
+    fn target_inflight(&self) -> u64 {
+        self.bdp_multiple(self.bw, 1.0).min(self.cwnd)
+    }
+
+    fn probe_rtt_cwnd(&self) -> u64 {
+        self.bdp_multiple(self.bw, PROBE_RTT_CWND_GAIN)
+            .max(self.min_pipe_cwnd())
+    }
+
+    fn send_quantum(&self) -> u64 {
+        bytes_in(self.pacing_rate, Duration::from_millis(1)).clamp(2 * self.current_mtu, 64 * 1024)
+    }
+
+    fn min_pipe_cwnd(&self) -> u64 {
+        4 * self.current_mtu
+    }
+
+    fn is_cwnd_limited(&self, in_flight: u64) -> bool {
+        in_flight + self.current_mtu > self.cwnd
+    }
+
+    fn is_probing_bw(&self) -> bool {
+        matches!(
+            self.state,
+            State::Startup | State::ProbeBwRefill | State::ProbeBwUp
+        )
+    }
+}
+
+impl Controller for Bbr3 {
+    fn on_rate_sample(&mut self, now: Instant, sample: &RateSample) {
+        let in_flight = sample.in_flight;
+        self.delivered = sample.total_delivered;
+        self.tx_in_flight = sample.tx_in_flight;
+        self.app_limited = sample.is_app_limited;
+        if let Some(rate) = sample.delivery_rate {
+            self.latest_rate = rate;
+            self.bw_latest = self.bw_latest.max(rate);
+        }
+
+        self.update_round(sample);
+        self.inflight_latest = self.inflight_latest.max(sample.delivered);
+        self.update_congestion_signals(sample);
+        if self.congestion_signals_apply()
+            && sample.lost as f64 > LOSS_THRESH * sample.tx_in_flight as f64
+        {
+            self.handle_inflight_too_high(now, sample.tx_in_flight);
+        }
+        self.update_ack_aggregation(now, sample);
+        self.check_full_bw_reached(sample);
+        if self.state == State::Startup && self.full_bw_reached {
+            self.enter_drain();
+        }
+        if self.state == State::Drain && in_flight <= self.inflight(self.max_bw(), 1.0) {
+            self.start_probe_bw_down(now);
+        }
+        self.update_probe_bw_cycle_phase(now, sample, in_flight);
+        self.update_min_rtt(now, sample.rtt);
+        self.check_probe_rtt(now, in_flight);
+        if self.round_start {
+            self.bw_latest = sample.delivery_rate.unwrap_or(0);
+            self.inflight_latest = sample.delivered;
+        }
+        self.bw = self.max_bw().min(self.bw_lo);
+
+        self.set_pacing_rate();
+        self.set_cwnd(sample);
+    }
+
+    fn on_congestion_event(
+        &mut self,
+        _now: Instant,
+        _sent: Instant,
+        is_persistent_congestion: bool,
+        _lost_bytes: u64,
+    ) {
+        self.loss_in_round = true;
+
+        if is_persistent_congestion {
+            self.prior_cwnd = self.prior_cwnd.max(self.cwnd);
+            self.cwnd = self.min_pipe_cwnd();
+        }
+    }
+
+    fn on_ecn(&mut self, now: Instant, _sent: Instant, newly_acked: u64, newly_ce: u64) {
+        self.acked_packets_in_round += newly_acked;
+        self.ce_packets_in_round += newly_ce;
+        if newly_ce == 0 {
+            return;
+        }
+        self.ce_in_round = true;
+
+        let ce_fraction = self.ce_packets_in_round as f64 / self.acked_packets_in_round as f64;
+        if self.congestion_signals_apply() && ce_fraction > ECN_THRESH {
+            self.handle_inflight_too_high(now, self.tx_in_flight);
+        }
+    }
+
+    fn on_mtu_update(&mut self, new_mtu: u16) {
+        self.current_mtu = new_mtu as u64;
+        self.cwnd = self.cwnd.max(self.min_pipe_cwnd());
+    }
+
+    fn seed_window(&mut self, window: u64) {
+        self.cwnd = window.max(self.min_pipe_cwnd());
+    }
+
+    fn window(&self) -> u64 {
+        self.cwnd
+    }
+
+    fn ssthresh(&self) -> u64 {
+        match self.full_bw_reached {
+            true => self.cwnd,
+            false => u64::MAX,
+        }
+    }
+
+    fn pacing_rate(&self) -> Option<u64> {
+        Some(self.pacing_rate)
+    }
+
+    fn send_quantum(&self) -> Option<u64> {
+        Some(Self::send_quantum(self))
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+
+    fn initial_window(&self) -> u64 {
+        self.config.initial_window
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Configuration for the [`Bbr3`] congestion controller
+#[derive(Debug, Clone)]
+pub struct Bbr3Config {
+    initial_window: u64,
+    rng_seed: Option<u64>,
+}
+
+impl Bbr3Config {
+    /// Default limit on the amount of outstanding data in bytes.
+    ///
+    /// Recommended value: `min(10 * max_datagram_size, max(2 * max_datagram_size, 14720))`
+    pub fn initial_window(&mut self, value: u64) -> &mut Self {
+        self.initial_window = value;
+        self
+    }
+
+    /// Seed for the random choice of when to probe for more bandwidth
+    ///
+    /// Controllers built with the same seed probe on the same schedule, which makes tests and
+    /// simulations reproducible, but may synchronize competing flows in production. Defaults to
+    /// `None`, seeding every controller from the operating system's entropy.
+    pub fn rng_seed(&mut self, value: Option<u64>) -> &mut Self {
+        self.rng_seed = value;
+        self
+    }
+}
+
+impl Default for Bbr3Config {
+    fn default() -> Self {
+        Self {
+            initial_window: 14720.clamp(2 * BASE_DATAGRAM_SIZE, 10 * BASE_DATAGRAM_SIZE),
+            rng_seed: None,
+        }
+    }
+}
+
+impl ControllerFactory for Arc<Bbr3Config> {
+    fn build(&self, now: Instant, current_mtu: u16) -> Box<dyn Controller> {
+        Box::new(Bbr3::new(self.clone(), now, current_mtu))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum State {
+    // Rapidly increase the sending rate until the bandwidth stops growing
+    Startup,
+    // Drain the queue created during Startup
+    Drain,
+    // Reduce data in flight to leave headroom for competing flows and drain any queue
+    ProbeBwDown,
+    // Send at the estimated bandwidth
+    ProbeBwCruise,
+    // Fill the pipe for a round trip, so that a probe's congestion signals can be identified
+    ProbeBwRefill,
+    // Send faster than the estimated bandwidth to look for more
+    ProbeBwUp,
+    // Temporarily reduce data in flight to measure the path's minimum RTT
+    ProbeRtt,
+}
+
+impl State {
+    fn is_probe_bw(self) -> bool {
+        matches!(
+            self,
+            Self::ProbeBwDown | Self::ProbeBwCruise | Self::ProbeBwRefill | Self::ProbeBwUp
+        )
+    }
+}
+
+/// Bytes transferred over `duration` at `bw` bytes per second
+fn bytes_in(bw: u64, duration: Duration) -> u64 {
+    (bw as u128 * duration.as_nanos() / 1_000_000_000) as u64
+}
+
+// Equal to 4 * ln(2), the smallest gain which doubles the delivery rate every round trip
+const STARTUP_PACING_GAIN: f64 = 2.77;
+const STARTUP_CWND_GAIN: f64 = 2.0;
+const DRAIN_PACING_GAIN: f64 = 0.35;
+const PROBE_BW_DOWN_PACING_GAIN: f64 = 0.9;
+const PROBE_BW_UP_PACING_GAIN: f64 = 1.25;
+const PROBE_BW_CWND_GAIN: f64 = 2.0;
+const PROBE_BW_UP_CWND_GAIN: f64 = 2.25;
+const PROBE_RTT_CWND_GAIN: f64 = 0.5;
+
+const STARTUP_GROWTH_TARGET: f64 = 1.25;
+const STARTUP_FULL_BW_ROUNDS: u32 = 3;
+
+// Maximum tolerated fraction of data lost in a round trip while probing
+const LOSS_THRESH: f64 = 0.02;
+// Maximum tolerated fraction of packets marked CE in a round trip while probing
+const ECN_THRESH: f64 = 0.5;
+// Scale of the reduction in data in flight in response to ECN marks
+const ECN_FACTOR: f64 = 1.0 / 3.0;
+const ECN_ALPHA_GAIN: f64 = 1.0 / 16.0;
+// Multiplicative decrease applied to the model's lower bounds in response to loss
+const BETA: f64 = 0.7;
+// Fraction of `inflight_hi` left unused when cruising, for competing flows
+const HEADROOM: f64 = 0.15;
+const PACING_MARGIN: f64 = 0.01;
+
+const PROBE_BW_MIN_WAIT: Duration = Duration::from_secs(2);
+const PROBE_RTT_INTERVAL: Duration = Duration::from_secs(5);
+const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+const MIN_RTT_FILTER_LEN: Duration = Duration::from_secs(10);
+// Round trips per slot of the ack aggregation filter
+const EXTRA_ACKED_ROUNDS: u64 = 5;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::congestion::{DeliveryRateSampler, SentDeliveryState};
+
+    const MTU: u16 = 1200;
+    const PACKET: u64 = MTU as u64;
+    /// 10 Mbit/s
+    const RATE: u64 = 1_250_000;
+    const DELAY: Duration = Duration::from_millis(20);
+    const BDP: u64 = RATE / 50;
+
+    /// A bottleneck link with a FIFO queue, feeding back acks to a sender limited only by
+    /// the congestion window
+    struct Simulation {
+        bbr: Bbr3,
+        delivery: DeliveryRateSampler,
+        now: Instant,
+        /// Queue capacity in bytes, beyond which packets are dropped
+        buffer: u64,
+        /// Queue depth in bytes beyond which packets are marked CE, if any
+        ce_threshold: Option<u64>,
+        /// When the bottleneck finishes transmitting the packets queued so far
+        busy_until: Instant,
+        in_flight: VecDeque<Packet>,
+        next_number: u64,
+        states: Vec<State>,
+        sent: u64,
+        lost: u64,
+        min_rtt: Duration,
+        max_rtt: Duration,
+    }
+
+    struct Packet {
+        number: u64,
+        sent: Instant,
+        acked: Option<Instant>,
+        ce: bool,
+        delivery: SentDeliveryState,
+    }
+
+    impl Simulation {
+        fn new(buffer: u64) -> Self {
+            let now = Instant::now();
+            // Make the bandwidth probing schedule reproducible
+            let mut config = Bbr3Config::default();
+            config.rng_seed(Some(0));
+            Self {
+                bbr: Bbr3::new(Arc::new(config), now, MTU),
+                delivery: DeliveryRateSampler::new(now),
+                now,
+                buffer,
+                ce_threshold: None,
+                busy_until: now,
+                in_flight: VecDeque::new(),
+                next_number: 0,
+                states: vec![State::Startup],
+                sent: 0,
+                lost: 0,
+                min_rtt: Duration::MAX,
+                max_rtt: Duration::ZERO,
+            }
+        }
+
+        fn run(&mut self, duration: Duration) {
+            let end = self.now + duration;
+            while self.now < end {
+                self.send();
+                self.receive();
+            }
+        }
+
+        fn send(&mut self) {
+            while (self.in_flight.len() as u64 + 1) * PACKET <= self.bbr.window() {
+                let queued = bytes_in(RATE, self.busy_until.saturating_duration_since(self.now));
+                let acked = if queued + PACKET > self.buffer {
+                    None
+                } else {
+                    self.busy_until = self.busy_until.max(self.now)
+                        + Duration::from_nanos(PACKET * 1_000_000_000 / RATE);
+                    Some(self.busy_until + DELAY)
+                };
+                let in_flight = self.in_flight.len() as u64 * PACKET;
+                let delivery = self.delivery.on_sent(self.now, in_flight, PACKET);
+                self.in_flight.push_back(Packet {
+                    number: self.next_number,
+                    sent: self.now,
+                    acked,
+                    ce: self.ce_threshold.map_or(false, |x| queued > x),
+                    delivery,
+                });
+                self.bbr.on_sent(self.now, PACKET, self.next_number);
+                self.next_number += 1;
+                self.sent += 1;
+            }
+        }
+
+        fn receive(&mut self) {
+            self.now = match self.in_flight.iter().filter_map(|x| x.acked).min() {
+                Some(time) => time,
+                None => self.now + DELAY,
+            };
+
+            let mut acked = Vec::new();
+            let mut remaining = VecDeque::new();
+            for packet in self.in_flight.drain(..) {
+                match packet.acked {
+                    Some(time) if time <= self.now => acked.push(packet),
+                    _ => remaining.push_back(packet),
+                }
+            }
+            self.in_flight = remaining;
+
+            for packet in &acked {
+                self.min_rtt = self.min_rtt.min(self.now - packet.sent);
+                self.max_rtt = self.max_rtt.max(self.now - packet.sent);
+                self.delivery
+                    .on_acked(self.now, packet.sent, PACKET, &packet.delivery);
+            }
+            let largest = acked.last().map(|x| x.number);
+            let in_flight = self.in_flight.len() as u64 * PACKET;
+            if let Some(sample) = self.delivery.on_end_acks(self.now, in_flight, self.min_rtt) {
+                self.bbr.on_rate_sample(self.now, &sample);
+            }
+
+            // Packets are declared lost once three later packets have been acknowledged, or if
+            // nothing else is left to acknowledge
+            let lost = self
+                .in_flight
+                .iter()
+                .take_while(|x| x.acked.is_none())
+                .filter(|x| largest.map_or(true, |largest| x.number + 3 <= largest))
+                .count();
+            if lost > 0 {
+                let last_lost = self.in_flight.drain(..lost).next_back().unwrap();
+                self.lost += lost as u64;
+                let lost_bytes = lost as u64 * PACKET;
+                self.delivery.on_lost(lost_bytes);
+                self.bbr
+                    .on_congestion_event(self.now, last_lost.sent, false, lost_bytes);
+            }
+
+            if let Some(last) = acked.last() {
+                let ce = acked.iter().filter(|x| x.ce).count() as u64;
+                self.bbr.on_ecn(self.now, last.sent, acked.len() as u64, ce);
+            }
+
+            if self.states.last() != Some(&self.bbr.state) {
+                self.states.push(self.bbr.state);
+            }
+        }
+    }
+
+    #[test]
+    fn startup_estimates_bandwidth() {
+        let mut sim = Simulation::new(u64::MAX);
+        sim.run(Duration::from_secs(1));
+        assert!(sim.bbr.full_bw_reached);
+        assert_eq!(&sim.states[..2], &[State::Startup, State::Drain]);
+        assert!(sim.bbr.state.is_probe_bw());
+        let max_bw = sim.bbr.max_bw();
+        assert!(
+            max_bw > RATE * 9 / 10 && max_bw < RATE * 11 / 10,
+            "{max_bw}"
+        );
+        let min_rtt = sim.bbr.min_rtt.unwrap();
+        assert!(min_rtt >= DELAY && min_rtt < DELAY + Duration::from_millis(2));
+        assert_eq!(sim.lost, 0);
+        // The queue built up in startup is drained, leaving little more than the BDP in flight
+        sim.run(Duration::from_millis(500));
+        assert!(sim.bbr.window() < 3 * BDP, "{}", sim.bbr.window());
+    }
+
+    #[test]
+    fn probe_bw_cycle() {
+        let mut sim = Simulation::new(u64::MAX);
+        sim.run(Duration::from_secs(8));
+        for state in [
+            State::ProbeBwDown,
+            State::ProbeBwCruise,
+            State::ProbeBwRefill,
+            State::ProbeBwUp,
+            State::ProbeRtt,
+        ] {
+            assert!(sim.states.contains(&state), "{state:?} in {:?}", sim.states);
+        }
+        // Probing was ended by the bandwidth plateauing
+        assert_eq!(sim.bbr.inflight_hi, u64::MAX);
+        assert!(sim.bbr.max_bw() < RATE * 11 / 10);
+    }
+
+    #[test]
+    fn loss_bounds_inflight() {
+        let mut sim = Simulation::new(BDP / 2);
+        sim.run(Duration::from_secs(1));
+        assert!(sim.lost > 0);
+        assert!(sim.bbr.full_bw_reached);
+        let startup_lost = sim.lost;
+
+        // Bandwidth probes are cut short by loss
+        sim.run(Duration::from_secs(10));
+        assert_ne!(sim.bbr.inflight_hi, u64::MAX);
+        assert!(sim.bbr.inflight_hi < 2 * BDP, "{}", sim.bbr.inflight_hi);
+        let lost = sim.lost - startup_lost;
+        assert!(lost * 100 < sim.sent, "lost {lost} of {}", sim.sent);
+    }
+
+    #[test]
+    fn ecn_bounds_inflight() {
+        let mut sim = Simulation::new(u64::MAX);
+        sim.ce_threshold = Some(BDP / 4);
+        sim.run(Duration::from_secs(10));
+        assert_eq!(sim.lost, 0);
+        assert!(sim.bbr.ecn_alpha > 0.0);
+        assert_ne!(sim.bbr.inflight_hi, u64::MAX);
+        // The queue is kept short after startup
+        sim.max_rtt = Duration::ZERO;
+        sim.run(Duration::from_secs(5));
+        assert!(sim.max_rtt < 2 * DELAY, "{:?}", sim.max_rtt);
+    }
+
+    #[test]
+    fn seeded_runs_match() {
+        let mut a = Simulation::new(BDP / 2);
+        let mut b = Simulation::new(BDP / 2);
+        a.run(Duration::from_secs(5));
+        b.run(Duration::from_secs(5));
+        assert_eq!(a.states, b.states);
+        assert_eq!((a.sent, a.lost), (b.sent, b.lost));
+        assert_eq!(a.bbr.window(), b.bbr.window());
+    }
+
+    #[test]
+    fn persistent_congestion() {
+        let mut sim = Simulation::new(u64::MAX);
+        sim.run(Duration::from_secs(1));
+        let now = sim.now;
+        sim.bbr
+            .on_congestion_event(now, now - DELAY, true, 10 * PACKET);
+        assert_eq!(sim.bbr.window(), 4 * PACKET);
+    }
+}
@@ -2,7 +2,7 @@ use std::any::Any;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use super::{Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
+use super::{hystart::HyStart, Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
 use crate::connection::RttEstimator;
 use std::cmp;
 
@@ -70,6 +70,10 @@ pub struct Cubic {
     recovery_start_time: Option<Instant>,
     cubic_state: State,
     current_mtu: u64,
+    /// Slow start exit state, if enabled
+    hystart: Option<HyStart>,
+    /// State before the most recent reduction, in case it turns out to have been spurious
+    prior: Option<(u64, u64, Option<Instant>, State)>,
 }
 
 impl Cubic {
@@ -79,18 +83,34 @@ impl Cubic {
             window: config.initial_window,
             ssthresh: u64::MAX,
             recovery_start_time: None,
+            hystart: config.hystart.then(HyStart::default),
             config,
             cubic_state: Default::default(),
             current_mtu: current_mtu as u64,
+            prior: None,
         }
     }
 
     fn minimum_window(&self) -> u64 {
         2 * self.current_mtu
     }
+
+    /// HyStart++ state, if enabled and still in the initial slow start
+    fn hystart(&mut self) -> Option<&mut HyStart> {
+        match self.ssthresh {
+            u64::MAX => self.hystart.as_mut(),
+            _ => None,
+        }
+    }
 }
 
 impl Controller for Cubic {
+    fn on_sent(&mut self, _now: Instant, _bytes: u64, last_packet_number: u64) {
+        if let Some(hystart) = self.hystart() {
+            hystart.on_sent(last_packet_number);
+        }
+    }
+
     fn on_ack(
         &mut self,
         now: Instant,
@@ -110,7 +130,10 @@ impl Controller for Cubic {
 
         if self.window < self.ssthresh {
             // Slow start
-            self.window += bytes;
+            self.window += match self.hystart() {
+                Some(hystart) => hystart.on_ack(rtt.latest(), bytes),
+                None => bytes,
+            };
         } else {
             // Congestion avoidance.
             let ca_start_time;
@@ -162,6 +185,22 @@ impl Controller for Cubic {
         }
     }
 
+    fn on_end_acks(
+        &mut self,
+        _now: Instant,
+        _in_flight: u64,
+        _app_limited: bool,
+        largest_packet_num_acked: Option<u64>,
+    ) {
+        let exit = self.hystart().map_or(false, |hystart| {
+            hystart.on_end_acks(largest_packet_num_acked)
+        });
+        if exit {
+            // The RTT rose for long enough to conclude that the path's capacity was reached
+            self.ssthresh = self.window;
+        }
+    }
+
     fn on_congestion_event(
         &mut self,
         now: Instant,
@@ -177,6 +216,12 @@ impl Controller for Cubic {
             return;
         }
 
+        self.prior = Some((
+            self.window,
+            self.ssthresh,
+            self.recovery_start_time,
+            self.cubic_state.clone(),
+        ));
         self.recovery_start_time = Some(now);
 
         // Fast convergence
@@ -213,15 +258,48 @@ impl Controller for Cubic {
         }
     }
 
+    fn on_spurious_congestion_event(&mut self) {
+        // Restore the state from before the reduction, as in RFC 9438 §4.9
+        if let Some((window, ssthresh, recovery_start_time, cubic_state)) = self.prior.take() {
+            self.window = self.window.max(window);
+            self.ssthresh = self.ssthresh.max(ssthresh);
+            self.recovery_start_time = recovery_start_time;
+            self.cubic_state = cubic_state;
+        }
+    }
+
     fn on_mtu_update(&mut self, new_mtu: u16) {
         self.current_mtu = new_mtu as u64;
         self.window = self.window.max(self.minimum_window());
     }
 
+    fn seed_window(&mut self, window: u64) {
+        self.window = window.max(self.minimum_window());
+    }
+
+    fn seed(&mut self, window: u64, ssthresh: u64, recovery_start: Option<Instant>) {
+        self.seed_window(window);
+        self.ssthresh = ssthresh;
+        if let Some(time) = recovery_start {
+            // Resume congestion avoidance as if it had started from the current window then
+            self.recovery_start_time = Some(time);
+            self.cubic_state.w_max = self.window as f64;
+            self.cubic_state.k = 0.0;
+        }
+    }
+
     fn window(&self) -> u64 {
         self.window
     }
 
+    fn ssthresh(&self) -> u64 {
+        self.ssthresh
+    }
+
+    fn recovery_start(&self) -> Option<Instant> {
+        self.recovery_start_time
+    }
+
     fn clone_box(&self) -> Box<dyn Controller> {
         Box::new(self.clone())
     }
@@ -239,6 +317,7 @@ impl Controller for Cubic {
 #[derive(Debug, Clone)]
 pub struct CubicConfig {
     initial_window: u64,
+    hystart: bool,
 }
 
 impl CubicConfig {
@@ -249,12 +328,22 @@ impl CubicConfig {
         self.initial_window = value;
         self
     }
+
+    /// Whether to leave the initial slow start when the RTT rises, using HyStart++ (RFC 9406)
+    ///
+    /// This avoids the large loss bursts caused by overshooting the capacity of paths with a
+    /// large bandwidth-delay product. Defaults to `false`.
+    pub fn hystart(&mut self, value: bool) -> &mut Self {
+        self.hystart = value;
+        self
+    }
 }
 
 impl Default for CubicConfig {
     fn default() -> Self {
         Self {
             initial_window: 14720.clamp(2 * BASE_DATAGRAM_SIZE, 10 * BASE_DATAGRAM_SIZE),
+            hystart: false,
         }
     }
 }
@@ -0,0 +1,273 @@
+use std::time::{Duration, Instant};
+
+/// Delivery rate and related statistics sampled from a batch of acknowledgements
+///
+/// Produced by tracking the amount of data delivered when each packet was sent, in the style of
+/// [draft-cheng-iccrg-delivery-rate-estimation](https://datatracker.ietf.org/doc/html/draft-cheng-iccrg-delivery-rate-estimation).
+/// The sample is taken from the most recently sent packet acknowledged by the batch, and covers
+/// the data delivered since that packet was sent.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RateSample {
+    /// Bytes delivered over `interval`
+    pub delivered: u64,
+    /// Interval over which `delivered` was measured
+    pub interval: Duration,
+    /// Bytes per second, unless `interval` was shorter than the path's minimum round-trip time,
+    /// over which acknowledgement compression would inflate it
+    pub delivery_rate: Option<u64>,
+    /// Total bytes ever delivered on the path when the sampled packet was sent
+    pub prior_delivered: u64,
+    /// Total bytes ever delivered on the path, including this batch
+    pub total_delivered: u64,
+    /// Bytes acknowledged by this batch
+    pub newly_acked: u64,
+    /// Bytes declared lost since the sampled packet was sent
+    pub lost: u64,
+    /// Bytes in flight when the sampled packet was sent, including the packet itself
+    pub tx_in_flight: u64,
+    /// Bytes in flight after this batch
+    pub in_flight: u64,
+    /// Round-trip time of the sampled packet, including any acknowledgement delay
+    pub rtt: Duration,
+    /// Whether the application had no more data to send around the time the sampled packet was
+    /// sent, such that the delivery rate may underestimate the path's capacity
+    pub is_app_limited: bool,
+}
+
+/// Delivery rate sampling state of a path
+#[derive(Debug, Clone)]
+pub(crate) struct DeliveryRateSampler {
+    /// Total bytes delivered
+    delivered: u64,
+    /// When `delivered` was last updated
+    delivered_time: Instant,
+    /// Send time of the packet the most recent sample was taken from
+    first_sent_time: Instant,
+    /// Total bytes declared lost
+    lost: u64,
+    /// Value of `delivered` after which samples are no longer app-limited, or 0
+    app_limited_until: u64,
+    /// Send time and state of the most recently sent packet in the current batch of acks
+    latest: Option<(Instant, SentDeliveryState)>,
+    /// Bytes acknowledged in the current batch of acks
+    newly_acked: u64,
+}
+
+impl DeliveryRateSampler {
+    pub(crate) fn new(now: Instant) -> Self {
+        Self {
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            lost: 0,
+            app_limited_until: 0,
+            latest: None,
+            newly_acked: 0,
+        }
+    }
+
+    /// Record the state of a packet of `size` bytes being sent with `in_flight` bytes already in
+    /// flight
+    pub(crate) fn on_sent(&mut self, now: Instant, in_flight: u64, size: u64) -> SentDeliveryState {
+        if in_flight == 0 {
+            // Don't count idle time towards the delivery rate
+            self.first_sent_time = now;
+            self.delivered_time = now;
+        }
+        SentDeliveryState {
+            delivered: self.delivered,
+            lost: self.lost,
+            delivered_time_offset: offset_micros(now, self.delivered_time),
+            first_sent_time_offset: offset_micros(now, self.first_sent_time),
+            in_flight: (in_flight + size).try_into().unwrap_or(u32::MAX),
+            is_app_limited: self.app_limited_until != 0,
+        }
+    }
+
+    /// Record a packet of `size` bytes sent at `time_sent` being acknowledged
+    pub(crate) fn on_acked(
+        &mut self,
+        now: Instant,
+        time_sent: Instant,
+        size: u64,
+        state: &SentDeliveryState,
+    ) {
+        self.delivered += size;
+        self.delivered_time = now;
+        self.newly_acked += size;
+        if self
+            .latest
+            .map_or(true, |(latest_sent, _)| time_sent >= latest_sent)
+        {
+            self.latest = Some((time_sent, *state));
+        }
+    }
+
+    pub(crate) fn on_lost(&mut self, size: u64) {
+        self.lost += size;
+    }
+
+    /// Record that the application had nothing more to send with `in_flight` bytes in flight
+    pub(crate) fn on_app_limited(&mut self, in_flight: u64) {
+        self.app_limited_until = (self.delivered + in_flight).max(1);
+    }
+
+    /// Finish processing a batch of acks, producing a sample if any packet was acknowledged
+    pub(crate) fn on_end_acks(
+        &mut self,
+        now: Instant,
+        in_flight: u64,
+        min_rtt: Duration,
+    ) -> Option<RateSample> {
+        if self.app_limited_until != 0 && self.delivered > self.app_limited_until {
+            // Everything sent while app-limited has been delivered
+            self.app_limited_until = 0;
+        }
+        let newly_acked = std::mem::take(&mut self.newly_acked);
+        let (time_sent, state) = self.latest.take()?;
+
+        self.first_sent_time = time_sent;
+        let send_elapsed = Duration::from_micros(state.first_sent_time_offset.into());
+        let delivered_time = time_sent - Duration::from_micros(state.delivered_time_offset.into());
+        let ack_elapsed = now.saturating_duration_since(delivered_time);
+        let interval = send_elapsed.max(ack_elapsed);
+        let delivered = self.delivered - state.delivered;
+        let delivery_rate = match interval.as_micros() {
+            0 => None,
+            _ if interval < min_rtt => None,
+            micros => Some((delivered as u128 * 1_000_000 / micros) as u64),
+        };
+
+        Some(RateSample {
+            delivered,
+            interval,
+            delivery_rate,
+            prior_delivered: state.delivered,
+            total_delivered: self.delivered,
+            newly_acked,
+            lost: self.lost - state.lost,
+            tx_in_flight: state.in_flight.into(),
+            in_flight,
+            rtt: now.saturating_duration_since(time_sent),
+            is_app_limited: state.is_app_limited,
+        })
+    }
+}
+
+/// Delivery rate sampling state when a packet was sent
+///
+/// Times are stored relative to the packet's send time, to keep `SentPacket` small.
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct SentDeliveryState {
+    delivered: u64,
+    lost: u64,
+    /// Microseconds between `delivered` last being updated and the packet being sent
+    delivered_time_offset: u32,
+    /// Microseconds between the start of the sampling interval and the packet being sent
+    first_sent_time_offset: u32,
+    in_flight: u32,
+    is_app_limited: bool,
+}
+
+fn offset_micros(now: Instant, earlier: Instant) -> u32 {
+    now.saturating_duration_since(earlier)
+        .as_micros()
+        .try_into()
+        .unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKET: u64 = 1200;
+    const RTT: Duration = Duration::from_millis(10);
+    const INTERVAL: Duration = Duration::from_millis(1);
+
+    /// Send a packet every `INTERVAL`, each acknowledged `RTT` later
+    fn steady_state(sampler: &mut DeliveryRateSampler, start: Instant, packets: u32) -> RateSample {
+        let in_flight = (RTT.as_millis() as u64) * PACKET;
+        let mut sent = Vec::new();
+        let mut sample = None;
+        for i in 0..packets {
+            let now = start + INTERVAL * i;
+            sent.push((now, sampler.on_sent(now, in_flight, PACKET)));
+            let ack = now + INTERVAL;
+            if let Some((time_sent, state)) = sent.iter().find(|(time, _)| *time + RTT == ack) {
+                sampler.on_acked(ack, *time_sent, PACKET, state);
+                sample = sampler.on_end_acks(ack, in_flight, RTT);
+            }
+        }
+        sample.unwrap()
+    }
+
+    #[test]
+    fn steady_state_rate() {
+        let now = Instant::now();
+        let mut sampler = DeliveryRateSampler::new(now);
+        let sample = steady_state(&mut sampler, now, 100);
+        assert_eq!(sample.delivery_rate, Some(PACKET * 1000));
+        assert_eq!(sample.newly_acked, PACKET);
+        assert_eq!(sample.interval, RTT);
+        assert_eq!(sample.delivered, 10 * PACKET);
+        assert_eq!(sample.total_delivered, 91 * PACKET);
+        assert_eq!(sample.rtt, RTT);
+        assert!(!sample.is_app_limited);
+    }
+
+    #[test]
+    fn empty_interval_is_invalid() {
+        let now = Instant::now();
+        let mut sampler = DeliveryRateSampler::new(now);
+        let state = sampler.on_sent(now, 0, PACKET);
+        sampler.on_acked(now, now, PACKET, &state);
+        let sample = sampler.on_end_acks(now, 0, Duration::ZERO).unwrap();
+        assert_eq!(sample.delivered, PACKET);
+        assert_eq!(sample.delivery_rate, None);
+        // Nothing further was acknowledged
+        assert!(sampler.on_end_acks(now + RTT, 0, RTT).is_none());
+    }
+
+    #[test]
+    fn interval_below_min_rtt_is_invalid() {
+        let now = Instant::now();
+        let mut sampler = DeliveryRateSampler::new(now);
+        let state = sampler.on_sent(now, 0, PACKET);
+        sampler.on_acked(now + RTT / 2, now, PACKET, &state);
+        let sample = sampler.on_end_acks(now + RTT / 2, 0, RTT).unwrap();
+        assert_eq!(sample.interval, RTT / 2);
+        assert_eq!(sample.delivery_rate, None);
+    }
+
+    #[test]
+    fn app_limited() {
+        let now = Instant::now();
+        let mut sampler = DeliveryRateSampler::new(now);
+        sampler.on_app_limited(0);
+        let state = sampler.on_sent(now, 0, PACKET);
+        sampler.on_acked(now + RTT, now, PACKET, &state);
+        let sample = sampler.on_end_acks(now + RTT, 0, RTT).unwrap();
+        assert!(sample.is_app_limited);
+
+        // Packets sent after the app-limited data was delivered are not app-limited
+        let now = now + RTT;
+        let state = sampler.on_sent(now, 0, PACKET);
+        sampler.on_acked(now + RTT, now, PACKET, &state);
+        let sample = sampler.on_end_acks(now + RTT, 0, RTT).unwrap();
+        assert!(!sample.is_app_limited);
+    }
+
+    #[test]
+    fn losses() {
+        let now = Instant::now();
+        let mut sampler = DeliveryRateSampler::new(now);
+        let state = sampler.on_sent(now, 0, PACKET);
+        let _lost = sampler.on_sent(now, PACKET, PACKET);
+        sampler.on_lost(PACKET);
+        sampler.on_acked(now + RTT, now, PACKET, &state);
+        let sample = sampler.on_end_acks(now + RTT, 0, RTT).unwrap();
+        assert_eq!(sample.lost, PACKET);
+        assert_eq!(sample.tx_in_flight, PACKET);
+    }
+}
@@ -0,0 +1,232 @@
+use std::time::Duration;
+
+/// HyStart++ slow start exit, as described in RFC 9406
+///
+/// Watches for the minimum RTT of each round trip rising above that of the previous one, which
+/// indicates that a queue has started to build, and responds by slowing the window's growth for a
+/// few round trips before leaving slow start altogether. If the RTT falls again in the meantime,
+/// the increase was spurious and regular slow start resumes.
+#[derive(Debug, Default, Clone)]
+pub(super) struct HyStart {
+    /// Largest packet number sent so far
+    largest_sent: u64,
+    /// The current round trip ends when a packet numbered above this is acknowledged
+    window_end: Option<u64>,
+    last_round_min_rtt: Option<Duration>,
+    current_round_min_rtt: Option<Duration>,
+    rtt_sample_count: u32,
+    /// Conservative slow start state, if the RTT has risen
+    css: Option<ConservativeSlowStart>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct ConservativeSlowStart {
+    /// Minimum RTT of the round trip in which conservative slow start was entered
+    baseline_min_rtt: Duration,
+    rounds: u32,
+}
+
+impl HyStart {
+    pub(super) fn on_sent(&mut self, last_packet_number: u64) {
+        self.largest_sent = self.largest_sent.max(last_packet_number);
+    }
+
+    /// Record the acknowledgement of `bytes` given the latest RTT sample, returning how much the
+    /// window should grow by
+    pub(super) fn on_ack(&mut self, rtt: Duration, bytes: u64) -> u64 {
+        self.current_round_min_rtt = Some(self.current_round_min_rtt.map_or(rtt, |x| x.min(rtt)));
+        self.rtt_sample_count += 1;
+
+        if let (Some(current), true) = (
+            self.current_round_min_rtt,
+            self.rtt_sample_count >= N_RTT_SAMPLE,
+        ) {
+            match (self.css, self.last_round_min_rtt) {
+                (None, Some(last)) => {
+                    let threshold = (last / MIN_RTT_DIVISOR).clamp(MIN_RTT_THRESH, MAX_RTT_THRESH);
+                    if current >= last + threshold {
+                        self.css = Some(ConservativeSlowStart {
+                            baseline_min_rtt: current,
+                            rounds: 0,
+                        });
+                    }
+                }
+                // The RTT increase was spurious
+                (Some(css), _) if current < css.baseline_min_rtt => self.css = None,
+                _ => {}
+            }
+        }
+
+        match self.css {
+            Some(_) => bytes / CSS_GROWTH_DIVISOR,
+            None => bytes,
+        }
+    }
+
+    /// Finish processing a batch of acks, returning whether slow start should end
+    pub(super) fn on_end_acks(&mut self, largest_packet_num_acked: Option<u64>) -> bool {
+        let largest_acked = match largest_packet_num_acked {
+            Some(x) => x,
+            None => return false,
+        };
+        if self.window_end.map_or(false, |end| largest_acked <= end) {
+            return false;
+        }
+
+        // A new round trip starts
+        self.window_end = Some(self.largest_sent);
+        self.last_round_min_rtt = self.current_round_min_rtt.take();
+        self.rtt_sample_count = 0;
+        if let Some(css) = &mut self.css {
+            css.rounds += 1;
+            return css.rounds >= CSS_ROUNDS;
+        }
+        false
+    }
+
+    /// Whether conservative slow start is in progress
+    #[cfg(test)]
+    fn in_css(&self) -> bool {
+        self.css.is_some()
+    }
+}
+
+const MIN_RTT_THRESH: Duration = Duration::from_millis(4);
+const MAX_RTT_THRESH: Duration = Duration::from_millis(16);
+const MIN_RTT_DIVISOR: u32 = 8;
+const N_RTT_SAMPLE: u32 = 8;
+const CSS_GROWTH_DIVISOR: u64 = 4;
+const CSS_ROUNDS: u32 = 5;
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Instant};
+
+    use super::*;
+    use crate::congestion::{Controller, Cubic, CubicConfig, NewReno, NewRenoConfig};
+    use crate::connection::RttEstimator;
+
+    const MTU: u64 = 1200;
+
+    /// Drives a controller through round trips in which its whole window is acknowledged
+    struct Rounds {
+        now: Instant,
+        next_packet: u64,
+        rtt: RttEstimator,
+    }
+
+    impl Rounds {
+        fn new() -> Self {
+            Self {
+                now: Instant::now(),
+                next_packet: 0,
+                rtt: RttEstimator::new(Duration::from_millis(100)),
+            }
+        }
+
+        fn round(&mut self, controller: &mut dyn Controller, rtt: Duration) {
+            let packets = controller.window() / MTU;
+            self.now += Duration::from_millis(1);
+            let sent = self.now;
+            let first = self.next_packet;
+            for _ in 0..packets {
+                controller.on_sent(sent, MTU, self.next_packet);
+                self.next_packet += 1;
+            }
+            self.now += rtt;
+            self.rtt.update(Duration::ZERO, rtt);
+            for packet in first..self.next_packet {
+                controller.on_ack(self.now, sent, MTU, false, &self.rtt);
+                controller.on_end_acks(self.now, 0, false, Some(packet));
+            }
+        }
+    }
+
+    #[test]
+    fn rtt_increase_enters_css() {
+        let mut hystart = HyStart::default();
+        let mut packet = 0;
+        let mut round = |hystart: &mut HyStart, rtt: Duration| {
+            let first = packet;
+            for _ in 0..10 {
+                hystart.on_sent(packet);
+                packet += 1;
+            }
+            let mut growth = 0;
+            for acked in first..packet {
+                growth += hystart.on_ack(rtt, MTU);
+                hystart.on_end_acks(Some(acked));
+            }
+            growth
+        };
+
+        assert_eq!(round(&mut hystart, Duration::from_millis(100)), 10 * MTU);
+        // An increase of less than an eighth of the RTT is tolerated
+        assert_eq!(round(&mut hystart, Duration::from_millis(110)), 10 * MTU);
+        assert!(!hystart.in_css());
+        // Growth slows once enough samples of a larger increase are seen
+        assert_eq!(
+            round(&mut hystart, Duration::from_millis(130)),
+            8 * MTU + 2 * MTU / 4
+        );
+        assert!(hystart.in_css());
+        // And resumes if the RTT falls back
+        round(&mut hystart, Duration::from_millis(100));
+        assert!(!hystart.in_css());
+    }
+
+    #[test]
+    fn new_reno_exits_slow_start() {
+        let mut config = NewRenoConfig::default();
+        config.hystart(true);
+        let mut rounds = Rounds::new();
+        let mut reno = NewReno::new(Arc::new(config), rounds.now, MTU as u16);
+        for _ in 0..4 {
+            rounds.round(&mut reno, Duration::from_millis(100));
+        }
+        // Exponential growth until the RTT rises
+        assert_eq!(reno.window(), 16 * reno.initial_window());
+        rounds.round(&mut reno, Duration::from_millis(150));
+        let window = reno.window();
+        for _ in 0..CSS_ROUNDS {
+            rounds.round(&mut reno, Duration::from_millis(150));
+        }
+        // Conservative slow start grows by a quarter per round, then congestion avoidance by a
+        // datagram per round
+        assert!(reno.window() < window * 4, "{} vs {window}", reno.window());
+        let window = reno.window();
+        rounds.round(&mut reno, Duration::from_millis(150));
+        assert_eq!(reno.window(), window + MTU);
+    }
+
+    #[test]
+    fn cubic_exits_slow_start() {
+        let mut config = CubicConfig::default();
+        config.hystart(true);
+        let mut rounds = Rounds::new();
+        let mut cubic = Cubic::new(Arc::new(config), rounds.now, MTU as u16);
+        for _ in 0..4 {
+            rounds.round(&mut cubic, Duration::from_millis(100));
+        }
+        for _ in 0..=CSS_ROUNDS {
+            rounds.round(&mut cubic, Duration::from_millis(150));
+        }
+        let window = cubic.window();
+        assert!(window < 64 * cubic.initial_window());
+        rounds.round(&mut cubic, Duration::from_millis(150));
+        assert!(cubic.window() < window * 5 / 4);
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let mut rounds = Rounds::new();
+        let mut reno = NewReno::new(Arc::new(NewRenoConfig::default()), rounds.now, MTU as u16);
+        for _ in 0..4 {
+            rounds.round(&mut reno, Duration::from_millis(100));
+        }
+        for _ in 0..3 {
+            rounds.round(&mut reno, Duration::from_millis(150));
+        }
+        assert_eq!(reno.window(), 128 * reno.initial_window());
+    }
+}
@@ -0,0 +1,378 @@
+use std::any::Any;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
+use crate::connection::RttEstimator;
+
+/// A low-priority congestion controller for background transfers, based on LEDBAT++
+///
+/// Queueing delay is estimated as the difference between the smoothed and minimum RTT of the path.
+/// While it stays below a target, the window grows more slowly than a standard controller's, and
+/// once it exceeds the target, the window shrinks in proportion to the excess. Connections using
+/// it therefore yield to competing traffic as soon as that traffic starts building a queue. To
+/// keep its estimate of the minimum RTT meaningful, the window is periodically collapsed for a
+/// couple of round trips, draining any queue it is responsible for.
+///
+/// See RFC 6817 and draft-irtf-iccrg-ledbat-plus-plus.
+#[derive(Debug, Clone)]
+pub struct Ledbat {
+    config: Arc<LedbatConfig>,
+    current_mtu: u64,
+    /// Maximum number of bytes in flight that may be sent, tracked fractionally since it changes
+    /// by small amounts on every acknowledgement
+    window: f64,
+    /// Slow start threshold in bytes. When the congestion window is below ssthresh, the mode is
+    /// slow start and the window grows by a fraction of the number of bytes acknowledged.
+    ssthresh: u64,
+    /// The time when the window was last reduced due to loss. Acknowledgements for packets sent
+    /// before this time neither grow nor further reduce the window.
+    recovery_start_time: Instant,
+    slowdown: Slowdown,
+}
+
+impl Ledbat {
+    /// Construct a state using the given `config` and current time `now`
+    pub fn new(config: Arc<LedbatConfig>, now: Instant, current_mtu: u16) -> Self {
+        Self {
+            window: config.initial_window as f64,
+            ssthresh: u64::MAX,
+            recovery_start_time: now,
+            current_mtu: current_mtu as u64,
+            slowdown: Slowdown::Unscheduled,
+            config,
+        }
+    }
+
+    fn minimum_window(&self) -> u64 {
+        2 * self.current_mtu
+    }
+
+    /// Fraction of the standard window growth used, from 1/16 to 1
+    ///
+    /// Paths with a short base delay need a lower gain to avoid overshooting the target.
+    fn gain(&self, base_delay: Duration) -> f64 {
+        let target = self.config.target_delay.as_secs_f64();
+        let base = base_delay.as_secs_f64().max(f64::EPSILON);
+        1.0 / (2.0 * target / base).ceil().clamp(1.0, MAX_GAIN_DIVISOR)
+    }
+}
+
+impl Controller for Ledbat {
+    fn on_ack(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        bytes: u64,
+        app_limited: bool,
+        rtt: &RttEstimator,
+    ) {
+        match self.slowdown {
+            Slowdown::Scheduled(start) if now >= start => {
+                self.ssthresh = self.window();
+                self.window = self.minimum_window() as f64;
+                self.slowdown = Slowdown::Frozen {
+                    start: now,
+                    until: now + SLOWDOWN_ROUNDS * rtt.get(),
+                };
+                return;
+            }
+            Slowdown::Frozen { until, .. } if now < until => return,
+            Slowdown::Frozen { start, .. } => self.slowdown = Slowdown::Regrowing { start },
+            _ => {}
+        }
+
+        if app_limited || sent <= self.recovery_start_time {
+            return;
+        }
+
+        let base_delay = rtt.min();
+        let queueing_delay = rtt.get().saturating_sub(base_delay);
+        let target = self.config.target_delay;
+        let gain = self.gain(base_delay);
+        let bytes = bytes as f64;
+
+        if self.window() < self.ssthresh {
+            // Slow start, until the queue starts growing appreciably
+            if queueing_delay > target * 3 / 4 {
+                self.ssthresh = self.window();
+            } else {
+                self.window += gain * bytes;
+            }
+            return;
+        }
+
+        match self.slowdown {
+            // Wait for the queue to settle before the first slowdown
+            Slowdown::Unscheduled => self.slowdown = Slowdown::Scheduled(now + 2 * rtt.get()),
+            // Spend at most a tenth of the time slowed down
+            Slowdown::Regrowing { start } => {
+                let interval = SLOWDOWN_INTERVAL_FACTOR * (now - start);
+                self.slowdown = Slowdown::Scheduled(now + interval);
+            }
+            _ => {}
+        }
+
+        // Congestion avoidance, changing the window by at most one datagram per round trip while
+        // below the target, or by up to half of it while above
+        let mss = self.current_mtu as f64;
+        let per_round = if queueing_delay <= target {
+            gain * mss
+        } else {
+            let excess = queueing_delay.as_secs_f64() / target.as_secs_f64() - 1.0;
+            (gain * mss - DECREASE_CONSTANT * self.window * excess).max(-self.window / 2.0)
+        };
+        self.window += per_round * bytes / self.window;
+        self.window = self.window.max(self.minimum_window() as f64);
+    }
+
+    fn on_congestion_event(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        is_persistent_congestion: bool,
+        _lost_bytes: u64,
+    ) {
+        if sent <= self.recovery_start_time {
+            return;
+        }
+
+        self.recovery_start_time = now;
+        let window = (self.window() as f32 * self.config.loss_reduction_factor) as u64;
+        let window = window.max(self.minimum_window());
+        self.window = window as f64;
+        self.ssthresh = window;
+
+        if is_persistent_congestion {
+            self.window = self.minimum_window() as f64;
+        }
+    }
+
+    fn on_mtu_update(&mut self, new_mtu: u16) {
+        self.current_mtu = new_mtu as u64;
+        self.window = self.window.max(self.minimum_window() as f64);
+    }
+
+    fn seed_window(&mut self, window: u64) {
+        self.window = window.max(self.minimum_window()) as f64;
+    }
+
+    fn window(&self) -> u64 {
+        self.window as u64
+    }
+
+    fn seed(&mut self, window: u64, ssthresh: u64, recovery_start: Option<Instant>) {
+        self.seed_window(window);
+        self.ssthresh = ssthresh;
+        if let Some(time) = recovery_start {
+            self.recovery_start_time = time;
+        }
+    }
+
+    fn ssthresh(&self) -> u64 {
+        self.ssthresh
+    }
+
+    fn recovery_start(&self) -> Option<Instant> {
+        Some(self.recovery_start_time)
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+
+    fn initial_window(&self) -> u64 {
+        self.config.initial_window
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Configuration for the `Ledbat` congestion controller
+#[derive(Debug, Clone)]
+pub struct LedbatConfig {
+    initial_window: u64,
+    target_delay: Duration,
+    loss_reduction_factor: f32,
+}
+
+impl LedbatConfig {
+    /// Default limit on the amount of outstanding data in bytes.
+    ///
+    /// Recommended value: `min(10 * max_datagram_size, max(2 * max_datagram_size, 14720))`
+    pub fn initial_window(&mut self, value: u64) -> &mut Self {
+        self.initial_window = value;
+        self
+    }
+
+    /// Queueing delay beyond which the window is reduced
+    ///
+    /// Defaults to 60ms. Lower values yield to competing traffic sooner, at the cost of
+    /// underutilizing paths with noisy RTTs.
+    pub fn target_delay(&mut self, value: Duration) -> &mut Self {
+        self.target_delay = value;
+        self
+    }
+
+    /// Reduction in congestion window when a new loss event is detected.
+    pub fn loss_reduction_factor(&mut self, value: f32) -> &mut Self {
+        self.loss_reduction_factor = value;
+        self
+    }
+}
+
+impl Default for LedbatConfig {
+    fn default() -> Self {
+        Self {
+            initial_window: 14720.clamp(2 * BASE_DATAGRAM_SIZE, 10 * BASE_DATAGRAM_SIZE),
+            target_delay: Duration::from_millis(60),
+            loss_reduction_factor: 0.5,
+        }
+    }
+}
+
+impl ControllerFactory for Arc<LedbatConfig> {
+    fn build(&self, now: Instant, current_mtu: u16) -> Box<dyn Controller> {
+        Box::new(Ledbat::new(self.clone(), now, current_mtu))
+    }
+}
+
+/// Progress of the periodic slowdowns used to drain the queue and remeasure the base delay
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Slowdown {
+    /// The initial slow start hasn't completed yet
+    Unscheduled,
+    /// The next slowdown begins with the first acknowledgement at or after this time
+    Scheduled(Instant),
+    /// The window is held at its minimum until `until`
+    Frozen { start: Instant, until: Instant },
+    /// Slow starting back up to the window prior to the slowdown which began at `start`
+    Regrowing { start: Instant },
+}
+
+const MAX_GAIN_DIVISOR: f64 = 16.0;
+const DECREASE_CONSTANT: f64 = 1.0;
+/// Round trips for which the window is held at its minimum in a slowdown
+const SLOWDOWN_ROUNDS: u32 = 2;
+/// Time between slowdowns, as a multiple of the time spent in the previous one
+const SLOWDOWN_INTERVAL_FACTOR: u32 = 9;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MTU: u16 = 1200;
+    const BASE: Duration = Duration::from_millis(20);
+
+    /// An RTT estimator with a minimum of `BASE` and a smoothed RTT of `current`
+    fn rtt(current: Duration) -> RttEstimator {
+        let mut rtt = RttEstimator::new(BASE);
+        rtt.update(Duration::ZERO, BASE);
+        for _ in 0..200 {
+            rtt.update(Duration::ZERO, current);
+        }
+        rtt
+    }
+
+    /// Acknowledge a window's worth of data, one datagram at a time
+    fn round(ledbat: &mut Ledbat, now: Instant, rtt: &RttEstimator) {
+        let sent = now - BASE / 2;
+        for _ in 0..ledbat.window() / MTU as u64 {
+            ledbat.on_ack(now, sent, MTU as u64, false, rtt);
+        }
+    }
+
+    #[test]
+    fn slow_start_exits_on_queueing() {
+        let mut now = Instant::now();
+        let mut ledbat = Ledbat::new(Arc::new(LedbatConfig::default()), now, MTU);
+        let idle = rtt(BASE);
+        let window = ledbat.window();
+        now += BASE;
+        round(&mut ledbat, now, &idle);
+        // Slow start grows by a sixth of the acknowledged data, with a 60ms target and 20ms base
+        assert_eq!(ledbat.window(), window + window / 6);
+        assert_eq!(ledbat.ssthresh, u64::MAX);
+
+        now += BASE;
+        let queued = rtt(BASE + Duration::from_millis(50));
+        let window = ledbat.window();
+        round(&mut ledbat, now, &queued);
+        assert_eq!(ledbat.ssthresh, window);
+        // The first slowdown is two round trips after leaving slow start
+        assert_eq!(ledbat.slowdown, Slowdown::Scheduled(now + 2 * queued.get()));
+    }
+
+    #[test]
+    fn yields_to_queueing_delay() {
+        let mut now = Instant::now();
+        let mut ledbat = Ledbat::new(Arc::new(LedbatConfig::default()), now, MTU);
+        ledbat.ssthresh = 0;
+        ledbat.seed_window(100 * MTU as u64);
+        ledbat.slowdown = Slowdown::Scheduled(now + Duration::from_secs(60));
+
+        // Below the target, the window grows by less than a datagram per round trip
+        let below = rtt(BASE + Duration::from_millis(30));
+        let window = ledbat.window();
+        now += BASE;
+        round(&mut ledbat, now, &below);
+        assert!(ledbat.window() > window);
+        assert!(ledbat.window() < window + MTU as u64);
+
+        // Above it, the window shrinks in proportion to the excess
+        let above = rtt(BASE + Duration::from_millis(90));
+        let window = ledbat.window();
+        now += BASE;
+        round(&mut ledbat, now, &above);
+        let reduced = ledbat.window();
+        assert!(reduced < window * 3 / 4, "{reduced} vs {window}");
+        assert!(reduced > window / 4, "{reduced} vs {window}");
+    }
+
+    #[test]
+    fn periodic_slowdown() {
+        let mut now = Instant::now();
+        let mut ledbat = Ledbat::new(Arc::new(LedbatConfig::default()), now, MTU);
+        let idle = rtt(BASE);
+        ledbat.seed_window(20 * MTU as u64);
+        ledbat.ssthresh = 20 * MTU as u64;
+        now += BASE;
+        ledbat.on_ack(now, now - BASE / 2, MTU as u64, false, &idle);
+        let window = ledbat.window();
+
+        // Two round trips after slow start, the window collapses for two more
+        now += 2 * idle.get();
+        ledbat.on_ack(now, now - BASE / 2, MTU as u64, false, &idle);
+        assert_eq!(ledbat.window(), ledbat.minimum_window());
+        assert_eq!(ledbat.ssthresh, window);
+        let start = now;
+        now += idle.get();
+        round(&mut ledbat, now, &idle);
+        assert_eq!(ledbat.window(), ledbat.minimum_window());
+
+        // Then slow starts back to where it was
+        now += idle.get();
+        while ledbat.window() < window {
+            round(&mut ledbat, now, &idle);
+            now += idle.get();
+        }
+        let elapsed = now - idle.get() - start;
+        assert_eq!(
+            ledbat.slowdown,
+            Slowdown::Scheduled(now - idle.get() + 9 * elapsed)
+        );
+    }
+
+    #[test]
+    fn loss_halves_window() {
+        let mut now = Instant::now();
+        let mut ledbat = Ledbat::new(Arc::new(LedbatConfig::default()), now, MTU);
+        let window = ledbat.window();
+        now += BASE;
+        ledbat.on_congestion_event(now, now - Duration::from_millis(1), false, 1200);
+        assert_eq!(ledbat.window(), window / 2);
+        assert_eq!(ledbat.ssthresh, window / 2);
+    }
+}
@@ -2,7 +2,7 @@ use std::any::Any;
 use std::sync::Arc;
 use std::time::Instant;
 
-use super::{Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
+use super::{hystart::HyStart, Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
 use crate::connection::RttEstimator;
 
 /// A simple, standard congestion controller
@@ -20,6 +20,11 @@ pub struct NewReno {
     recovery_start_time: Instant,
     /// Bytes which had been acked by the peer since leaving slow start
     bytes_acked: u64,
+    /// Slow start exit state, if enabled
+    hystart: Option<HyStart>,
+    /// Window and slow start threshold before the most recent reduction, in case it turns out to
+    /// have been spurious
+    prior: Option<(u64, u64)>,
 }
 
 impl NewReno {
@@ -30,24 +35,40 @@ impl NewReno {
             ssthresh: u64::max_value(),
             recovery_start_time: now,
             current_mtu: current_mtu as u64,
+            hystart: config.hystart.then(HyStart::default),
             config,
             bytes_acked: 0,
+            prior: None,
         }
     }
 
     fn minimum_window(&self) -> u64 {
         2 * self.current_mtu
     }
+
+    /// HyStart++ state, if enabled and still in the initial slow start
+    fn hystart(&mut self) -> Option<&mut HyStart> {
+        match self.ssthresh {
+            u64::MAX => self.hystart.as_mut(),
+            _ => None,
+        }
+    }
 }
 
 impl Controller for NewReno {
+    fn on_sent(&mut self, _now: Instant, _bytes: u64, last_packet_number: u64) {
+        if let Some(hystart) = self.hystart() {
+            hystart.on_sent(last_packet_number);
+        }
+    }
+
     fn on_ack(
         &mut self,
         _now: Instant,
         sent: Instant,
         bytes: u64,
         app_limited: bool,
-        _rtt: &RttEstimator,
+        rtt: &RttEstimator,
     ) {
         if app_limited || sent <= self.recovery_start_time {
             return;
@@ -55,7 +76,10 @@ impl Controller for NewReno {
 
         if self.window < self.ssthresh {
             // Slow start
-            self.window += bytes;
+            self.window += match self.hystart() {
+                Some(hystart) => hystart.on_ack(rtt.latest(), bytes),
+                None => bytes,
+            };
 
             if self.window >= self.ssthresh {
                 // Exiting slow start
@@ -82,6 +106,23 @@ impl Controller for NewReno {
         }
     }
 
+    fn on_end_acks(
+        &mut self,
+        _now: Instant,
+        _in_flight: u64,
+        _app_limited: bool,
+        largest_packet_num_acked: Option<u64>,
+    ) {
+        let exit = self.hystart().map_or(false, |hystart| {
+            hystart.on_end_acks(largest_packet_num_acked)
+        });
+        if exit {
+            // The RTT rose for long enough to conclude that the path's capacity was reached
+            self.ssthresh = self.window;
+            self.bytes_acked = 0;
+        }
+    }
+
     fn on_congestion_event(
         &mut self,
         now: Instant,
@@ -94,6 +135,7 @@ impl Controller for NewReno {
         }
 
         self.recovery_start_time = now;
+        self.prior = Some((self.window, self.ssthresh));
         self.window = (self.window as f32 * self.config.loss_reduction_factor) as u64;
         self.window = self.window.max(self.minimum_window());
         self.ssthresh = self.window;
@@ -103,15 +145,42 @@ impl Controller for NewReno {
         }
     }
 
+    fn on_spurious_congestion_event(&mut self) {
+        if let Some((window, ssthresh)) = self.prior.take() {
+            self.window = self.window.max(window);
+            self.ssthresh = self.ssthresh.max(ssthresh);
+        }
+    }
+
     fn on_mtu_update(&mut self, new_mtu: u16) {
         self.current_mtu = new_mtu as u64;
         self.window = self.window.max(self.minimum_window());
     }
 
+    fn seed_window(&mut self, window: u64) {
+        self.window = window.max(self.minimum_window());
+    }
+
     fn window(&self) -> u64 {
         self.window
     }
 
+    fn seed(&mut self, window: u64, ssthresh: u64, recovery_start: Option<Instant>) {
+        self.seed_window(window);
+        self.ssthresh = ssthresh;
+        if let Some(time) = recovery_start {
+            self.recovery_start_time = time;
+        }
+    }
+
+    fn ssthresh(&self) -> u64 {
+        self.ssthresh
+    }
+
+    fn recovery_start(&self) -> Option<Instant> {
+        Some(self.recovery_start_time)
+    }
+
     fn clone_box(&self) -> Box<dyn Controller> {
         Box::new(self.clone())
     }
@@ -130,6 +199,7 @@ impl Controller for NewReno {
 pub struct NewRenoConfig {
     initial_window: u64,
     loss_reduction_factor: f32,
+    hystart: bool,
 }
 
 impl NewRenoConfig {
@@ -146,6 +216,15 @@ impl NewRenoConfig {
         self.loss_reduction_factor = value;
         self
     }
+
+    /// Whether to leave the initial slow start when the RTT rises, using HyStart++ (RFC 9406)
+    ///
+    /// This avoids the large loss bursts caused by overshooting the capacity of paths with a
+    /// large bandwidth-delay product. Defaults to `false`.
+    pub fn hystart(&mut self, value: bool) -> &mut Self {
+        self.hystart = value;
+        self
+    }
 }
 
 impl Default for NewRenoConfig {
@@ -153,6 +232,7 @@ impl Default for NewRenoConfig {
         Self {
             initial_window: 14720.clamp(2 * BASE_DATAGRAM_SIZE, 10 * BASE_DATAGRAM_SIZE),
             loss_reduction_factor: 0.5,
+            hystart: false,
         }
     }
 }
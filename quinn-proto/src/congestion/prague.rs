@@ -0,0 +1,359 @@
+use std::any::Any;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::{Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
+use crate::connection::RttEstimator;
+
+/// A scalable congestion controller for L4S, based on TCP Prague
+///
+/// Rather than halving the window whenever congestion is signalled, the fraction of packets
+/// marked Congestion Experienced is tracked as a moving average over round trips, and the first
+/// mark in each round trip reduces the window in proportion to it. This keeps queues short without
+/// sacrificing throughput, but requires the path to mark packets as an L4S-aware queue would, so
+/// should be combined with [`TransportConfig::send_ect1`](crate::TransportConfig::send_ect1).
+/// Packet loss is responded to like [`NewReno`](super::NewReno).
+#[derive(Debug, Clone)]
+pub struct Prague {
+    config: Arc<PragueConfig>,
+    current_mtu: u64,
+    /// Maximum number of bytes in flight that may be sent.
+    window: u64,
+    /// Slow start threshold in bytes. When the congestion window is below ssthresh, the mode is
+    /// slow start and the window grows by the number of bytes acknowledged.
+    ssthresh: u64,
+    /// The time when the window was last reduced. Acknowledgements for packets sent before this
+    /// time neither grow nor further reduce the window.
+    recovery_start_time: Instant,
+    /// Bytes which had been acked by the peer since leaving slow start
+    bytes_acked: u64,
+    /// Moving average of the fraction of packets marked CE per round trip, from 0 to 1
+    alpha: f64,
+    /// Packets acknowledged during the current round trip
+    round_acked: u64,
+    /// Packets marked CE during the current round trip
+    round_ce: u64,
+    /// The round trip ends when a packet sent after this time is acknowledged
+    round_start: Instant,
+}
+
+impl Prague {
+    /// Construct a state using the given `config` and current time `now`
+    pub fn new(config: Arc<PragueConfig>, now: Instant, current_mtu: u16) -> Self {
+        Self {
+            window: config.initial_window,
+            ssthresh: u64::MAX,
+            recovery_start_time: now,
+            current_mtu: current_mtu as u64,
+            bytes_acked: 0,
+            alpha: config.initial_alpha,
+            round_acked: 0,
+            round_ce: 0,
+            round_start: now,
+            config,
+        }
+    }
+
+    /// Moving average of the fraction of packets marked Congestion Experienced
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    fn minimum_window(&self) -> u64 {
+        2 * self.current_mtu
+    }
+}
+
+impl Controller for Prague {
+    fn on_ack(
+        &mut self,
+        _now: Instant,
+        sent: Instant,
+        bytes: u64,
+        app_limited: bool,
+        _rtt: &RttEstimator,
+    ) {
+        if app_limited || sent <= self.recovery_start_time {
+            return;
+        }
+
+        if self.window < self.ssthresh {
+            // Slow start
+            self.window += bytes;
+
+            if self.window >= self.ssthresh {
+                self.bytes_acked = self.window - self.ssthresh;
+            }
+        } else {
+            // Congestion avoidance, increasing the window by 1 datagram for every round trip
+            self.bytes_acked += bytes;
+
+            if self.bytes_acked >= self.window {
+                self.bytes_acked -= self.window;
+                self.window += self.current_mtu;
+            }
+        }
+    }
+
+    fn on_ecn(&mut self, now: Instant, sent: Instant, newly_acked: u64, newly_ce: u64) {
+        self.round_acked += newly_acked;
+        self.round_ce += newly_ce;
+        if sent > self.round_start {
+            // A packet sent after the round trip started was acknowledged, so it's complete
+            let fraction = (self.round_ce as f64 / self.round_acked.max(1) as f64).min(1.0);
+            let gain = self.config.ewma_gain;
+            self.alpha = (1.0 - gain) * self.alpha + gain * fraction;
+            self.round_acked = 0;
+            self.round_ce = 0;
+            self.round_start = now;
+        }
+
+        // Respond to the first mark right away rather than at the end of the round trip, so that
+        // slow start doesn't keep doubling the window into the queue
+        if newly_ce == 0 || sent <= self.recovery_start_time {
+            return;
+        }
+
+        self.recovery_start_time = now;
+        self.window = (self.window as f64 * (1.0 - self.alpha / 2.0)) as u64;
+        self.window = self.window.max(self.minimum_window());
+        self.ssthresh = self.window;
+        self.bytes_acked = 0;
+    }
+
+    fn on_congestion_event(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        is_persistent_congestion: bool,
+        _lost_bytes: u64,
+    ) {
+        if sent <= self.recovery_start_time {
+            return;
+        }
+
+        self.recovery_start_time = now;
+        self.window = (self.window as f32 * self.config.loss_reduction_factor) as u64;
+        self.window = self.window.max(self.minimum_window());
+        self.ssthresh = self.window;
+
+        if is_persistent_congestion {
+            self.window = self.minimum_window();
+        }
+    }
+
+    fn on_mtu_update(&mut self, new_mtu: u16) {
+        self.current_mtu = new_mtu as u64;
+        self.window = self.window.max(self.minimum_window());
+    }
+
+    fn seed_window(&mut self, window: u64) {
+        self.window = window.max(self.minimum_window());
+    }
+
+    fn window(&self) -> u64 {
+        self.window
+    }
+
+    fn seed(&mut self, window: u64, ssthresh: u64, recovery_start: Option<Instant>) {
+        self.seed_window(window);
+        self.ssthresh = ssthresh;
+        if let Some(time) = recovery_start {
+            self.recovery_start_time = time;
+        }
+    }
+
+    fn ssthresh(&self) -> u64 {
+        self.ssthresh
+    }
+
+    fn recovery_start(&self) -> Option<Instant> {
+        Some(self.recovery_start_time)
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+
+    fn initial_window(&self) -> u64 {
+        self.config.initial_window
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Configuration for the `Prague` congestion controller
+#[derive(Debug, Clone)]
+pub struct PragueConfig {
+    initial_window: u64,
+    loss_reduction_factor: f32,
+    ewma_gain: f64,
+    initial_alpha: f64,
+}
+
+impl PragueConfig {
+    /// Default limit on the amount of outstanding data in bytes.
+    ///
+    /// Recommended value: `min(10 * max_datagram_size, max(2 * max_datagram_size, 14720))`
+    pub fn initial_window(&mut self, value: u64) -> &mut Self {
+        self.initial_window = value;
+        self
+    }
+
+    /// Reduction in congestion window when a new loss event is detected.
+    pub fn loss_reduction_factor(&mut self, value: f32) -> &mut Self {
+        self.loss_reduction_factor = value;
+        self
+    }
+
+    /// Weight given to the most recent round trip when averaging the fraction of packets marked
+    /// Congestion Experienced, from 0 to 1
+    ///
+    /// Defaults to 1/16.
+    pub fn ewma_gain(&mut self, value: f64) -> &mut Self {
+        self.ewma_gain = value.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Fraction of packets assumed to be marked Congestion Experienced before any round trip has
+    /// been observed, from 0 to 1
+    ///
+    /// Defaults to 1, so that the window is halved in response to the first marks.
+    pub fn initial_alpha(&mut self, value: f64) -> &mut Self {
+        self.initial_alpha = value.clamp(0.0, 1.0);
+        self
+    }
+}
+
+impl Default for PragueConfig {
+    fn default() -> Self {
+        Self {
+            initial_window: 14720.clamp(2 * BASE_DATAGRAM_SIZE, 10 * BASE_DATAGRAM_SIZE),
+            loss_reduction_factor: 0.5,
+            ewma_gain: 1.0 / 16.0,
+            initial_alpha: 1.0,
+        }
+    }
+}
+
+impl ControllerFactory for Arc<PragueConfig> {
+    fn build(&self, now: Instant, current_mtu: u16) -> Box<dyn Controller> {
+        Box::new(Prague::new(self.clone(), now, current_mtu))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const MTU: u16 = 1200;
+
+    fn prague(config: PragueConfig, now: Instant) -> Prague {
+        let mut prague = Prague::new(Arc::new(config), now, MTU);
+        // Leave slow start so that the window only changes in response to congestion
+        prague.ssthresh = prague.window;
+        prague
+    }
+
+    /// Acknowledge `acked` packets, `ce` of which were marked, ending the round trip at `now`
+    fn round(prague: &mut Prague, now: Instant, acked: u64, ce: u64) {
+        prague.on_ecn(now, now - Duration::from_millis(1), acked, ce);
+    }
+
+    #[test]
+    fn reduction_proportional_to_marks() {
+        let mut config = PragueConfig::default();
+        config.ewma_gain(1.0);
+        let mut now = Instant::now();
+
+        for ce in [10, 5, 1] {
+            let mut prague = prague(config.clone(), now);
+            now += Duration::from_millis(10);
+            let window = prague.window();
+            round(&mut prague, now, 10, ce);
+            assert_eq!(prague.alpha(), ce as f64 / 10.0);
+            let expected = window as f64 * (1.0 - ce as f64 / 20.0);
+            assert_eq!(prague.window(), expected as u64);
+        }
+    }
+
+    #[test]
+    fn unmarked_rounds_decay_alpha() {
+        let mut now = Instant::now();
+        let mut prague = prague(PragueConfig::default(), now);
+        let window = prague.window();
+        for _ in 0..16 {
+            now += Duration::from_millis(10);
+            round(&mut prague, now, 10, 0);
+        }
+        assert_eq!(prague.window(), window);
+        assert!(prague.alpha() < 0.4);
+
+        // Sparse marks now cause only a small reduction
+        now += Duration::from_millis(10);
+        round(&mut prague, now, 10, 1);
+        assert!(prague.window() > window * 4 / 5);
+        assert!(prague.window() < window);
+    }
+
+    #[test]
+    fn one_reduction_per_round() {
+        let mut now = Instant::now();
+        let mut prague = prague(PragueConfig::default(), now);
+        let window = prague.window();
+        now += Duration::from_millis(10);
+        let round_start = now;
+        round(&mut prague, now, 10, 10);
+        let reduced = prague.window();
+        assert!(reduced < window);
+
+        // Acknowledgements for packets sent before the reduction only contribute to the next round
+        now += Duration::from_millis(5);
+        prague.on_ecn(now, round_start - Duration::from_millis(1), 10, 10);
+        assert_eq!(prague.window(), reduced);
+        assert_eq!(prague.round_ce, 10);
+    }
+
+    #[test]
+    fn first_mark_exits_slow_start() {
+        let mut now = Instant::now();
+        let mut prague = Prague::new(Arc::new(PragueConfig::default()), now, MTU);
+        let rtt = RttEstimator::new(Duration::from_millis(10));
+        now += Duration::from_millis(10);
+        prague.on_ack(now, now, 12_000, false, &rtt);
+        round(&mut prague, now, 10, 0);
+        let window = prague.window();
+
+        // A mark halfway through the next round trip reduces the window immediately
+        let round_start = now;
+        now += Duration::from_millis(5);
+        let sent = round_start - Duration::from_millis(1);
+        prague.on_ecn(now, sent, 1, 1);
+        let reduced = (window as f64 * (1.0 - prague.alpha() / 2.0)) as u64;
+        assert!(reduced < window * 3 / 4);
+        assert_eq!(prague.window(), reduced);
+        assert_eq!(prague.ssthresh, reduced);
+
+        // Further marks in the same round trip don't reduce it again, and growth is no longer
+        // exponential
+        prague.on_ecn(now, sent, 1, 1);
+        assert_eq!(prague.window(), reduced);
+        now += Duration::from_millis(1);
+        prague.on_ack(now, now, 1200, false, &rtt);
+        assert_eq!(prague.window(), reduced);
+    }
+
+    #[test]
+    fn loss_halves_window() {
+        let mut now = Instant::now();
+        let mut prague = prague(PragueConfig::default(), now);
+        let window = prague.window();
+        now += Duration::from_millis(10);
+        prague.on_congestion_event(now, now - Duration::from_millis(1), false, 1200);
+        assert_eq!(prague.window(), window / 2);
+    }
+}
@@ -22,7 +22,9 @@ use crate::{
     crypto::{self, HeaderKey, KeyPair, Keys, PacketKey},
     frame,
     frame::{Close, Datagram, FrameStruct},
+    memory_budget::MemoryBudget,
     packet::{Header, LongType, Packet, PartialDecode, SpaceId},
+    path_cache::PathProperties,
     range_set::ArrayRangeSet,
     shared::{
         ConnectionEvent, ConnectionEventInner, ConnectionId, EcnCodepoint, EndpointEvent,
@@ -61,10 +63,13 @@ mod spaces;
 pub use spaces::Retransmits;
 #[cfg(not(fuzzing))]
 use spaces::Retransmits;
-use spaces::{PacketSpace, SendableFrames, SentPacket, ThinRetransmits};
+use spaces::{LostPacket, PacketSpace, SendableFrames, SentPacket, ThinRetransmits};
 
 mod stats;
-pub use stats::{ConnectionStats, FrameStats, PathStats, UdpStats};
+pub use stats::{
+    ConnectionStats, FrameStats, HandshakeStats, PacketSpaceStats, PathStats, RecvStreamStats,
+    SendStreamStats, UdpStats,
+};
 
 mod streams;
 #[cfg(fuzzing)]
@@ -74,11 +79,11 @@ use streams::StreamsState;
 //pub(crate) use streams::{ByteSlice, BytesArray};
 pub use streams::{
     BytesSource, Chunks, FinishError, ReadError, ReadableError, RecvStream, SendStream,
-    StreamEvent, Streams, UnknownStream, WriteError, Written,
+    SetDscpError, StreamEvent, Streams, UnknownStream, WriteError, Written,
 };
 
 mod timer;
-use crate::congestion::Controller;
+use crate::congestion::{Controller, ControllerFactory};
 use timer::{Timer, TimerTable};
 
 /// Protocol state and logic for a single QUIC connection
@@ -135,6 +140,8 @@ pub struct Connection {
     local_ip: Option<IpAddr>,
     path: PathData,
     prev_path: Option<PathData>,
+    /// Replaces `config.congestion_controller_factory` for new paths, if set
+    congestion_controller_factory: Option<Box<dyn ControllerFactory + Send + Sync>>,
     state: State,
     side: Side,
     /// Whether or not 0-RTT was enabled during the handshake. Does not imply acceptance.
@@ -218,6 +225,12 @@ pub struct Connection {
     datagrams: DatagramState,
     /// Connection level statistics
     stats: ConnectionStats,
+    /// When the connection was created, which handshake statistics are measured from
+    created: Instant,
+    /// Share of the endpoint's egress bandwidth, for the I/O layer's benefit
+    egress_weight: u32,
+    /// Whether the I/O layer enforces `Transmit::departure_time`
+    departure_times_supported: bool,
     /// QUIC version used for the connection.
     version: u32,
 }
@@ -237,6 +250,8 @@ impl Connection {
         now: Instant,
         version: u32,
         allow_mtud: bool,
+        cached_path: Option<PathProperties>,
+        memory_budget: Arc<MemoryBudget>,
     ) -> Self {
         let side = if server_config.is_some() {
             Side::Server
@@ -254,6 +269,9 @@ impl Connection {
         });
         let mut rng = StdRng::from_entropy();
         let path_validated = server_config.as_ref().map_or(true, |c| c.use_retry);
+        let egress_weight = server_config.as_ref().map_or(1, |c| c.egress_weight);
+        let initial_rtt =
+            cached_path.map_or(config.initial_rtt, |path| path.rtt.max(TIMER_GRANULARITY));
         let mut this = Self {
             endpoint_config,
             server_config,
@@ -263,7 +281,7 @@ impl Connection {
             local_cid_state: CidState::new(cid_gen.cid_len(), cid_gen.cid_lifetime(), now),
             path: PathData::new(
                 remote,
-                config.initial_rtt,
+                initial_rtt,
                 config
                     .congestion_controller_factory
                     .build(now, config.get_initial_mtu()),
@@ -279,6 +297,7 @@ impl Connection {
             ),
             local_ip,
             prev_path: None,
+            congestion_controller_factory: None,
             side,
             state,
             zero_rtt_enabled: false,
@@ -328,8 +347,36 @@ impl Connection {
             rem_cids: CidQueue::new(rem_cid),
             rng,
             stats: ConnectionStats::default(),
+            created: now,
+            egress_weight,
+            departure_times_supported: false,
             version,
         };
+        if let Some(timeout) = this.config.handshake_timeout {
+            this.timers.set(Timer::Handshake, now + timeout);
+        }
+        if side.is_server() && path_validated {
+            // Servers only validate the client's address up front by sending a Retry
+            this.stats.handshake.retries = 1;
+        }
+        if let Some(path) = cached_path {
+            // Cap the window, since the path might be more congested than it used to be
+            let initial_window = this.path.congestion.initial_window();
+            if path.cwnd > initial_window {
+                this.path
+                    .congestion
+                    .seed_window(path.cwnd.min(MAX_CACHED_WINDOW_MULTIPLIER * initial_window));
+            }
+            this.path.mtud.probe_first(path.mtu);
+        }
+        this.streams.set_rate_limit(this.config.send_rate_limit);
+        this.streams.set_memory_budget(memory_budget);
+        if this.config.receive_window_auto_tuning {
+            this.streams.set_max_receive_windows(
+                this.config.max_receive_window,
+                this.config.max_stream_receive_window,
+            );
+        }
         if side.is_client() {
             // Kick off the connection
             this.write_crypto();
@@ -423,8 +470,23 @@ impl Connection {
     pub fn poll_transmit(&mut self, now: Instant, max_datagrams: usize) -> Option<Transmit> {
         assert!(max_datagrams != 0);
         let max_datagrams = max_datagrams.min(MAX_TRANSMIT_SEGMENTS);
+        self.streams.set_time(now);
+        for dir in Dir::iter() {
+            if self.streams.take_streams_blocked_dirty(dir) {
+                self.spaces[SpaceId::Data].pending.streams_blocked[dir as usize] = true;
+            }
+        }
+
+        match self.streams.update_rate_limits(now) {
+            Some(time) => self.timers.set(Timer::RateLimit, time),
+            None => self.timers.stop(Timer::RateLimit),
+        }
+        self.update_memory_usage();
 
         let mut num_datagrams = 0;
+        // All datagrams in a transmit share the same departure time and DSCP
+        let mut departure_time = None;
+        let mut dscp = self.config.dscp;
 
         // Send PATH_CHALLENGE for a previous path if necessary
         if let Some(ref mut prev_path) = self.prev_path {
@@ -471,8 +533,10 @@ impl Connection {
                     destination,
                     contents: buf.freeze(),
                     ecn: None,
+                    dscp: self.config.dscp,
                     segment_size: None,
                     src_ip: self.local_ip,
+                    departure_time: None,
                 });
             }
         }
@@ -558,6 +622,16 @@ impl Connection {
                     break;
                 }
 
+                // Stream data marked with a different DSCP is left for the next transmit
+                let datagram_dscp = match space_id {
+                    SpaceId::Data => self.streams.next_dscp(self.config.dscp),
+                    _ => self.config.dscp,
+                };
+                if num_datagrams > 0 && datagram_dscp != dscp {
+                    break;
+                }
+                dscp = datagram_dscp;
+
                 // Anti-amplification is only based on `total_sent`, which gets
                 // updated at the end of this method. Therefore we pass the amount
                 // of bytes for datagrams that are already created, as well as 1 byte
@@ -593,19 +667,41 @@ impl Connection {
 
                     // Check whether the next datagram is blocked by pacing
                     let smoothed_rtt = self.path.rtt.get();
-                    if let Some(delay) = self.path.pacing.delay(
+                    self.path.pacing.set_rate(
+                        self.path.congestion.pacing_rate(),
+                        self.path.congestion.send_quantum(),
+                        self.config.max_send_rate,
+                    );
+                    let departure = match self.path.pacing.delay(
                         smoothed_rtt,
                         bytes_to_send,
                         self.path.current_mtu(),
                         self.path.congestion.window(),
                         now,
                     ) {
-                        self.timers.set(Timer::Pacing, delay);
-                        congestion_blocked = true;
-                        // Loss probes should be subject to pacing, even though
-                        // they are not congestion controlled.
+                        None => self.path.pacing.departure_time(now),
+                        // Let the I/O layer hold back the datagram instead of waiting
+                        Some(delay)
+                            if self.config.pacing_offload
+                                && self.departure_times_supported
+                                && delay.saturating_duration_since(now) <= pacing::MAX_DEFERRAL =>
+                        {
+                            self.path.pacing.defer(delay);
+                            Some(delay)
+                        }
+                        Some(delay) => {
+                            self.timers.set(Timer::Pacing, delay);
+                            congestion_blocked = true;
+                            // Loss probes should be subject to pacing, even though
+                            // they are not congestion controlled.
+                            break;
+                        }
+                    };
+                    if num_datagrams > 0 && departure != departure_time {
+                        // Leave the datagram for the next transmit
                         break;
                     }
+                    departure_time = departure;
                 }
 
                 // Finish current packet
@@ -740,7 +836,8 @@ impl Connection {
                 break;
             }
 
-            let sent = self.populate_packet(space_id, &mut buf, buf_capacity - builder.tag_len);
+            let sent =
+                self.populate_packet(now, space_id, &mut buf, buf_capacity - builder.tag_len);
 
             // ACK-only packets should only be sent when explicitly allowed. If we write them due
             // to any other reason, there is a bug which leads to one component announcing write
@@ -780,6 +877,10 @@ impl Connection {
         }
 
         self.app_limited = buf.is_empty() && !congestion_blocked;
+        self.streams.set_congestion_blocked(congestion_blocked, now);
+        if self.app_limited {
+            self.path.delivery_rate.on_app_limited(self.in_flight.bytes);
+        }
 
         // Send MTU probe if necessary
         if buf.is_empty() && self.state.is_established() {
@@ -838,15 +939,17 @@ impl Connection {
             destination: self.path.remote,
             contents: buf.freeze(),
             ecn: if self.path.sending_ecn {
-                Some(EcnCodepoint::Ect0)
+                Some(self.ecn_codepoint())
             } else {
                 None
             },
+            dscp,
             segment_size: match num_datagrams {
                 1 => None,
                 _ => Some(self.path.current_mtu() as usize),
             },
             src_ip: self.local_ip,
+            departure_time,
         })
     }
 
@@ -907,9 +1010,13 @@ impl Connection {
                 }
 
                 let was_anti_amplification_blocked = self.path.anti_amplification_blocked(1);
+                self.streams.set_time(now);
 
                 self.stats.udp_rx.datagrams += 1;
                 self.stats.udp_rx.bytes += first_decode.len() as u64;
+                if self.stats.handshake.first_byte.is_none() {
+                    self.stats.handshake.first_byte = Some(now.duration_since(self.created));
+                }
                 let data_len = first_decode.len();
 
                 self.handle_decode(now, remote, ecn, first_decode);
@@ -930,6 +1037,7 @@ impl Connection {
                     // the server's first flight is lost.
                     self.set_loss_detection_timer(now);
                 }
+                self.update_memory_usage();
             }
             NewIdentifiers(ids, now) => {
                 self.local_cid_state.new_cids(&ids, now);
@@ -945,6 +1053,70 @@ impl Connection {
                     self.reset_cid_retirement();
                 }
             }
+            PathMtuError {
+                now,
+                remote,
+                max_udp_payload_size,
+                quoted,
+            } => {
+                // Only trust reports which quote a datagram we might have sent on the current path
+                if remote != self.path.remote || !self.sent_to_active_cid(&quoted) {
+                    trace!("ignoring unrecognized path MTU error");
+                    return;
+                }
+                self.on_path_mtu_error(now, max_udp_payload_size);
+            }
+        }
+    }
+
+    /// Report buffered stream data to the endpoint's memory budget, and issue any flow control
+    /// credit the budget no longer holds back
+    fn update_memory_usage(&mut self) {
+        if self.streams.update_memory_usage() {
+            self.streams
+                .queue_held_back_credit(&mut self.spaces[SpaceId::Data].pending);
+        }
+    }
+
+    /// Whether `quoted` starts with the header of a packet addressed to the active remote CID
+    fn sent_to_active_cid(&self, quoted: &[u8]) -> bool {
+        let cid = self.rem_cids.active();
+        let quoted_cid = match quoted.first() {
+            // Long header: flags, version, then the length-prefixed destination CID
+            Some(&first) if first & 0x80 != 0 => quoted
+                .get(5)
+                .filter(|&&len| usize::from(len) == cid.len())
+                .and_then(|_| quoted.get(6..6 + cid.len())),
+            Some(_) => quoted.get(1..1 + cid.len()),
+            None => None,
+        };
+        quoted_cid == Some(&cid[..])
+    }
+
+    fn on_path_mtu_error(&mut self, now: Instant, max_udp_payload_size: u16) {
+        // An in-flight probe that exceeds the reported size won't be acknowledged
+        if let Some(packet) = self.path.mtud.in_flight_mtu_probe() {
+            let space = &mut self.spaces[SpaceId::Data];
+            if space
+                .sent_packets
+                .get(&packet)
+                .map_or(false, |info| info.size > max_udp_payload_size)
+            {
+                let info = space.sent_packets.remove(&packet).unwrap();
+                self.remove_in_flight(SpaceId::Data, &info);
+                self.path.mtud.on_probe_lost();
+                self.stats.path.lost_plpmtud_probes += 1;
+            }
+        }
+
+        if self.path.mtud.on_path_mtu_error(now, max_udp_payload_size) {
+            debug!(
+                mtu = self.path.current_mtu(),
+                "MTU lowered by path MTU error"
+            );
+            self.path
+                .congestion
+                .on_mtu_update(self.path.mtud.current_mtu());
         }
     }
 
@@ -958,6 +1130,7 @@ impl Connection {
     /// `Instant` that was output by `poll_timeout`; however spurious extra calls will simply
     /// no-op and therefore are safe.
     pub fn handle_timeout(&mut self, now: Instant) {
+        self.streams.set_time(now);
         for &timer in &Timer::VALUES {
             if !self.timers.is_expired(timer, now) {
                 continue;
@@ -970,7 +1143,12 @@ impl Connection {
                     self.endpoint_events.push_back(EndpointEventInner::Drained);
                 }
                 Timer::Idle => {
-                    self.kill(ConnectionError::TimedOut);
+                    self.kill(now, ConnectionError::TimedOut);
+                }
+                Timer::Handshake => {
+                    debug!("handshake timed out");
+                    self.stats.handshake.timed_out = true;
+                    self.kill(now, ConnectionError::TimedOut);
                 }
                 Timer::KeepAlive => {
                     trace!("sending keep-alive");
@@ -992,6 +1170,12 @@ impl Connection {
                     self.path.challenge_pending = false;
                 }
                 Timer::Pacing => trace!("pacing timer expired"),
+                Timer::RateLimit => {
+                    trace!("rate limit timer expired");
+                    if let Some(time) = self.streams.update_rate_limits(now) {
+                        self.timers.set(Timer::RateLimit, time);
+                    }
+                }
                 Timer::PushNewCid => {
                     // Update `retire_prior_to` field in NEW_CONNECTION_ID frame
                     let num_new_cid = self.local_cid_state.on_cid_timeout().into();
@@ -1029,7 +1213,7 @@ impl Connection {
     fn close_inner(&mut self, now: Instant, reason: Close) {
         let was_closed = self.state.is_closed();
         if !was_closed {
-            self.close_common();
+            self.close_common(now);
             self.set_close_timer(now);
             self.close = true;
             self.state = State::Closed(state::Closed { reason });
@@ -1046,6 +1230,8 @@ impl Connection {
         let mut stats = self.stats;
         stats.path.rtt = self.path.rtt.get();
         stats.path.cwnd = self.path.congestion.window();
+        stats.send_rate_limited = self.streams.rate_limited_time;
+        stats.handshake.accepted_0rtt = self.accepted_0rtt;
 
         stats
     }
@@ -1149,6 +1335,63 @@ impl Connection {
         self.path.congestion.as_ref()
     }
 
+    /// Replace this connection's congestion controller with one built by `factory`
+    ///
+    /// Useful to change how aggressively a connection competes for bandwidth mid-transfer, e.g.
+    /// to demote it to a background controller such as [`LedbatConfig`](crate::congestion::LedbatConfig). The new
+    /// controller starts from the current congestion window, and the path's RTT estimates are
+    /// retained. `factory` is also used for any paths the connection later migrates to.
+    pub fn set_congestion_controller(
+        &mut self,
+        now: Instant,
+        factory: impl ControllerFactory + Send + Sync + 'static,
+    ) {
+        self.path.replace_congestion_controller(now, &factory);
+        if let Some(ref mut prev_path) = self.prev_path {
+            prev_path.replace_congestion_controller(now, &factory);
+        }
+        self.congestion_controller_factory = Some(Box::new(factory));
+    }
+
+    /// Limit the rate at which stream data is sent to `rate` bytes per second
+    ///
+    /// Unlike congestion control, the limit is fixed, e.g. to throttle a bulk upload. Stream
+    /// payload is counted, including retransmissions, but not framing or packet overhead.
+    /// Individual streams may additionally be limited with
+    /// [`SendStream::set_rate_limit()`](crate::SendStream::set_rate_limit). Time spent held back
+    /// by either limit is reported in [`ConnectionStats::send_rate_limited`]. Initially
+    /// [`TransportConfig::send_rate_limit()`].
+    pub fn set_send_rate_limit(&mut self, rate: Option<u64>) {
+        self.streams.set_rate_limit(rate);
+    }
+
+    /// The current limit on the rate at which stream data is sent, in bytes per second
+    pub fn send_rate_limit(&self) -> Option<u64> {
+        self.streams.rate_limit()
+    }
+
+    /// Set this connection's weight when sharing its endpoint's egress bandwidth
+    ///
+    /// Only meaningful to I/O layers which limit the endpoint's aggregate send rate; see
+    /// [`ServerConfig::egress_weight()`]. Clients start out with a weight of 1.
+    pub fn set_egress_weight(&mut self, weight: u32) {
+        self.egress_weight = weight;
+    }
+
+    /// This connection's weight when sharing its endpoint's egress bandwidth
+    pub fn egress_weight(&self) -> u32 {
+        self.egress_weight
+    }
+
+    /// Inform the connection whether the I/O layer enforces [`Transmit::departure_time`]
+    ///
+    /// [`TransportConfig::pacing_offload()`] only takes effect while this is set, and pacing falls
+    /// back to timers otherwise. Must be updated if the connection moves to a socket with
+    /// different capabilities. Defaults to `false`.
+    pub fn set_departure_times_supported(&mut self, value: bool) {
+        self.departure_times_supported = value;
+    }
+
     /// Modify the number of remotely initiated streams that may be concurrently open
     ///
     /// No streams may be opened by the peer unless fewer than `count` are already open. Large
@@ -1201,6 +1444,8 @@ impl Connection {
             }
         };
 
+        self.detect_spurious_losses(now, space, &ack);
+
         // Avoid DoS from unreasonably huge ack ranges by filtering out just the new acks.
         let mut newly_acked = ArrayRangeSet::new();
         for range in ack.iter() {
@@ -1238,6 +1483,13 @@ impl Connection {
             }
         }
 
+        if let Some(sample) =
+            self.path
+                .delivery_rate
+                .on_end_acks(now, self.in_flight.bytes, self.path.rtt.min())
+        {
+            self.path.congestion.on_rate_sample(now, &sample);
+        }
         self.path.congestion.on_end_acks(
             now,
             self.in_flight.bytes,
@@ -1300,7 +1552,8 @@ impl Connection {
         ecn: frame::EcnCounts,
         largest_sent_time: Instant,
     ) {
-        match self.spaces[space].detect_ecn(newly_acked, ecn) {
+        let codepoint = self.ecn_codepoint();
+        match self.spaces[space].detect_ecn(newly_acked, ecn, codepoint) {
             Err(e) => {
                 debug!("halting ECN due to verification failure: {}", e);
                 self.path.sending_ecn = false;
@@ -1308,16 +1561,28 @@ impl Connection {
                 // future attempts to use ECN on new paths.
                 self.spaces[space].ecn_feedback = frame::EcnCounts::ZERO;
             }
-            Ok(false) => {}
-            Ok(true) => {
-                self.stats.path.congestion_events += 1;
+            Ok(newly_ce) => {
+                if newly_ce != 0 {
+                    self.stats.path.congestion_events += 1;
+                    self.path
+                        .on_ecn_congestion(now, self.stats.path.congestion_events);
+                }
                 self.path
                     .congestion
-                    .on_congestion_event(now, largest_sent_time, false, 0);
+                    .on_ecn(now, largest_sent_time, newly_acked, newly_ce);
             }
         }
     }
 
+    /// ECN-capable codepoint to mark outgoing packets with
+    fn ecn_codepoint(&self) -> EcnCodepoint {
+        if self.config.send_ect1 {
+            EcnCodepoint::Ect1
+        } else {
+            EcnCodepoint::Ect0
+        }
+    }
+
     // Not timing-aware, so it's safe to call this for inferred acks, such as arise from
     // high-latency handshakes
     fn on_packet_acked(&mut self, now: Instant, space: SpaceId, info: SentPacket) {
@@ -1332,6 +1597,14 @@ impl Connection {
                 self.app_limited,
                 &self.path.rtt,
             );
+            if info.size != 0 {
+                self.path.delivery_rate.on_acked(
+                    now,
+                    info.time_sent,
+                    info.size.into(),
+                    &info.delivery,
+                );
+            }
         }
 
         // Update state for confirmed delivery of frames
@@ -1399,17 +1672,59 @@ impl Connection {
         self.set_loss_detection_timer(now);
     }
 
+    /// Handle the acknowledgement of packets which were deemed lost
+    fn detect_spurious_losses(&mut self, now: Instant, space: SpaceId, ack: &frame::Ack) {
+        let mut spurious = Vec::new();
+        for range in ack.iter() {
+            let lost_packets = &self.spaces[space].lost_packets;
+            spurious.extend(lost_packets.range(range).map(|(&packet, _)| packet));
+        }
+
+        let rtt = cmp::max(self.path.rtt.conservative(), TIMER_GRANULARITY);
+        for packet in spurious {
+            let lost = self.spaces[space]
+                .lost_packets
+                .remove(&packet)
+                .expect("spurious losses are taken from lost_packets");
+            trace!(packet, "packet deemed lost was acknowledged");
+            self.stats.path.spurious_lost_packets += 1;
+            if self.config.adaptive_reordering_threshold {
+                // Thresholds which would have avoided deeming the packet lost
+                let reordering = (lost.largest_acked - packet + 1).min(MAX_PACKET_THRESHOLD);
+                let delay = now.saturating_duration_since(lost.time_sent).as_secs_f32()
+                    / rtt.as_secs_f32()
+                    * (9.0 / 8.0);
+                self.path
+                    .on_reordering(reordering as u32, delay.min(MAX_TIME_THRESHOLD));
+            }
+
+            let episode = match lost.congestion_event {
+                Some(x) => x,
+                None => continue,
+            };
+            if self.path.on_spurious_loss(episode) {
+                debug!("congestion event {} was spurious", episode);
+                self.stats.path.spurious_congestion_events += 1;
+                if self.config.adaptive_reordering_threshold {
+                    self.path.congestion.on_spurious_congestion_event();
+                }
+            }
+        }
+    }
+
     fn detect_lost_packets(&mut self, now: Instant, pn_space: SpaceId, due_to_ack: bool) {
         let mut lost_packets = Vec::<u64>::new();
         let mut lost_mtu_probe = None;
         let in_flight_mtu_probe = self.path.mtud.in_flight_mtu_probe();
         let rtt = self.path.rtt.conservative();
-        let loss_delay = cmp::max(rtt.mul_f32(self.config.time_threshold), TIMER_GRANULARITY);
+        let time_threshold = self.config.time_threshold.max(self.path.time_threshold);
+        let loss_delay = cmp::max(rtt.mul_f32(time_threshold), TIMER_GRANULARITY);
 
         // Packets sent before this time are deemed lost.
         let lost_send_time = now.checked_sub(loss_delay).unwrap();
         let largest_acked_packet = self.spaces[pn_space].largest_acked_packet.unwrap();
-        let packet_threshold = self.config.packet_threshold as u64;
+        let packet_threshold = cmp::max(self.config.packet_threshold, self.path.packet_threshold);
+        let packet_threshold = packet_threshold as u64;
         let mut size_of_lost_packets = 0u64;
 
         // InPersistentCongestion: Determine if all packets in the time period before the newest
@@ -1477,15 +1792,41 @@ impl Connection {
             let largest_lost_sent = self.spaces[pn_space].sent_packets[&largest_lost].time_sent;
             self.lost_packets += lost_packets.len() as u64;
             self.stats.path.lost_packets += lost_packets.len() as u64;
+            self.stats.handshake.space_mut(pn_space).lost_packets += lost_packets.len() as u64;
             self.stats.path.lost_bytes += size_of_lost_packets;
+            self.path.delivery_rate.on_lost(size_of_lost_packets);
             trace!(
                 "packets lost: {:?}, bytes lost: {}",
                 lost_packets,
                 size_of_lost_packets
             );
 
+            // Every ack-eliciting packet is in flight, so their loss raises a congestion event
+            let space = &self.spaces[pn_space];
+            let lost_ack_eliciting_packets = lost_packets
+                .iter()
+                .filter(|&packet| space.sent_packets[packet].ack_eliciting)
+                .count() as u64;
+            let loss_episode = match lost_ack_eliciting_packets {
+                0 => None,
+                n => Some(self.path.on_packets_lost(
+                    now,
+                    largest_lost_sent,
+                    self.stats.path.congestion_events + 1,
+                    n,
+                )),
+            };
+
             for packet in &lost_packets {
                 let info = self.spaces[pn_space].sent_packets.remove(packet).unwrap(); // safe: lost_packets is populated just above
+                self.spaces[pn_space].lost_packets.insert(
+                    *packet,
+                    LostPacket {
+                        time_sent: info.time_sent,
+                        largest_acked: largest_acked_packet,
+                        congestion_event: loss_episode.filter(|_| info.ack_eliciting),
+                    },
+                );
                 self.remove_in_flight(pn_space, &info);
                 for frame in info.stream_frames {
                     self.streams.retransmit(frame);
@@ -1494,6 +1835,20 @@ impl Connection {
                 self.path.mtud.on_non_probe_lost(*packet, info.size);
             }
 
+            // Packets which haven't been acknowledged long after being deemed lost really were
+            let space = &mut self.spaces[pn_space];
+            while let Some((&packet, &lost)) = space.lost_packets.iter().next() {
+                if space.lost_packets.len() <= MAX_LOST_PACKETS
+                    && lost.time_sent + congestion_period > now
+                {
+                    break;
+                }
+                space.lost_packets.remove(&packet);
+                if let Some(episode) = lost.congestion_event {
+                    self.path.on_genuine_loss(episode);
+                }
+            }
+
             if self.path.mtud.black_hole_detected(now) {
                 self.stats.path.black_holes_detected += 1;
             }
@@ -1707,6 +2062,9 @@ impl Connection {
         debug_assert!(self.side.is_server());
         let len = packet.header_data.len() + packet.payload.len();
         self.path.total_recvd = len as u64;
+        self.stats.handshake.first_byte = Some(now.duration_since(self.created));
+        self.stats.handshake.initial.received_packets += 1;
+        self.stats.handshake.initial.received_bytes += len as u64;
 
         match self.state {
             State::Handshake(ref mut state) => match packet.header {
@@ -1888,10 +2246,14 @@ impl Connection {
             // No longer needed
             self.retry_token = Bytes::new();
         }
+        if space_id == SpaceId::Handshake {
+            self.stats.handshake.confirmed = Some(now.duration_since(self.created));
+        }
         let space = &mut self.spaces[space_id];
         space.crypto = None;
         space.time_of_last_ack_eliciting_packet = None;
         space.loss_time = None;
+        space.lost_packets.clear();
         let sent_packets = mem::take(&mut space.sent_packets);
         for (_, packet) in sent_packets.into_iter() {
             self.remove_in_flight(space_id, &packet);
@@ -2061,6 +2423,11 @@ impl Connection {
                         }
                     }
 
+                    let space = self.stats.handshake.space_mut(packet.header.space());
+                    space.received_packets += 1;
+                    space.received_bytes +=
+                        (packet.header_data.len() + packet.payload.len()) as u64;
+
                     if !self.state.is_closed() {
                         let spin = match packet.header {
                             Header::Short { spin, .. } => spin,
@@ -2106,7 +2473,7 @@ impl Connection {
         }
 
         if !was_closed && self.state.is_closed() {
-            self.close_common();
+            self.close_common(now);
             if !self.state.is_drained() {
                 self.set_close_timer(now);
             }
@@ -2222,6 +2589,7 @@ impl Connection {
                 }
                 self.streams.retransmit_all_for_0rtt();
 
+                self.stats.handshake.retries += 1;
                 let token_len = packet.payload.len() - 16;
                 self.retry_token = packet.payload.freeze().split_to(token_len);
                 self.state = State::Handshake(state::Handshake {
@@ -2299,6 +2667,8 @@ impl Connection {
                     self.discard_space(now, SpaceId::Handshake);
                 }
 
+                self.stats.handshake.completed = Some(now.duration_since(self.created));
+                self.timers.stop(Timer::Handshake);
                 self.events.push_back(Event::Connected);
                 self.state = State::Established;
                 trace!("established");
@@ -2556,6 +2926,7 @@ impl Connection {
                 }
                 Frame::DataBlocked { offset } => {
                     debug!(offset, "peer claims to be blocked at connection level");
+                    self.streams.received_data_blocked(offset);
                 }
                 Frame::StreamDataBlocked { id, offset } => {
                     if id.initiator() == self.side && id.dir() == Dir::Uni {
@@ -2568,6 +2939,7 @@ impl Connection {
                         stream = %id,
                         offset, "peer claims to be blocked at stream level"
                     );
+                    self.streams.received_stream_data_blocked(id, offset);
                 }
                 Frame::StreamsBlocked { dir, limit } => {
                     if limit > MAX_STREAM_COUNT {
@@ -2579,6 +2951,7 @@ impl Connection {
                         "peer claims to be blocked opening more than {} {} streams",
                         limit, dir
                     );
+                    self.streams.received_streams_blocked(dir, limit);
                 }
                 Frame::StopSending(frame::StopSending { id, error_code }) => {
                     if id.initiator() != self.side {
@@ -2738,12 +3111,14 @@ impl Connection {
             let peer_max_udp_payload_size =
                 u16::try_from(self.peer_params.max_udp_payload_size.into_inner())
                     .unwrap_or(u16::MAX);
+            let congestion_controller_factory = match self.congestion_controller_factory {
+                Some(ref factory) => factory.as_ref(),
+                None => self.config.congestion_controller_factory.as_ref(),
+            };
             PathData::new(
                 remote,
                 self.config.initial_rtt,
-                self.config
-                    .congestion_controller_factory
-                    .build(now, self.config.get_initial_mtu()),
+                congestion_controller_factory.build(now, self.config.get_initial_mtu()),
                 self.config.get_initial_mtu(),
                 self.config.min_mtu,
                 Some(peer_max_udp_payload_size),
@@ -2808,6 +3183,7 @@ impl Connection {
 
     fn populate_packet(
         &mut self,
+        now: Instant,
         space_id: SpaceId,
         buf: &mut BytesMut,
         max_size: usize,
@@ -2915,6 +3291,8 @@ impl Connection {
                 &mut sent.retransmits,
                 &mut self.stats.frame_tx,
                 max_size,
+                now,
+                self.path.rtt.get(),
             );
         }
 
@@ -2966,7 +3344,9 @@ impl Connection {
 
         // STREAM
         if space_id == SpaceId::Data {
-            sent.stream_frames = self.streams.write_stream_frames(buf, max_size);
+            sent.stream_frames = self
+                .streams
+                .write_stream_frames(buf, max_size, self.config.dscp);
             self.stats.frame_tx.stream += sent.stream_frames.len() as u64;
         }
 
@@ -3007,11 +3387,25 @@ impl Connection {
         stats.frame_tx.acks += 1;
     }
 
-    fn close_common(&mut self) {
+    fn close_common(&mut self, now: Instant) {
         trace!("connection closed");
         for &timer in &Timer::VALUES {
             self.timers.stop(timer);
         }
+
+        // Let future connections on this path benefit from what we learned, if anything
+        if self.path.first_packet_after_rtt_sample.is_some() {
+            self.endpoint_events
+                .push_back(EndpointEventInner::PathLearned(
+                    now,
+                    self.path.remote,
+                    PathProperties {
+                        mtu: self.path.current_mtu(),
+                        rtt: self.path.rtt.get(),
+                        cwnd: self.path.congestion.window(),
+                    },
+                ));
+        }
     }
 
     fn set_close_timer(&mut self, now: Instant) {
@@ -3194,6 +3588,12 @@ impl Connection {
         self.lost_packets
     }
 
+    /// Packet and time reordering thresholds learned on the current path
+    #[cfg(test)]
+    pub(crate) fn reordering_thresholds(&self) -> (u32, f32) {
+        (self.path.packet_threshold, self.path.time_threshold)
+    }
+
     /// Whether explicit congestion notification is in use on outgoing packets.
     #[cfg(test)]
     pub(crate) fn using_ecn(&self) -> bool {
@@ -3258,8 +3658,8 @@ impl Connection {
     }
 
     /// Terminate the connection instantly, without sending a close packet
-    fn kill(&mut self, reason: ConnectionError) {
-        self.close_common();
+    fn kill(&mut self, now: Instant, reason: ConnectionError) {
+        self.close_common(now);
         self.error = Some(reason);
         self.state = State::Drained;
         self.endpoint_events.push_back(EndpointEventInner::Drained);
@@ -3468,6 +3868,17 @@ fn instant_saturating_sub(x: Instant, y: Instant) -> Duration {
 
 // Prevents overflow and improves behavior in extreme circumstances
 const MAX_BACKOFF_EXPONENT: u32 = 16;
+
+/// Largest packet reordering threshold learned from spurious losses
+const MAX_PACKET_THRESHOLD: u64 = 64;
+/// Largest time reordering threshold learned from spurious losses, as a factor of RTT
+const MAX_TIME_THRESHOLD: f32 = 2.0;
+/// Maximum number of packets deemed lost which are remembered per packet number space, in case
+/// they are acknowledged later
+const MAX_LOST_PACKETS: usize = 256;
+
+/// Largest multiple of the initial congestion window adopted from a previous connection
+const MAX_CACHED_WINDOW_MULTIPLIER: u64 = 4;
 // Minimal remaining size to allow packet coalescing
 const MIN_PACKET_SPACE: usize = 40;
 /// The maximum amount of datagrams that are sent in a single transmit
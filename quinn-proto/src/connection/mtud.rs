@@ -134,6 +134,43 @@ impl MtuDiscovery {
         }
     }
 
+    /// Notifies the [`MtuDiscovery`] that the network reported the path to only support UDP
+    /// payloads of up to `max_udp_payload_size` bytes, e.g. via an ICMP "packet too big" message
+    ///
+    /// The report must already have been validated. If an in-flight probe exceeds the reported size,
+    /// it must be declared lost through [`MtuDiscovery::on_probe_lost`] beforehand. Returns true if
+    /// the current MTU was lowered.
+    pub(crate) fn on_path_mtu_error(&mut self, now: Instant, max_udp_payload_size: u16) -> bool {
+        // Never go below the MTU the network is required to support
+        let max_udp_payload_size = max_udp_payload_size.max(self.black_hole_detector.min_mtu);
+        if max_udp_payload_size < self.current_mtu {
+            self.current_mtu = max_udp_payload_size;
+            trace!(
+                current_mtu = self.current_mtu,
+                "MTU lowered by path MTU error"
+            );
+
+            // Searching for a higher MTU right away would only trigger the same error again
+            if let Some(state) = &mut self.state {
+                state.on_black_hole_detected(now);
+            }
+            return true;
+        }
+
+        if let Some(state) = &mut self.state {
+            state.on_path_mtu_error(max_udp_payload_size);
+        }
+        false
+    }
+
+    /// Makes the first search probe `mtu` before anything else, e.g. because an earlier connection
+    /// found the path to support it
+    pub(crate) fn probe_first(&mut self, mtu: u16) {
+        if let Some(state) = &mut self.state {
+            state.first_probe = Some(mtu);
+        }
+    }
+
     /// Notifies the [`MtuDiscovery`] that a non-probe packet was lost
     ///
     /// When done notifying of lost packets, [`MtuDiscovery::black_hole_detected`] must be called, to
@@ -169,6 +206,8 @@ struct EnabledMtuDiscovery {
     phase: Phase,
     peer_max_udp_payload_size: u16,
     config: MtuDiscoveryConfig,
+    /// Size to probe at the start of the first search, if any
+    first_probe: Option<u16>,
 }
 
 impl EnabledMtuDiscovery {
@@ -177,6 +216,7 @@ impl EnabledMtuDiscovery {
             phase: Phase::Initial,
             peer_max_udp_payload_size: MAX_UDP_PAYLOAD,
             config,
+            first_probe: None,
         }
     }
 
@@ -189,11 +229,12 @@ impl EnabledMtuDiscovery {
     ) -> Option<u16> {
         if let Phase::Initial = &self.phase {
             // Start the first search
-            self.phase = Phase::Searching(SearchState::new(
-                current_mtu,
-                self.peer_max_udp_payload_size,
-                &self.config,
-            ));
+            let mut state =
+                SearchState::new(current_mtu, self.peer_max_udp_payload_size, &self.config);
+            if let Some(mtu) = self.first_probe.take() {
+                state.probe_next(mtu);
+            }
+            self.phase = Phase::Searching(state);
         } else if let Phase::Complete(next_mtud_activation) = &self.phase {
             if now < *next_mtud_activation {
                 return None;
@@ -264,6 +305,13 @@ impl EnabledMtuDiscovery {
         }
     }
 
+    /// Called when the network reported a maximum UDP payload size of at least the current MTU
+    fn on_path_mtu_error(&mut self, max_udp_payload_size: u16) {
+        if let Phase::Searching(state) = &mut self.phase {
+            state.on_path_mtu_error(max_udp_payload_size);
+        }
+    }
+
     /// Called when a black hole is detected
     fn on_black_hole_detected(&mut self, now: Instant) {
         // Stop searching, if applicable, and reset the timer
@@ -294,6 +342,8 @@ struct SearchState {
     in_flight_probe: Option<u64>,
     /// Lost probes at the current probe size
     lost_probe_count: usize,
+    /// Whether the upper bound should be probed next, instead of bisecting
+    hinted: bool,
 }
 
 impl SearchState {
@@ -312,6 +362,7 @@ impl SearchState {
         Self {
             in_flight_probe: None,
             lost_probe_count: 0,
+            hinted: false,
             lower_bound,
             upper_bound,
             // During initialization, we consider the lower bound to have already been
@@ -327,7 +378,13 @@ impl SearchState {
         if last_probe_succeeded {
             self.lower_bound = self.last_probed_mtu;
         } else {
-            self.upper_bound = self.last_probed_mtu - 1;
+            // The upper bound might already be lower, if it was reported by the network
+            self.upper_bound = self.upper_bound.min(self.last_probed_mtu - 1);
+        }
+
+        // The network told us which size to expect, so try it instead of bisecting
+        if std::mem::take(&mut self.hinted) && self.upper_bound > self.lower_bound {
+            return Some(self.upper_bound);
         }
 
         let next_mtu = (self.lower_bound as i32 + self.upper_bound as i32) / 2;
@@ -348,6 +405,26 @@ impl SearchState {
 
         Some(next_mtu as u16)
     }
+
+    /// Limits the search to `mtu`, and probes it next
+    fn probe_next(&mut self, mtu: u16) {
+        self.upper_bound = mtu.clamp(self.lower_bound, self.upper_bound);
+        self.hinted = self.upper_bound > self.lower_bound;
+    }
+
+    /// Narrows the search to the maximum UDP payload size reported by the network
+    fn on_path_mtu_error(&mut self, max_udp_payload_size: u16) {
+        if max_udp_payload_size >= self.upper_bound {
+            return;
+        }
+
+        self.probe_next(max_udp_payload_size);
+
+        // A lost probe that exceeds the reported size is not worth retransmitting
+        if self.lost_probe_count > 0 && self.last_probed_mtu > max_udp_payload_size {
+            self.lost_probe_count = MAX_PROBE_RETRANSMITS;
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -680,6 +757,56 @@ mod tests {
         );
     }
 
+    #[test]
+    fn mtu_discovery_path_mtu_error_selects_next_probe() {
+        let mut config = MtuDiscoveryConfig::default();
+        config.upper_bound(10_000);
+        let mut mtud = MtuDiscovery::new(1_200, 1_200, None, config);
+        let now = Instant::now();
+
+        assert_eq!(mtud.poll_transmit(now, 1), Some(5600));
+
+        // The probe is reported too big, so it's declared lost and never retransmitted
+        mtud.on_probe_lost();
+        assert!(!mtud.on_path_mtu_error(now, 1_472));
+        assert_eq!(mtud.current_mtu, 1_200);
+
+        // The reported size is probed right away
+        assert_eq!(mtud.poll_transmit(now, 2), Some(1_472));
+        mtud.on_acked(SpaceId::Data, 2, 1_472);
+        assert_eq!(mtud.current_mtu, 1_472);
+
+        // Nothing is left to search
+        assert_eq!(mtud.poll_transmit(now, 3), None);
+        assert!(completed(&mtud));
+    }
+
+    #[test]
+    fn mtu_discovery_path_mtu_error_lowers_current_mtu() {
+        let mut mtud = default_mtud();
+        let now = Instant::now();
+        drive_to_completion(&mut mtud, now, 1500);
+        assert_eq!(mtud.current_mtu, 1452);
+
+        // Reports of a size above the current MTU are ignored outside of a search
+        assert!(!mtud.on_path_mtu_error(now, 1460));
+        assert_eq!(mtud.current_mtu, 1452);
+
+        assert!(mtud.on_path_mtu_error(now, 1300));
+        assert_eq!(mtud.current_mtu, 1300);
+        assert!(completed(&mtud));
+
+        // The MTU never drops below the minimum
+        assert!(mtud.on_path_mtu_error(now, 500));
+        assert_eq!(mtud.current_mtu, 1_200);
+
+        // Searching resumes after the black hole cooldown
+        assert_eq!(mtud.poll_transmit(now + Duration::from_secs(30), 100), None);
+        assert!(mtud
+            .poll_transmit(now + Duration::from_secs(61), 101)
+            .is_some());
+    }
+
     #[test]
     fn mtu_discovery_with_peer_max_udp_payload_size_clamps_upper_bound() {
         let mut mtud = default_mtud();
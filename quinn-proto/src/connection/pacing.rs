@@ -11,13 +11,26 @@ use tracing::warn;
 /// Once the bucket is empty, further transmission is blocked.
 /// The bucket refills at a rate slightly faster
 /// than one congestion window per RTT, as recommended in
-/// <https://tools.ietf.org/html/draft-ietf-quic-recovery-34#section-7.7>
+/// <https://tools.ietf.org/html/draft-ietf-quic-recovery-34#section-7.7>,
+/// unless the congestion controller or the connection's configuration dictates
+/// a rate.
 pub(super) struct Pacer {
     capacity: u64,
     last_window: u64,
     last_mtu: u16,
+    /// Pacing rate in bytes per second dictated by the congestion controller
+    rate: Option<u64>,
+    /// Burst size dictated by the congestion controller
+    quantum: Option<u64>,
+    /// Largest permitted pacing rate in bytes per second
+    max_rate: Option<u64>,
+    /// Explicit rate and burst size the capacity was last derived from
+    last_rate: Option<u64>,
+    last_quantum: Option<u64>,
     tokens: u64,
     prev: Instant,
+    /// Whether `prev` was moved ahead to the departure time of a deferred burst
+    deferred: bool,
 }
 
 impl Pacer {
@@ -28,11 +41,30 @@ impl Pacer {
             capacity,
             last_window: window,
             last_mtu: mtu,
+            rate: None,
+            quantum: None,
+            max_rate: None,
+            last_rate: None,
+            last_quantum: None,
             tokens: capacity,
             prev: now,
+            deferred: false,
         }
     }
 
+    /// Pace at `rate` bytes per second in bursts of up to `quantum` bytes rather than deriving
+    /// them from the congestion window, and never faster than `max_rate` bytes per second
+    pub(super) fn set_rate(
+        &mut self,
+        rate: Option<u64>,
+        quantum: Option<u64>,
+        max_rate: Option<u64>,
+    ) {
+        self.rate = rate.filter(|&x| x != 0);
+        self.quantum = quantum.filter(|&x| x != 0);
+        self.max_rate = max_rate;
+    }
+
     /// Record that a packet has been transmitted.
     pub(super) fn on_transmit(&mut self, packet_length: u16) {
         self.tokens = self.tokens.saturating_sub(packet_length.into())
@@ -58,13 +90,24 @@ impl Pacer {
             "zero-sized congestion control window is nonsense"
         );
 
-        if window != self.last_window || mtu != self.last_mtu {
-            self.capacity = optimal_capacity(smoothed_rtt, window, mtu);
+        let rate = self.explicit_rate(smoothed_rtt, window);
+        if window != self.last_window
+            || mtu != self.last_mtu
+            || rate != self.last_rate
+            || self.quantum != self.last_quantum
+        {
+            self.capacity = match (self.quantum, rate) {
+                (Some(quantum), _) => quantum.max(mtu.into()),
+                (None, Some(rate)) => rate_capacity(rate, mtu),
+                (None, None) => optimal_capacity(smoothed_rtt, window, mtu),
+            };
 
             // Clamp the tokens
             self.tokens = self.capacity.min(self.tokens);
             self.last_window = window;
             self.last_mtu = mtu;
+            self.last_rate = rate;
+            self.last_quantum = self.quantum;
         }
 
         // if we can already send a packet, there is no need for delay
@@ -72,6 +115,10 @@ impl Pacer {
             return None;
         }
 
+        if let Some(rate) = rate {
+            return self.rate_delay(rate, bytes_to_send, now);
+        }
+
         // we disable pacing for extremely large windows
         if window > u32::max_value().into() {
             return None;
@@ -79,10 +126,7 @@ impl Pacer {
 
         let window = window as u32;
 
-        let time_elapsed = now.checked_duration_since(self.prev).unwrap_or_else(|| {
-            warn!("received a timestamp early than a previous recorded time, ignoring");
-            Default::default()
-        });
+        let (pending, time_elapsed) = self.elapsed(now);
 
         if smoothed_rtt.as_nanos() == 0 {
             return None;
@@ -95,7 +139,10 @@ impl Pacer {
             .saturating_add(new_tokens as _)
             .min(self.capacity);
 
-        self.prev = now;
+        if !pending {
+            self.prev = now;
+            self.deferred = false;
+        }
 
         // if we can already send a packet, there is no need for delay
         if self.tokens >= bytes_to_send {
@@ -111,6 +158,81 @@ impl Pacer {
         // this is the time at which the pacing window becomes empty
         Some(self.prev + (unscaled_delay / 5) * 4)
     }
+
+    /// Like [`delay`](Self::delay), when pacing at an explicit `rate` in bytes per second
+    fn rate_delay(&mut self, rate: u64, bytes_to_send: u64, now: Instant) -> Option<Instant> {
+        let (pending, time_elapsed) = self.elapsed(now);
+        let new_tokens = rate as f64 * time_elapsed.as_secs_f64();
+        self.tokens = self
+            .tokens
+            .saturating_add(new_tokens as _)
+            .min(self.capacity);
+
+        if !pending {
+            self.prev = now;
+            self.deferred = false;
+        }
+
+        if self.tokens >= bytes_to_send {
+            return None;
+        }
+
+        let missing = bytes_to_send.max(self.capacity) - self.tokens;
+        Some(self.prev + Duration::from_secs_f64(missing as f64 / rate as f64))
+    }
+
+    /// The rate in bytes per second to pace at if not derived from a congestion `window` sent
+    /// every `smoothed_rtt`
+    fn explicit_rate(&self, smoothed_rtt: Duration, window: u64) -> Option<u64> {
+        let max_rate = match self.max_rate {
+            Some(x) => x,
+            None => return self.rate,
+        };
+        let rate = self.rate.unwrap_or_else(|| {
+            let rtt = smoothed_rtt.as_nanos().max(1);
+            (window as u128 * 5 * 1_000_000_000 / (4 * rtt)).min(u64::MAX.into()) as u64
+        });
+        match rate > max_rate {
+            true => Some(max_rate),
+            false => self.rate,
+        }
+    }
+
+    /// Whether a deferred burst is pending, and the time over which tokens accrued since the
+    /// previous call
+    fn elapsed(&self, now: Instant) -> (bool, Duration) {
+        // No tokens accrue before a deferred burst departs
+        let pending = self.departure_time(now).is_some();
+        let time_elapsed = if pending {
+            Duration::ZERO
+        } else {
+            now.checked_duration_since(self.prev).unwrap_or_else(|| {
+                warn!("received a timestamp early than a previous recorded time, ignoring");
+                Default::default()
+            })
+        };
+        (pending, time_elapsed)
+    }
+
+    /// Release the burst which becomes available at `departure` ahead of time
+    ///
+    /// Used when the I/O layer enforces departure times, so that the datagrams can be handed off
+    /// immediately rather than after the pacing timer fires. `departure` must have been returned
+    /// by [`delay`](Self::delay), at which point the bucket will have refilled.
+    pub(super) fn defer(&mut self, departure: Instant) {
+        self.tokens = self.capacity;
+        self.prev = departure;
+        self.deferred = true;
+    }
+
+    /// The departure time of datagrams transmitted at `now`, if a deferred burst is pending
+    pub(super) fn departure_time(&self, now: Instant) -> Option<Instant> {
+        if self.deferred && self.prev > now {
+            Some(self.prev)
+        } else {
+            None
+        }
+    }
 }
 
 /// Calculates a pacer capacity for a certain window and RTT
@@ -136,6 +258,13 @@ fn optimal_capacity(smoothed_rtt: Duration, window: u64, mtu: u16) -> u64 {
     capacity.clamp(MIN_BURST_SIZE * mtu as u64, MAX_BURST_SIZE * mtu as u64)
 }
 
+/// Calculates a pacer capacity for a certain rate in bytes per second, as for
+/// [`optimal_capacity`]
+fn rate_capacity(rate: u64, mtu: u16) -> u64 {
+    let capacity = (rate as u128 * BURST_INTERVAL_NANOS / 1_000_000_000) as u64;
+    capacity.clamp(MIN_BURST_SIZE * mtu as u64, MAX_BURST_SIZE * mtu as u64)
+}
+
 /// The burst interval
 ///
 /// The capacity will we refilled in 4/5 of that time.
@@ -144,6 +273,12 @@ fn optimal_capacity(smoothed_rtt: Duration, window: u64, mtu: u16) -> u64 {
 /// more applicable.
 const BURST_INTERVAL_NANOS: u128 = 2_000_000; // 2ms
 
+/// The furthest into the future a burst may be deferred to when pacing is offloaded
+///
+/// Beyond this, the pacing timer is used instead, to avoid queueing excessive amounts of data in
+/// the kernel and to stay well clear of the `fq` qdisc's drop horizon.
+pub(super) const MAX_DEFERRAL: Duration = Duration::from_millis(10);
+
 /// Allows some usage of GSO, and doesn't slow down the handshake.
 const MIN_BURST_SIZE: u64 = 10;
 
@@ -299,4 +434,99 @@ mod tests {
         );
         assert_eq!(pacer.tokens, pacer.capacity);
     }
+
+    #[test]
+    fn paces_at_explicit_rate() {
+        let window = 2_000_000u64;
+        let mtu = 1000;
+        let rtt = Duration::from_millis(50);
+        let now = Instant::now();
+
+        let mut pacer = Pacer::new(rtt, window, mtu, now);
+        pacer.set_rate(Some(1_000_000), Some(4 * mtu as u64), None);
+        assert_eq!(pacer.delay(rtt, mtu as u64, mtu, window, now), None);
+        assert_eq!(pacer.capacity, 4 * mtu as u64);
+        for _ in 0..4 {
+            pacer.on_transmit(mtu);
+        }
+
+        // Refilling a burst of 4000 bytes at 1MB/s takes 4ms
+        assert_eq!(
+            pacer.delay(rtt, mtu as u64, mtu, window, now),
+            Some(now + Duration::from_millis(4))
+        );
+        assert_eq!(
+            pacer.delay(rtt, mtu as u64, mtu, window, now + Duration::from_millis(1)),
+            None
+        );
+        assert_eq!(pacer.tokens, mtu as u64);
+    }
+
+    #[test]
+    fn caps_rate() {
+        let window = 2_000_000u64;
+        let mtu = 1000;
+        let rtt = Duration::from_millis(50);
+        let now = Instant::now();
+
+        // The window-derived rate of 50MB/s exceeds the cap
+        let mut pacer = Pacer::new(rtt, window, mtu, now);
+        pacer.set_rate(None, None, Some(1_000_000));
+        assert_eq!(pacer.explicit_rate(rtt, window), Some(1_000_000));
+        assert_eq!(pacer.delay(rtt, mtu as u64, mtu, window, now), None);
+        assert_eq!(pacer.capacity, MIN_BURST_SIZE * mtu as u64);
+
+        // As does the congestion controller's rate
+        pacer.set_rate(Some(2_000_000), None, Some(1_000_000));
+        assert_eq!(pacer.explicit_rate(rtt, window), Some(1_000_000));
+
+        // Slower rates are unaffected
+        pacer.set_rate(Some(500_000), None, Some(1_000_000));
+        assert_eq!(pacer.explicit_rate(rtt, window), Some(500_000));
+        pacer.set_rate(None, None, Some(1_000_000));
+        assert_eq!(pacer.explicit_rate(rtt, 10_000), None);
+    }
+
+    #[test]
+    fn defers_burst() {
+        let window = 2_000_000u64;
+        let mtu = 1000;
+        let rtt = Duration::from_millis(50);
+        let now = Instant::now();
+
+        let mut pacer = Pacer::new(rtt, window, mtu, now);
+        let packet_capacity = pacer.capacity / mtu as u64;
+        for _ in 0..packet_capacity {
+            pacer.on_transmit(mtu);
+        }
+        assert_eq!(pacer.departure_time(now), None);
+
+        let departure = pacer
+            .delay(rtt, mtu as u64, mtu, window, now)
+            .expect("Send must be delayed");
+        pacer.defer(departure);
+        assert_eq!(pacer.departure_time(now), Some(departure));
+
+        // The deferred burst can be handed off immediately
+        for _ in 0..packet_capacity {
+            assert_eq!(pacer.delay(rtt, mtu as u64, mtu, window, now), None);
+            assert_eq!(pacer.departure_time(now), Some(departure));
+            pacer.on_transmit(mtu);
+        }
+
+        // The following burst departs one interval after the deferred one
+        let pace_duration = Duration::from_nanos((BURST_INTERVAL_NANOS * 4 / 5) as u64);
+        assert_eq!(
+            pacer.delay(rtt, mtu as u64, mtu, window, now),
+            Some(departure + pace_duration)
+        );
+
+        // Once the deferred burst has departed, tokens accrue as usual
+        assert_eq!(pacer.departure_time(departure), None);
+        assert_eq!(
+            pacer.delay(rtt, mtu as u64, mtu, window, departure + pace_duration),
+            None
+        );
+        assert_eq!(pacer.tokens, pacer.capacity);
+    }
 }
@@ -6,6 +6,7 @@ use tracing::{trace, trace_span};
 
 use super::{spaces::SentPacket, Connection, SentFrames};
 use crate::{
+    congestion::SentDeliveryState,
     frame::{self, Close},
     packet::{Header, LongType, PacketNumber, PartialEncode, SpaceId, FIXED_BIT},
     TransportError, TransportErrorCode,
@@ -65,7 +66,10 @@ impl PacketBuilder {
             )
         } else if sent_with_keys > confidentiality_limit {
             // Confidentiality limited violated and there's nothing we can do
-            conn.kill(TransportError::AEAD_LIMIT_REACHED("confidentiality limit reached").into());
+            conn.kill(
+                now,
+                TransportError::AEAD_LIMIT_REACHED("confidentiality limit reached").into(),
+            );
             return None;
         }
 
@@ -173,6 +177,9 @@ impl PacketBuilder {
         let exact_number = self.exact_number;
         let space_id = self.space;
         let (size, padded) = self.finish(conn, buffer);
+        let stats = conn.stats.handshake.space_mut(space_id);
+        stats.sent_packets += 1;
+        stats.sent_bytes += size as u64;
         let sent = match sent {
             Some(sent) => sent,
             None => return,
@@ -183,6 +190,13 @@ impl PacketBuilder {
             false => 0,
         };
 
+        let delivery = match size {
+            0 => SentDeliveryState::default(),
+            _ => conn
+                .path
+                .delivery_rate
+                .on_sent(now, conn.in_flight.bytes, size.into()),
+        };
         let packet = SentPacket {
             largest_acked: sent.largest_acked,
             time_sent: now,
@@ -190,6 +204,7 @@ impl PacketBuilder {
             ack_eliciting,
             retransmits: sent.retransmits,
             stream_frames: sent.stream_frames,
+            delivery,
         };
 
         conn.in_flight.insert(&packet);
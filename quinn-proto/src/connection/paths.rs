@@ -11,6 +11,8 @@ pub(super) struct PathData {
     pub(super) sending_ecn: bool,
     /// Congestion controller state
     pub(super) congestion: Box<dyn congestion::Controller>,
+    /// Delivery rate sampling state, for the congestion controller
+    pub(super) delivery_rate: congestion::DeliveryRateSampler,
     /// Pacing state
     pub(super) pacing: Pacer,
     pub(super) challenge: Option<u64>,
@@ -30,6 +32,14 @@ pub(super) struct PathData {
     ///
     /// Used in persistent congestion determination.
     pub(super) first_packet_after_rtt_sample: Option<(SpaceId, u64)>,
+    /// Packet reordering threshold learned from spurious losses, or 0
+    pub(super) packet_threshold: u32,
+    /// Time reordering threshold learned from spurious losses as a factor of RTT, or 0
+    pub(super) time_threshold: f32,
+    /// Number of loss episodes begun since a spurious loss was last detected
+    episodes_since_reordering: u32,
+    /// Losses since the congestion controller last responded to loss
+    pub(super) loss_episode: Option<LossEpisode>,
 }
 
 impl PathData {
@@ -50,6 +60,7 @@ impl PathData {
             sending_ecn: true,
             pacing: Pacer::new(initial_rtt, congestion.initial_window(), initial_mtu, now),
             congestion,
+            delivery_rate: congestion::DeliveryRateSampler::new(now),
             challenge: None,
             challenge_pending: false,
             validated,
@@ -59,6 +70,10 @@ impl PathData {
                 MtuDiscovery::new(initial_mtu, min_mtu, peer_max_udp_payload_size, config)
             }),
             first_packet_after_rtt_sample: None,
+            packet_threshold: 0,
+            time_threshold: 0.0,
+            episodes_since_reordering: 0,
+            loss_episode: None,
         }
     }
 
@@ -71,6 +86,7 @@ impl PathData {
             pacing: Pacer::new(smoothed_rtt, congestion.window(), prev.current_mtu(), now),
             sending_ecn: true,
             congestion,
+            delivery_rate: prev.delivery_rate.clone(),
             challenge: None,
             challenge_pending: false,
             validated: false,
@@ -78,6 +94,10 @@ impl PathData {
             total_recvd: 0,
             mtud: prev.mtud.clone(),
             first_packet_after_rtt_sample: prev.first_packet_after_rtt_sample,
+            packet_threshold: prev.packet_threshold,
+            time_threshold: prev.time_threshold,
+            episodes_since_reordering: prev.episodes_since_reordering,
+            loss_episode: prev.loss_episode,
         }
     }
 
@@ -91,6 +111,120 @@ impl PathData {
     pub(super) fn current_mtu(&self) -> u16 {
         self.mtud.current_mtu()
     }
+
+    /// Replace the congestion controller with one built by `factory`, continuing from the current
+    /// congestion window, slow start threshold and recovery period
+    pub(super) fn replace_congestion_controller(
+        &mut self,
+        now: Instant,
+        factory: &dyn congestion::ControllerFactory,
+    ) {
+        let window = self.congestion.window();
+        let ssthresh = self.congestion.ssthresh();
+        let recovery_start = self.congestion.recovery_start();
+        self.congestion = factory.build(now, self.current_mtu());
+        self.congestion.seed(window, ssthresh, recovery_start);
+    }
+
+    /// Record `packets` ack-eliciting packets, the latest of which was sent at `sent`, being
+    /// deemed lost in congestion event `event`, returning the loss episode they belong to
+    pub(super) fn on_packets_lost(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        event: u64,
+        packets: u64,
+    ) -> u64 {
+        match &mut self.loss_episode {
+            // Congestion controllers don't respond again to losses of packets sent before their
+            // last response
+            Some(episode) if sent <= episode.start => {
+                if let Some(unconfirmed) = &mut episode.unconfirmed {
+                    *unconfirmed += packets;
+                }
+                episode.id
+            }
+            _ => {
+                self.loss_episode = Some(LossEpisode {
+                    id: event,
+                    start: now,
+                    unconfirmed: Some(packets),
+                });
+                // Relax the learned thresholds once reordering appears to have subsided
+                self.episodes_since_reordering += 1;
+                if self.episodes_since_reordering >= REORDERING_DECAY_EPISODES {
+                    self.episodes_since_reordering = 0;
+                    self.packet_threshold /= 2;
+                    self.time_threshold /= 2.0;
+                }
+                event
+            }
+        }
+    }
+
+    /// Raise the reordering thresholds to at least `packets` and `time`, which would have avoided
+    /// deeming an acknowledged packet lost
+    pub(super) fn on_reordering(&mut self, packets: u32, time: f32) {
+        self.packet_threshold = self.packet_threshold.max(packets);
+        self.time_threshold = self.time_threshold.max(time);
+        self.episodes_since_reordering = 0;
+    }
+
+    /// Record an ack-eliciting packet deemed lost in `episode` being acknowledged after all,
+    /// returning whether every packet deemed lost in the episode has now been acknowledged
+    pub(super) fn on_spurious_loss(&mut self, episode: u64) -> bool {
+        let unconfirmed = match &mut self.loss_episode {
+            Some(LossEpisode {
+                id,
+                unconfirmed: Some(unconfirmed),
+                ..
+            }) if *id == episode => unconfirmed,
+            _ => return false,
+        };
+        *unconfirmed -= 1;
+        if *unconfirmed != 0 {
+            return false;
+        }
+        self.loss_episode = None;
+        true
+    }
+
+    /// Record the congestion controller responding to ECN in congestion event `event`
+    pub(super) fn on_ecn_congestion(&mut self, now: Instant, event: u64) {
+        // Any later loss episode could only be undone to the state the controller was in before
+        // this response
+        self.loss_episode = Some(LossEpisode {
+            id: event,
+            start: now,
+            unconfirmed: None,
+        });
+    }
+
+    /// Record a packet deemed lost in `episode` having really been lost
+    pub(super) fn on_genuine_loss(&mut self, episode: u64) {
+        if let Some(current) = &mut self.loss_episode {
+            if current.id == episode {
+                current.unconfirmed = None;
+            }
+        }
+    }
+}
+
+/// Number of loss episodes without spurious losses after which the reordering thresholds learned
+/// from them are halved
+const REORDERING_DECAY_EPISODES: u32 = 4;
+
+/// Packets deemed lost since the congestion controller last responded to loss, for detecting
+/// whether that response was spurious
+#[derive(Debug, Copy, Clone)]
+pub(super) struct LossEpisode {
+    /// The congestion event which started the episode
+    id: u64,
+    /// When the congestion controller responded
+    start: Instant,
+    /// Number of ack-eliciting packets deemed lost in the episode which have not been
+    /// acknowledged since, or `None` if any of them is known to really have been lost
+    unconfirmed: Option<u64>,
 }
 
 /// RTT estimation for a particular network path
@@ -107,7 +241,7 @@ pub struct RttEstimator {
 }
 
 impl RttEstimator {
-    fn new(initial_rtt: Duration) -> Self {
+    pub(crate) fn new(initial_rtt: Duration) -> Self {
         Self {
             latest: initial_rtt,
             smoothed: None,
@@ -129,6 +263,11 @@ impl RttEstimator {
         self.get().max(self.latest)
     }
 
+    /// Most recent RTT sample, not adjusted for the peer's ack delay
+    pub fn latest(&self) -> Duration {
+        self.latest
+    }
+
     /// Minimum RTT registered so far for this estimator.
     pub fn min(&self) -> Duration {
         self.min
@@ -23,6 +23,8 @@ pub(super) struct SendBuffer {
     acks: RangeSet,
     /// Previously transmitted ranges deemed lost
     retransmits: RangeSet,
+    /// Largest number of bytes `unacked_segments` may hold, if limited
+    limit: Option<u64>,
 }
 
 impl SendBuffer {
@@ -172,6 +174,11 @@ impl SendBuffer {
         self.offset
     }
 
+    /// First stream offset which hasn't been sent
+    pub(super) fn unsent(&self) -> u64 {
+        self.unsent
+    }
+
     /// Whether all sent data has been acknowledged
     pub(super) fn is_fully_acked(&self) -> bool {
         self.unacked_len == 0
@@ -188,6 +195,26 @@ impl SendBuffer {
     pub(super) fn unacked(&self) -> u64 {
         self.unacked_len as u64 - self.acks.iter().map(|x| x.end - x.start).sum::<u64>()
     }
+
+    pub(super) fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    /// Limit the amount of data which may be buffered to `limit` bytes
+    ///
+    /// Data already buffered is kept regardless.
+    pub(super) fn set_limit(&mut self, limit: Option<u64>) {
+        self.limit = limit;
+    }
+
+    /// Number of bytes which may be written before the limit is reached
+    ///
+    /// Data acknowledged out of order still counts against the limit until everything before it
+    /// has been acknowledged too, since until then it can't be discarded.
+    pub(super) fn headroom(&self) -> u64 {
+        self.limit
+            .map_or(u64::MAX, |x| x.saturating_sub(self.unacked_len as u64))
+    }
 }
 
 #[cfg(test)]
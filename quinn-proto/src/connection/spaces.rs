@@ -10,8 +10,8 @@ use rustc_hash::FxHashSet;
 
 use super::assembler::Assembler;
 use crate::{
-    connection::StreamsState, crypto::Keys, frame, packet::SpaceId, range_set::ArrayRangeSet,
-    shared::IssuedCid, Dir, StreamId, VarInt,
+    congestion::SentDeliveryState, connection::StreamsState, crypto::Keys, frame, packet::SpaceId,
+    range_set::ArrayRangeSet, shared::IssuedCid, Dir, EcnCodepoint, StreamId, VarInt,
 };
 
 pub(super) struct PacketSpace {
@@ -33,6 +33,8 @@ pub(super) struct PacketSpace {
     /// Transmitted but not acked
     // We use a BTreeMap here so we can efficiently query by range on ACK and for loss detection
     pub(super) sent_packets: BTreeMap<u64, SentPacket>,
+    /// Packets deemed lost recently, in case they are acknowledged after all
+    pub(super) lost_packets: BTreeMap<u64, LostPacket>,
     /// Number of explicit congestion notification codepoints seen on incoming packets
     pub(super) ecn_counters: frame::EcnCounts,
     /// Recent ECN counters sent by the peer in ACK frames
@@ -77,6 +79,7 @@ impl PacketSpace {
             largest_acked_packet: None,
             largest_acked_packet_sent: now,
             sent_packets: BTreeMap::new(),
+            lost_packets: BTreeMap::new(),
             ecn_counters: frame::EcnCounts::ZERO,
             ecn_feedback: frame::EcnCounts::ZERO,
 
@@ -146,12 +149,15 @@ impl PacketSpace {
         SendableFrames { acks, other }
     }
 
-    /// Verifies sanity of an ECN block and returns whether congestion was encountered.
+    /// Verifies sanity of an ECN block and returns the number of newly CE-marked packets
+    ///
+    /// `codepoint` is the ECN-capable codepoint that outgoing packets were marked with.
     pub(super) fn detect_ecn(
         &mut self,
         newly_acked: u64,
         ecn: frame::EcnCounts,
-    ) -> Result<bool, &'static str> {
+        codepoint: EcnCodepoint,
+    ) -> Result<u64, &'static str> {
         let ect0_increase = ecn
             .ect0
             .checked_sub(self.ecn_feedback.ect0)
@@ -168,15 +174,19 @@ impl PacketSpace {
         if total_increase < newly_acked {
             return Err("ECN bleaching");
         }
-        if (ect0_increase + ce_increase) < newly_acked || ect1_increase != 0 {
+        let (sent_increase, other_increase) = match codepoint {
+            EcnCodepoint::Ect1 => (ect1_increase, ect0_increase),
+            _ => (ect0_increase, ect1_increase),
+        };
+        if (sent_increase + ce_increase) < newly_acked || other_increase != 0 {
             return Err("ECN corruption");
         }
         // If total_increase > newly_acked (which happens when ACKs are lost), this is required by
         // the draft so that long-term drift does not occur. If =, then the only question is whether
-        // to count CE packets as CE or ECT0. Recording them as CE is more consistent and keeps the
+        // to count CE packets as CE or ECT. Recording them as CE is more consistent and keeps the
         // congestion check obvious.
         self.ecn_feedback = ecn;
-        Ok(ce_increase != 0)
+        Ok(ce_increase)
     }
 
     pub(super) fn sent(&mut self, number: u64, packet: SentPacket) {
@@ -219,6 +229,19 @@ pub(super) struct SentPacket {
     ///
     /// The actual application data is stored with the stream state.
     pub(super) stream_frames: frame::StreamMetaVec,
+    /// Delivery rate sampling state when the packet was sent
+    pub(super) delivery: SentDeliveryState,
+}
+
+/// A packet which was deemed lost
+#[derive(Debug, Copy, Clone)]
+pub(super) struct LostPacket {
+    /// The time the packet was sent
+    pub(super) time_sent: Instant,
+    /// The largest packet number acknowledged when the packet was deemed lost
+    pub(super) largest_acked: u64,
+    /// The congestion event the loss contributed to, if the packet was ack-eliciting
+    pub(super) congestion_event: Option<u64>,
 }
 
 /// Retransmittable data queue
@@ -230,6 +253,9 @@ pub struct Retransmits {
     pub(super) reset_stream: Vec<(StreamId, VarInt)>,
     pub(super) stop_sending: Vec<frame::StopSending>,
     pub(super) max_stream_data: FxHashSet<StreamId>,
+    pub(super) data_blocked: bool,
+    pub(super) stream_data_blocked: FxHashSet<StreamId>,
+    pub(super) streams_blocked: [bool; 2],
     pub(super) crypto: VecDeque<frame::Crypto>,
     pub(super) new_cids: Vec<IssuedCid>,
     pub(super) retire_cids: Vec<u64>,
@@ -246,6 +272,13 @@ impl Retransmits {
                 .max_stream_data
                 .iter()
                 .all(|&id| !streams.can_send_flow_control(id))
+            && !(self.data_blocked && streams.data_blocked())
+            && self
+                .stream_data_blocked
+                .iter()
+                .all(|&id| !streams.stream_data_blocked(id))
+            && !Dir::iter()
+                .any(|dir| self.streams_blocked[dir as usize] && streams.streams_blocked(dir))
             && self.crypto.is_empty()
             && self.new_cids.is_empty()
             && self.retire_cids.is_empty()
@@ -264,6 +297,11 @@ impl ::std::ops::BitOrAssign for Retransmits {
         self.reset_stream.extend_from_slice(&rhs.reset_stream);
         self.stop_sending.extend_from_slice(&rhs.stop_sending);
         self.max_stream_data.extend(&rhs.max_stream_data);
+        self.data_blocked |= rhs.data_blocked;
+        self.stream_data_blocked.extend(&rhs.stream_data_blocked);
+        for dir in Dir::iter() {
+            self.streams_blocked[dir as usize] |= rhs.streams_blocked[dir as usize];
+        }
         for crypto in rhs.crypto.into_iter().rev() {
             self.crypto.push_front(crypto);
         }
@@ -1,7 +1,7 @@
 //! Connection statistics
 
-use crate::{frame::Frame, Dir};
-use std::time::Duration;
+use crate::{frame::Frame, packet::SpaceId, Dir};
+use std::time::{Duration, Instant};
 
 /// Statistics about UDP datagrams transmitted or received on a connection
 #[derive(Default, Debug, Copy, Clone)]
@@ -140,6 +140,72 @@ pub struct PathStats {
     pub lost_plpmtud_probes: u64,
     /// The number of times a black hole was detected in the path
     pub black_holes_detected: u64,
+    /// The amount of packets deemed lost on this path which were later acknowledged (also counted
+    /// by `lost_packets`)
+    pub spurious_lost_packets: u64,
+    /// Congestion events on this path all of whose lost packets were later acknowledged (also
+    /// counted by `congestion_events`)
+    pub spurious_congestion_events: u64,
+}
+
+/// Statistics about the packets of one packet number space
+#[derive(Debug, Default, Copy, Clone)]
+#[non_exhaustive]
+pub struct PacketSpaceStats {
+    /// The amount of packets sent
+    pub sent_packets: u64,
+    /// The amount of bytes sent in packets
+    pub sent_bytes: u64,
+    /// The amount of packets deemed lost, whose contents were retransmitted as needed
+    pub lost_packets: u64,
+    /// The amount of packets received, excluding duplicates and packets which failed to decrypt
+    pub received_packets: u64,
+    /// The amount of bytes received in packets
+    pub received_bytes: u64,
+}
+
+/// Statistics about the handshake of a connection
+///
+/// Times are measured from when the connection was created.
+#[derive(Debug, Default, Copy, Clone)]
+#[non_exhaustive]
+pub struct HandshakeStats {
+    /// Time until the first datagram was received from the peer
+    pub first_byte: Option<Duration>,
+    /// Time until the handshake completed and the connection was established
+    pub completed: Option<Duration>,
+    /// Time until the handshake was confirmed, after which Handshake packets are no longer sent
+    ///
+    /// Servers confirm the handshake as soon as it completes, while clients wait for the server's
+    /// HANDSHAKE_DONE frame.
+    pub confirmed: Option<Duration>,
+    /// The amount of Retry packets which led the client to start over, or which the server sent
+    /// before accepting the connection
+    pub retries: u32,
+    /// Whether 0-RTT data was accepted
+    pub accepted_0rtt: bool,
+    /// Whether the connection was abandoned for exceeding the handshake timeout
+    ///
+    /// Such connections are closed with
+    /// [`ConnectionError::TimedOut`](crate::ConnectionError::TimedOut), like those exceeding the
+    /// idle timeout.
+    pub timed_out: bool,
+    /// Packets of the Initial packet number space
+    pub initial: PacketSpaceStats,
+    /// Packets of the Handshake packet number space
+    pub handshake: PacketSpaceStats,
+    /// Packets of the application data packet number space, including 0-RTT packets
+    pub data: PacketSpaceStats,
+}
+
+impl HandshakeStats {
+    pub(crate) fn space_mut(&mut self, space: SpaceId) -> &mut PacketSpaceStats {
+        match space {
+            SpaceId::Initial => &mut self.initial,
+            SpaceId::Handshake => &mut self.handshake,
+            SpaceId::Data => &mut self.data,
+        }
+    }
 }
 
 /// Connection statistics
@@ -156,4 +222,52 @@ pub struct ConnectionStats {
     pub frame_rx: FrameStats,
     /// Statistics related to the current transmission path
     pub path: PathStats,
+    /// Total time during which stream data was held back by send rate limits
+    pub send_rate_limited: Duration,
+    /// Statistics about the handshake
+    pub handshake: HandshakeStats,
+}
+
+/// Statistics about the sending half of a stream
+///
+/// Times are those of the events processed by the connection, so a blocked write is dated to the
+/// latest event processed before it.
+#[derive(Debug, Default, Copy, Clone)]
+#[non_exhaustive]
+pub struct SendStreamStats {
+    /// Bytes written by the application
+    pub bytes_written: u64,
+    /// Bytes of stream data sent for the first time
+    pub bytes_sent: u64,
+    /// Bytes of stream data sent again after being deemed lost
+    pub bytes_retransmitted: u64,
+    /// Bytes of stream data acknowledged by the peer
+    pub bytes_acked: u64,
+    /// Total time during which writes were blocked by stream-level flow control
+    pub stream_blocked: Duration,
+    /// Total time during which writes were blocked by connection-level flow control or the
+    /// connection's send window
+    pub connection_blocked: Duration,
+    /// Total time during which data waiting to be sent was held back by congestion control or
+    /// pacing
+    pub congestion_blocked: Duration,
+    /// When stream data was first acknowledged
+    pub first_acked: Option<Instant>,
+    /// When stream data was last acknowledged, which for a finished stream is when the last of it
+    /// was
+    pub last_acked: Option<Instant>,
+}
+
+/// Statistics about the receiving half of a stream
+#[derive(Debug, Default, Copy, Clone)]
+#[non_exhaustive]
+pub struct RecvStreamStats {
+    /// The highest offset of stream data received, counting any gaps before it
+    pub max_offset_received: u64,
+    /// Bytes read by the application
+    pub bytes_read: u64,
+    /// When stream data was first received
+    pub first_received: Option<Instant>,
+    /// When stream data was last received
+    pub last_received: Option<Instant>,
 }
@@ -8,7 +8,12 @@ use thiserror::Error;
 use tracing::trace;
 
 use super::spaces::{Retransmits, ThinRetransmits};
-use crate::{frame, Dir, StreamId, VarInt};
+use crate::{
+    frame, ConfigError, Dir, RecvStreamStats, SendStreamStats, StreamId, VarInt, MAX_DSCP,
+};
+
+mod rate_limit;
+use rate_limit::TokenBucket;
 
 mod recv;
 use recv::Recv;
@@ -16,8 +21,11 @@ pub use recv::{Chunks, ReadError, ReadableError};
 
 mod send;
 pub(crate) use send::{ByteSlice, BytesArray};
-pub use send::{BytesSource, FinishError, WriteError, Written};
-use send::{Send, SendState};
+pub use send::{BytesSource, FinishError, SetDscpError, WriteError, Written};
+use send::{FlowControl, Send, SendState};
+
+mod window_tuner;
+use window_tuner::WindowTuner;
 
 mod state;
 #[allow(unreachable_pub)] // fuzzing only
@@ -43,8 +51,8 @@ impl<'a> Streams<'a> {
             return None;
         }
 
-        // TODO: Queue STREAM_ID_BLOCKED if this fails
-        if self.state.next[dir as usize] >= self.state.max[dir as usize] {
+        if self.state.streams_blocked(dir) {
+            self.state.on_streams_blocked(dir);
             return None;
         }
 
@@ -157,6 +165,51 @@ impl<'a> RecvStream<'a> {
 
         Ok(())
     }
+
+    /// Set the number of bytes beyond those read which the peer may send on this stream
+    ///
+    /// Overrides [`TransportConfig::stream_receive_window()`] for this stream, and stops it from
+    /// being auto-tuned. A larger window is announced to the peer right away. Credit which has
+    /// already been issued can't be revoked, so a smaller window only takes effect as the
+    /// application reads. The connection-wide window still applies.
+    ///
+    /// [`TransportConfig::stream_receive_window()`]: crate::TransportConfig::stream_receive_window
+    pub fn set_receive_window(&mut self, window: VarInt) -> Result<(), UnknownStream> {
+        let headroom = self.state.recv_headroom();
+        let stream = self
+            .state
+            .recv
+            .get_mut(&self.id)
+            .ok_or(UnknownStream { _private: () })?;
+
+        if stream
+            .set_receive_window(window.into(), headroom)
+            .should_transmit()
+        {
+            self.pending.max_stream_data.insert(self.id);
+        }
+        Ok(())
+    }
+
+    /// Get the receive window of this stream
+    pub fn receive_window(&self) -> Result<u64, UnknownStream> {
+        let stream = self
+            .state
+            .recv
+            .get(&self.id)
+            .ok_or(UnknownStream { _private: () })?;
+
+        Ok(stream.receive_window())
+    }
+
+    /// Get statistics about this stream
+    ///
+    /// Available until all of the stream's data has been read, or it has been stopped or reset.
+    pub fn stats(&self) -> Result<RecvStreamStats, UnknownStream> {
+        self.state
+            .recv_stats(self.id)
+            .ok_or(UnknownStream { _private: () })
+    }
 }
 
 /// Access to streams
@@ -207,6 +260,8 @@ impl<'a> SendStream<'a> {
         }
 
         let limit = self.state.write_limit();
+        let now = self.state.now;
+        let congestion_blocked = self.state.congestion_blocked_time();
         let stream = self
             .state
             .send
@@ -221,15 +276,33 @@ impl<'a> SendStream<'a> {
                 stream.connection_blocked = true;
                 self.state.connection_blocked.push(self.id);
             }
+            stream.set_blocked(Some(FlowControl::Connection), now);
+            let max_data = self.state.max_data;
+            if self.state.data_blocked() && self.state.data_blocked_reported != Some(max_data) {
+                self.state.data_blocked_reported = Some(max_data);
+                self.pending.data_blocked = true;
+            }
             return Err(WriteError::Blocked);
         }
 
         let was_pending = stream.is_pending();
-        let written = stream.write(source, limit)?;
+        let written = match stream.write(source, limit) {
+            Err(WriteError::Blocked) if stream.offset() == stream.max_data => {
+                stream.set_blocked(Some(FlowControl::Stream), now);
+                if stream.blocked_reported != Some(stream.max_data) {
+                    stream.blocked_reported = Some(stream.max_data);
+                    self.pending.stream_data_blocked.insert(self.id);
+                }
+                return Err(WriteError::Blocked);
+            }
+            result => result?,
+        };
+        stream.set_blocked(None, now);
         self.state.data_sent += written.bytes as u64;
         self.state.unacked_data += written.bytes as u64;
         trace!(stream = %self.id, "wrote {} bytes", written.bytes);
         if !was_pending {
+            stream.start_waiting(congestion_blocked);
             push_pending(&mut self.state.pending, self.id, stream.priority);
         }
         Ok(written)
@@ -249,6 +322,7 @@ impl<'a> SendStream<'a> {
     ///
     /// [`StreamEvent::Finished`]: crate::StreamEvent::Finished
     pub fn finish(&mut self) -> Result<(), FinishError> {
+        let congestion_blocked = self.state.congestion_blocked_time();
         let stream = self
             .state
             .send
@@ -258,6 +332,7 @@ impl<'a> SendStream<'a> {
         let was_pending = stream.is_pending();
         stream.finish()?;
         if !was_pending {
+            stream.start_waiting(congestion_blocked);
             push_pending(&mut self.state.pending, self.id, stream.priority);
         }
 
@@ -269,6 +344,7 @@ impl<'a> SendStream<'a> {
     /// # Panics
     /// - when applied to a receive stream
     pub fn reset(&mut self, error_code: VarInt) -> Result<(), UnknownStream> {
+        let congestion_blocked = self.state.congestion_blocked_time();
         let stream = self
             .state
             .send
@@ -284,6 +360,8 @@ impl<'a> SendStream<'a> {
         // send. We leave flow control alone because the peer's responsible for issuing additional
         // credit based on the final offset communicated in the RESET_STREAM frame we send.
         self.state.unacked_data -= stream.pending.unacked();
+        stream.set_blocked(None, self.state.now);
+        stream.stop_waiting(congestion_blocked);
         stream.reset();
         self.pending.reset_stream.push((self.id, error_code));
 
@@ -319,6 +397,129 @@ impl<'a> SendStream<'a> {
 
         Ok(stream.priority)
     }
+
+    /// Set the DSCP to mark packets carrying data of this stream with
+    ///
+    /// Overrides [`TransportConfig::dscp()`](crate::TransportConfig::dscp) for this stream. Data
+    /// from streams with different DSCPs is never sent in the same packet. Must be less than 64.
+    ///
+    /// # Panics
+    /// - when applied to a receive stream
+    pub fn set_dscp(&mut self, dscp: Option<u8>) -> Result<(), SetDscpError> {
+        if dscp.map_or(false, |x| x > MAX_DSCP) {
+            return Err(ConfigError::OutOfBounds.into());
+        }
+        let stream = self
+            .state
+            .send
+            .get_mut(&self.id)
+            .ok_or(SetDscpError::UnknownStream)?;
+
+        stream.dscp = dscp;
+        Ok(())
+    }
+
+    /// Get the DSCP override of a stream
+    ///
+    /// # Panics
+    /// - when applied to a receive stream
+    pub fn dscp(&self) -> Result<Option<u8>, UnknownStream> {
+        let stream = self
+            .state
+            .send
+            .get(&self.id)
+            .ok_or(UnknownStream { _private: () })?;
+
+        Ok(stream.dscp)
+    }
+
+    /// Limit the rate at which this stream's data is sent to `rate` bytes per second
+    ///
+    /// Applies in addition to the connection's limit set by
+    /// [`Connection::set_send_rate_limit()`](crate::Connection::set_send_rate_limit). Data from
+    /// other streams may be sent while this stream is held back by its limit. A rate of 0 pauses
+    /// transmission of the stream's data. Counts stream payload, including retransmissions, but
+    /// not framing overhead.
+    ///
+    /// # Panics
+    /// - when applied to a receive stream
+    pub fn set_rate_limit(&mut self, rate: Option<u64>) -> Result<(), UnknownStream> {
+        let stream = self
+            .state
+            .send
+            .get_mut(&self.id)
+            .ok_or(UnknownStream { _private: () })?;
+
+        rate_limit::set_rate_limit(&mut stream.rate_limit, rate);
+        Ok(())
+    }
+
+    /// Get the rate limit of a stream, in bytes per second
+    ///
+    /// # Panics
+    /// - when applied to a receive stream
+    pub fn rate_limit(&self) -> Result<Option<u64>, UnknownStream> {
+        let stream = self
+            .state
+            .send
+            .get(&self.id)
+            .ok_or(UnknownStream { _private: () })?;
+
+        Ok(stream.rate_limit.as_ref().map(TokenBucket::rate))
+    }
+
+    /// Limit the amount of unacknowledged data buffered for this stream to `limit` bytes
+    ///
+    /// Writes are blocked while the limit is reached, and the stream becomes writable again as
+    /// the peer acknowledges data. Applies in addition to the connection-wide
+    /// [`TransportConfig::send_window()`](crate::TransportConfig::send_window). Data already
+    /// buffered is kept when the limit is lowered.
+    ///
+    /// # Panics
+    /// - when applied to a receive stream
+    pub fn set_send_buffer_limit(&mut self, limit: Option<u64>) -> Result<(), UnknownStream> {
+        let stream = self
+            .state
+            .send
+            .get_mut(&self.id)
+            .ok_or(UnknownStream { _private: () })?;
+
+        let was_full = stream.pending.headroom() == 0;
+        stream.pending.set_limit(limit);
+        if was_full && stream.pending.headroom() > 0 && stream.is_writable() {
+            self.state
+                .events
+                .push_back(StreamEvent::Writable { id: self.id });
+        }
+        Ok(())
+    }
+
+    /// Get the send buffer limit of a stream, in bytes
+    ///
+    /// # Panics
+    /// - when applied to a receive stream
+    pub fn send_buffer_limit(&self) -> Result<Option<u64>, UnknownStream> {
+        let stream = self
+            .state
+            .send
+            .get(&self.id)
+            .ok_or(UnknownStream { _private: () })?;
+
+        Ok(stream.pending.limit())
+    }
+
+    /// Get statistics about this stream
+    ///
+    /// Remain available after the stream has finished until the connection is polled for events
+    /// again following [`StreamEvent::Finished`].
+    ///
+    /// # Panics
+    /// - when applied to a receive stream
+    pub fn stats(&self) -> Result<SendStreamStats, UnknownStream> {
+        self.state
+            .send_stats(self.id)
+            .ok_or(UnknownStream { _private: () })
+    }
 }
 
 fn push_pending(pending: &mut BinaryHeap<PendingLevel>, id: StreamId, priority: i32) {
@@ -414,6 +615,34 @@ pub enum StreamEvent {
         /// Directionality for which streams are newly available
         dir: Dir,
     },
+    /// The peer reported that it has data to send but is blocked by connection-level flow control
+    ///
+    /// The connection's receive window may be raised with
+    /// [`Connection::set_receive_window()`](crate::Connection::set_receive_window).
+    DataBlocked {
+        /// The connection-level limit at which the peer is blocked
+        limit: u64,
+    },
+    /// The peer reported that it has data to send on a stream but is blocked by stream-level flow
+    /// control
+    ///
+    /// The stream's receive window may be raised with [`RecvStream::set_receive_window()`].
+    StreamDataBlocked {
+        /// Which stream is blocked
+        id: StreamId,
+        /// The stream-level limit at which the peer is blocked
+        limit: u64,
+    },
+    /// The peer reported that it wants to open a stream but is blocked by the stream count limit
+    ///
+    /// The limit may be raised with
+    /// [`Connection::set_max_concurrent_streams()`](crate::Connection::set_max_concurrent_streams).
+    StreamsBlocked {
+        /// Directionality of the streams the peer wants to open
+        dir: Dir,
+        /// The number of streams the peer may open in total
+        limit: u64,
+    },
 }
 
 /// Indicates whether a frame needs to be transmitted
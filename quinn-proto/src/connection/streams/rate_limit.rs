@@ -0,0 +1,134 @@
+use std::time::{Duration, Instant};
+
+/// A token bucket limiting the rate at which stream data is sent
+///
+/// Data may be sent whenever the bucket isn't empty, even if it holds fewer tokens than the data
+/// requires. The bucket then goes into debt, which it pays off before anything more may be sent,
+/// so that the limit holds on average without splitting frames into tiny pieces.
+#[derive(Debug, Copy, Clone)]
+pub(super) struct TokenBucket {
+    /// Bytes per second
+    rate: u64,
+    /// Bytes which may be sent, negative while in debt
+    tokens: f64,
+    /// When `tokens` was last brought up to date, if ever
+    updated: Option<Instant>,
+}
+
+impl TokenBucket {
+    /// Construct a full bucket refilling at `rate` bytes per second
+    pub(super) fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            tokens: burst(rate),
+            updated: None,
+        }
+    }
+
+    pub(super) fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Change the rate at which the bucket refills, keeping any tokens or debt
+    pub(super) fn set_rate(&mut self, rate: u64) {
+        self.rate = rate;
+        self.tokens = self.tokens.min(burst(rate));
+    }
+
+    /// Add the tokens which accrued up to `now`
+    pub(super) fn refill(&mut self, now: Instant) {
+        if let Some(updated) = self.updated {
+            let elapsed = now.saturating_duration_since(updated).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate as f64).min(burst(self.rate));
+        }
+        self.updated = Some(now);
+    }
+
+    /// Whether data may be sent
+    pub(super) fn is_ready(&self) -> bool {
+        self.rate != 0 && self.tokens >= 0.0
+    }
+
+    /// Record `bytes` having been sent
+    pub(super) fn consume(&mut self, bytes: u64) {
+        self.tokens -= bytes as f64;
+    }
+
+    /// When data may next be sent, if it may not be sent now and the rate is non-zero
+    pub(super) fn ready_time(&self) -> Option<Instant> {
+        if self.rate == 0 || self.tokens >= 0.0 {
+            return None;
+        }
+        let updated = self.updated?;
+        // Round up, so that the debt has certainly been paid off by then
+        let micros = (-self.tokens * 1_000_000.0 / self.rate as f64).ceil();
+        Some(updated + Duration::from_micros(micros as u64))
+    }
+}
+
+/// Whether data limited by `bucket`, if any, may be sent
+pub(super) fn is_ready(bucket: &Option<TokenBucket>) -> bool {
+    bucket.as_ref().map_or(true, TokenBucket::is_ready)
+}
+
+/// Limit `bucket` to `rate` bytes per second, or remove the limit
+pub(super) fn set_rate_limit(bucket: &mut Option<TokenBucket>, rate: Option<u64>) {
+    match (bucket.as_mut(), rate) {
+        (Some(bucket), Some(rate)) => bucket.set_rate(rate),
+        (_, rate) => *bucket = rate.map(TokenBucket::new),
+    }
+}
+
+/// Largest number of tokens a bucket refilling at `rate` bytes per second may hold
+fn burst(rate: u64) -> f64 {
+    rate as f64 * BURST_INTERVAL.as_secs_f64()
+}
+
+/// Time over which unused tokens may accumulate
+const BURST_INTERVAL: Duration = Duration::from_millis(10);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pays_off_debt() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(100_000);
+        bucket.refill(now);
+        assert!(bucket.is_ready());
+
+        // The burst of 1000 bytes may be exceeded once
+        bucket.consume(1200);
+        assert!(!bucket.is_ready());
+        assert_eq!(bucket.ready_time(), Some(now + Duration::from_millis(2)));
+        bucket.refill(now + Duration::from_millis(1));
+        assert!(!bucket.is_ready());
+        bucket.refill(now + Duration::from_millis(2));
+        assert!(bucket.is_ready());
+
+        // Idle time accrues no more than a burst
+        bucket.refill(now + Duration::from_secs(1));
+        bucket.consume(1000);
+        assert!(bucket.is_ready());
+        bucket.consume(1);
+        assert!(!bucket.is_ready());
+    }
+
+    #[test]
+    fn zero_rate_blocks() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(1000);
+        bucket.refill(now);
+        bucket.consume(1010);
+        bucket.set_rate(0);
+        assert!(!bucket.is_ready());
+        assert_eq!(bucket.ready_time(), None);
+        bucket.refill(now + Duration::from_secs(1));
+        assert!(!bucket.is_ready());
+
+        // Until the rate is raised, when the debt is paid off as usual
+        bucket.set_rate(1000);
+        assert_eq!(bucket.ready_time(), Some(now + Duration::from_secs(2)));
+    }
+}
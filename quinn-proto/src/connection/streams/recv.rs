@@ -1,20 +1,29 @@
 use std::collections::hash_map::Entry;
 use std::mem;
+use std::time::{Duration, Instant};
 
 use thiserror::Error;
 use tracing::debug;
 
-use super::{Retransmits, ShouldTransmit, StreamHalf, StreamId, StreamsState, UnknownStream};
+use super::{
+    Retransmits, ShouldTransmit, StreamHalf, StreamId, StreamsState, UnknownStream, WindowTuner,
+};
 use crate::connection::assembler::{Assembler, Chunk, IllegalOrderedRead};
-use crate::{frame, TransportError, VarInt};
+use crate::{frame, RecvStreamStats, TransportError, VarInt};
 
 #[derive(Debug, Default)]
 pub(super) struct Recv {
     state: RecvState,
     pub(super) assembler: Assembler,
     sent_max_stream_data: u64,
+    /// Number of bytes beyond those read the peer may send
+    receive_window: u64,
+    /// Grows `receive_window`, unless the application chose a window for this stream
+    window_tuner: Option<WindowTuner>,
     pub(super) end: u64,
     pub(super) stopped: bool,
+    /// When stream data was first and last received
+    received: Option<(Instant, Instant)>,
 }
 
 impl Recv {
@@ -23,8 +32,28 @@ impl Recv {
             state: RecvState::default(),
             assembler: Assembler::new(),
             sent_max_stream_data: initial_max_data,
+            receive_window: initial_max_data,
+            window_tuner: Some(WindowTuner::default()),
             end: 0,
             stopped: false,
+            received: None,
+        }
+    }
+
+    /// Record that stream data was received at `now`
+    pub(super) fn on_data_received(&mut self, now: Option<Instant>) {
+        if let Some(now) = now {
+            let first = self.received.map_or(now, |(first, _)| first);
+            self.received = Some((first, now));
+        }
+    }
+
+    pub(super) fn stats(&self) -> RecvStreamStats {
+        RecvStreamStats {
+            max_offset_received: self.end,
+            bytes_read: self.assembler.bytes_read(),
+            first_received: self.received.map(|(first, _)| first),
+            last_received: self.received.map(|(_, last)| last),
         }
     }
 
@@ -95,8 +124,14 @@ impl Recv {
     /// transmission of the value is recommended. If the boolean value is
     /// `false` the new window should only be transmitted if a previous transmission
     /// had failed.
-    pub(super) fn max_stream_data(&mut self, stream_receive_window: u64) -> (u64, ShouldTransmit) {
-        let max_stream_data = self.assembler.bytes_read() + stream_receive_window;
+    ///
+    /// No more than `headroom` bytes beyond those already received are announced, to stay within
+    /// the endpoint's memory budget.
+    pub(super) fn max_stream_data(&mut self, headroom: u64) -> (u64, ShouldTransmit) {
+        let stream_receive_window = self.receive_window.min(headroom);
+        let max_stream_data = (self.assembler.bytes_read() + self.receive_window)
+            .min(self.end.saturating_add(headroom))
+            .max(self.sent_max_stream_data);
 
         // Only announce a window update if it's significant enough
         // to make it worthwhile sending a MAX_STREAM_DATA frame.
@@ -106,10 +141,46 @@ impl Recv {
         // smaller than `stream_receive_window` in order to make sure the stream
         // does not get stuck.
         let diff = max_stream_data - self.sent_max_stream_data;
-        let transmit = self.receiving_unknown_size() && diff >= (stream_receive_window / 8);
+        let transmit =
+            self.receiving_unknown_size() && diff > 0 && diff >= (stream_receive_window / 8);
         (max_stream_data, ShouldTransmit(transmit))
     }
 
+    /// Whether less credit is announced than the window allows, to leave room for only
+    /// `headroom` more bytes
+    pub(super) fn credit_held_back(&self, headroom: u64) -> bool {
+        self.receiving_unknown_size()
+            && self.end.saturating_add(headroom) < self.assembler.bytes_read() + self.receive_window
+    }
+
+    /// Grow the receive window, up to `max`, if the application reads fast enough to need it
+    pub(super) fn tune_window(&mut self, now: Instant, rtt: Duration, max: u64) {
+        if let Some(ref mut tuner) = self.window_tuner {
+            self.receive_window = tuner.tune(
+                now,
+                rtt,
+                self.assembler.bytes_read(),
+                self.receive_window,
+                max,
+            );
+        }
+    }
+
+    pub(super) fn receive_window(&self) -> u64 {
+        self.receive_window
+    }
+
+    /// Replace the receive window, which is no longer auto-tuned thereafter
+    ///
+    /// Returns whether more credit than already announced may be issued right away, given that no
+    /// more than `headroom` bytes beyond those received may be announced.
+    pub(super) fn set_receive_window(&mut self, window: u64, headroom: u64) -> ShouldTransmit {
+        self.receive_window = window;
+        self.window_tuner = None;
+        let (max_stream_data, _) = self.max_stream_data(headroom);
+        ShouldTransmit(self.receiving_unknown_size() && max_stream_data > self.sent_max_stream_data)
+    }
+
     /// Records that a `MAX_STREAM_DATA` announcing a certain window was sent
     ///
     /// This will suppress enqueuing further `MAX_STREAM_DATA` frames unless
@@ -121,6 +192,11 @@ impl Recv {
         }
     }
 
+    /// The largest `MAX_STREAM_DATA` sent, or the initial limit
+    pub(super) fn sent_max_stream_data(&self) -> u64 {
+        self.sent_max_stream_data
+    }
+
     pub(super) fn receiving_unknown_size(&self) -> bool {
         matches!(self.state, RecvState::Recv { size: None })
     }
@@ -307,7 +383,11 @@ impl<'a> Chunks<'a> {
 
         // If the stream hasn't finished, we may need to issue stream-level flow control credit
         if let ChunksState::Readable(mut rs) = state {
-            let (_, max_stream_data) = rs.max_stream_data(self.streams.stream_receive_window);
+            let headroom = self.streams.recv_headroom();
+            if rs.credit_held_back(headroom) {
+                self.streams.hold_back_credit(headroom);
+            }
+            let (_, max_stream_data) = rs.max_stream_data(headroom);
             should_transmit |= max_stream_data.0;
             if max_stream_data.0 {
                 self.pending.max_stream_data.insert(self.id);
@@ -1,7 +1,10 @@
+use std::time::{Duration, Instant};
+
 use bytes::Bytes;
 use thiserror::Error;
 
-use crate::{connection::send_buffer::SendBuffer, frame, VarInt};
+use super::rate_limit::TokenBucket;
+use crate::{connection::send_buffer::SendBuffer, frame, ConfigError, SendStreamStats, VarInt};
 
 #[derive(Debug)]
 pub(super) struct Send {
@@ -9,12 +12,24 @@ pub(super) struct Send {
     pub(super) state: SendState,
     pub(super) pending: SendBuffer,
     pub(super) priority: i32,
+    /// DSCP overriding the connection's for packets carrying this stream's data
+    pub(super) dscp: Option<u8>,
+    /// Limit on the rate at which this stream's data is sent
+    pub(super) rate_limit: Option<TokenBucket>,
     /// Whether a frame containing a FIN bit must be transmitted, even if we don't have any new data
     pub(super) fin_pending: bool,
     /// Whether this stream is in the `connection_blocked` list of `Streams`
     pub(super) connection_blocked: bool,
+    /// Stream-level flow control limit at which we last reported being blocked
+    pub(super) blocked_reported: Option<u64>,
     /// The reason the peer wants us to stop, if `STOP_SENDING` was received
     pub(super) stop_reason: Option<VarInt>,
+    /// Statistics, less those derived from other state
+    pub(super) stats: SendStreamStats,
+    /// Since when writes have been blocked by flow control, and at which level
+    blocked: Option<(FlowControl, Instant)>,
+    /// The connection's total congestion-blocked time when the stream last began to wait to send
+    waiting_since: Option<Duration>,
 }
 
 impl Send {
@@ -24,10 +39,65 @@ impl Send {
             state: SendState::Ready,
             pending: SendBuffer::new(),
             priority: 0,
+            dscp: None,
+            rate_limit: None,
             fin_pending: false,
             connection_blocked: false,
+            blocked_reported: None,
             stop_reason: None,
+            stats: SendStreamStats::default(),
+            blocked: None,
+            waiting_since: None,
+        }
+    }
+
+    /// Record that writes are blocked by flow control at level `blocked` from `now` on, or no
+    /// longer blocked
+    pub(super) fn set_blocked(&mut self, blocked: Option<FlowControl>, now: Option<Instant>) {
+        let now = match now {
+            Some(x) => x,
+            None => return,
+        };
+        if let Some((level, since)) = self.blocked {
+            if Some(level) == blocked {
+                return;
+            }
+            *level.time(&mut self.stats) += now.saturating_duration_since(since);
+        }
+        self.blocked = blocked.map(|x| (x, now));
+    }
+
+    /// Record that the stream has data waiting to be sent, given the connection's total
+    /// congestion-blocked time so far
+    pub(super) fn start_waiting(&mut self, congestion_blocked: Duration) {
+        if self.waiting_since.is_none() {
+            self.waiting_since = Some(congestion_blocked);
+        }
+    }
+
+    /// Record that the stream no longer has data waiting to be sent
+    pub(super) fn stop_waiting(&mut self, congestion_blocked: Duration) {
+        if let Some(since) = self.waiting_since.take() {
+            self.stats.congestion_blocked += congestion_blocked.saturating_sub(since);
+        }
+    }
+
+    /// Compile statistics as of `now`, given the connection's total congestion-blocked time
+    pub(super) fn stats(
+        &self,
+        now: Option<Instant>,
+        congestion_blocked: Duration,
+    ) -> SendStreamStats {
+        let mut stats = self.stats;
+        stats.bytes_written = self.pending.offset();
+        stats.bytes_acked = self.pending.offset() - self.pending.unacked();
+        if let (Some((level, since)), Some(now)) = (self.blocked, now) {
+            *level.time(&mut stats) += now.saturating_duration_since(since);
         }
+        if let Some(since) = self.waiting_since {
+            stats.congestion_blocked += congestion_blocked.saturating_sub(since);
+        }
+        stats
     }
 
     /// Whether the stream has been reset
@@ -60,7 +130,7 @@ impl Send {
         if let Some(error_code) = self.stop_reason {
             return Err(WriteError::Stopped(error_code));
         }
-        let budget = self.max_data - self.pending.offset();
+        let budget = (self.max_data - self.pending.offset()).min(self.pending.headroom());
         if budget == 0 {
             return Err(WriteError::Blocked);
         }
@@ -143,6 +213,23 @@ impl Send {
     }
 }
 
+/// Level of flow control blocking writes
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) enum FlowControl {
+    Stream,
+    Connection,
+}
+
+impl FlowControl {
+    /// The statistic accounting for time blocked at this level
+    fn time(self, stats: &mut SendStreamStats) -> &mut Duration {
+        match self {
+            Self::Stream => &mut stats.stream_blocked,
+            Self::Connection => &mut stats.connection_blocked,
+        }
+    }
+}
+
 /// A [`BytesSource`] implementation for `&'a mut [Bytes]`
 ///
 /// The type allows to dequeue [`Bytes`] chunks from an array of chunks, up to
@@ -304,6 +391,18 @@ pub enum FinishError {
     UnknownStream,
 }
 
+/// Reasons why attempting to set the DSCP of a stream might fail
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum SetDscpError {
+    /// The DSCP is invalid, as it would be for
+    /// [`TransportConfig::dscp()`](crate::TransportConfig::dscp)
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    /// The stream has not been opened or was already finished or reset
+    #[error("unknown stream")]
+    UnknownStream,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
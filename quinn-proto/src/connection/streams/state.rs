@@ -2,20 +2,24 @@ use std::{
     collections::{binary_heap::PeekMut, hash_map, BinaryHeap, VecDeque},
     convert::TryFrom,
     mem,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use bytes::{BufMut, BytesMut};
 use rustc_hash::FxHashMap;
 use tracing::{debug, trace};
 
+use super::rate_limit::{is_ready, set_rate_limit};
 use super::{
-    push_pending, PendingLevel, Recv, Retransmits, Send, SendState, ShouldTransmit, StreamEvent,
-    StreamHalf, ThinRetransmits,
+    push_pending, FlowControl, PendingLevel, Recv, Retransmits, Send, SendState, ShouldTransmit,
+    StreamEvent, StreamHalf, ThinRetransmits, TokenBucket, WindowTuner,
 };
 use crate::{
     coding::BufMutExt,
-    connection::stats::FrameStats,
+    connection::stats::{FrameStats, RecvStreamStats, SendStreamStats},
     frame::{self, FrameStruct, StreamMetaVec},
+    memory_budget::{MemoryBudget, MemoryUsage},
     transport_parameters::TransportParameters,
     Dir, Side, StreamId, TransportError, VarInt, MAX_STREAM_COUNT,
 };
@@ -55,7 +59,7 @@ pub struct StreamsState {
     /// Streams with outgoing data queued
     pub(super) pending: BinaryHeap<PendingLevel>,
 
-    events: VecDeque<StreamEvent>,
+    pub(super) events: VecDeque<StreamEvent>,
     /// Streams blocked on connection-level flow control or stream window space
     ///
     /// Streams are only added to this list when a write fails.
@@ -81,6 +85,12 @@ pub struct StreamsState {
     pub(super) stream_receive_window: u64,
     /// Whether the corresponding `max_remote` has increased
     max_streams_dirty: [bool; 2],
+    /// Connection-level flow control limit at which we last reported being blocked
+    pub(super) data_blocked_reported: Option<u64>,
+    /// Stream count limits at which we last reported being blocked, per direction
+    streams_blocked_reported: [Option<u64>; 2],
+    /// Whether a `STREAMS_BLOCKED` frame should be sent, per direction
+    streams_blocked_dirty: [bool; 2],
 
     // Pertinent state from the TransportParameters supplied by the peer
     initial_max_stream_data_uni: VarInt,
@@ -89,6 +99,39 @@ pub struct StreamsState {
 
     /// The shrink to be applied to local_max_data when receive_window is shrunk
     receive_window_shrink_debt: u64,
+    /// Total flow control credit issued for data read or discarded by the application
+    data_read: u64,
+    /// Largest `receive_window` may be grown to by auto-tuning
+    max_receive_window: u64,
+    /// Largest a stream's receive window may be grown to by auto-tuning
+    max_stream_receive_window: u64,
+    window_tuner: WindowTuner,
+    /// Share of the endpoint's memory budget, if any
+    memory: Option<MemoryUsage>,
+    /// Receive headroom when flow control credit was last held back by the memory budget, if it
+    /// has been since
+    credit_held_back: Option<u64>,
+
+    /// Limit on the rate at which stream data is sent
+    rate_limit: Option<TokenBucket>,
+    /// Streams with pending data held back by their own rate limit, kept out of `pending` until
+    /// it allows them to send again
+    rate_limited: Vec<StreamId>,
+    /// Since when all pending stream data has been held back by rate limits, if it is
+    rate_limited_since: Option<Instant>,
+    /// Total time pending stream data was held back by rate limits
+    pub(crate) rate_limited_time: Duration,
+
+    /// Time of the latest event processed by the connection, which dates application calls
+    pub(super) now: Option<Instant>,
+    /// Since when the connection has been blocked by congestion control or pacing, if it is
+    congestion_blocked_since: Option<Instant>,
+    /// Total time the connection was blocked by congestion control or pacing before that
+    congestion_blocked_time: Duration,
+    /// Final statistics of send streams which finished, until their `Finished` event is polled
+    finished_stats: FxHashMap<StreamId, SendStreamStats>,
+    /// Final statistics of the stream whose `Finished` event was polled last, until the next poll
+    last_finished: Option<(StreamId, SendStreamStats)>,
 }
 
 impl StreamsState {
@@ -128,10 +171,28 @@ impl StreamsState {
             send_window,
             stream_receive_window: stream_receive_window.into(),
             max_streams_dirty: [false, false],
+            data_blocked_reported: None,
+            streams_blocked_reported: [None, None],
+            streams_blocked_dirty: [false, false],
             initial_max_stream_data_uni: 0u32.into(),
             initial_max_stream_data_bidi_local: 0u32.into(),
             initial_max_stream_data_bidi_remote: 0u32.into(),
             receive_window_shrink_debt: 0,
+            data_read: 0,
+            max_receive_window: receive_window.into(),
+            max_stream_receive_window: stream_receive_window.into(),
+            window_tuner: WindowTuner::default(),
+            memory: None,
+            credit_held_back: None,
+            rate_limit: None,
+            rate_limited: Vec::new(),
+            rate_limited_since: None,
+            rate_limited_time: Duration::ZERO,
+            now: None,
+            congestion_blocked_since: None,
+            congestion_blocked_time: Duration::ZERO,
+            finished_stats: FxHashMap::default(),
+            last_finished: None,
         };
 
         for dir in Dir::iter() {
@@ -224,9 +285,13 @@ impl StreamsState {
             return Ok(ShouldTransmit(false));
         }
 
+        let has_data = !frame.data.is_empty();
         let (new_bytes, closed) =
             rs.ingest(frame, payload_len, self.data_recvd, self.local_max_data)?;
         self.data_recvd = self.data_recvd.saturating_add(new_bytes);
+        if has_data {
+            rs.on_data_received(self.now);
+        }
 
         if !rs.stopped {
             self.on_stream_frame(true, stream);
@@ -310,6 +375,8 @@ impl StreamsState {
         };
 
         if stream.try_stop(error_code) {
+            // Writes now fail rather than block
+            stream.set_blocked(None, self.now);
             self.events
                 .push_back(StreamEvent::Stopped { id, error_code });
             self.on_stream_frame(false, id);
@@ -330,16 +397,83 @@ impl StreamsState {
 
     /// Whether any stream data is queued, regardless of control frames
     pub(crate) fn can_send_stream_data(&self) -> bool {
+        is_ready(&self.rate_limit) && self.any_pending(|s| is_ready(&s.rate_limit))
+    }
+
+    /// Whether any stream which could produce stream frames satisfies `f`
+    fn any_pending(&self, f: impl Fn(&Send) -> bool) -> bool {
         // Reset streams may linger in the pending stream list, but will never produce stream frames
         self.pending.iter().any(|level| {
             level
                 .queue
                 .borrow()
                 .iter()
-                .any(|id| self.send.get(id).map_or(false, |s| !s.is_reset()))
+                .any(|id| self.send.get(id).map_or(false, |s| !s.is_reset() && f(s)))
         })
     }
 
+    /// Limit the rate at which stream data is sent to `rate` bytes per second
+    pub(crate) fn set_rate_limit(&mut self, rate: Option<u64>) {
+        set_rate_limit(&mut self.rate_limit, rate);
+    }
+
+    pub(crate) fn rate_limit(&self) -> Option<u64> {
+        self.rate_limit.as_ref().map(TokenBucket::rate)
+    }
+
+    /// Bring rate limits up to date, returning when to check them again if they are holding back
+    /// stream data
+    ///
+    /// Only the connection's limit and the streams currently held back by their own are examined,
+    /// so this is cheap however many streams are pending.
+    pub(crate) fn update_rate_limits(&mut self, now: Instant) -> Option<Instant> {
+        if let Some(bucket) = &mut self.rate_limit {
+            bucket.refill(now);
+        }
+
+        // Return streams whose limit allows them to send again to the pending queue
+        let mut streams = None;
+        for id in mem::take(&mut self.rate_limited) {
+            let stream = match self.send.get_mut(&id) {
+                Some(s) if !s.is_reset() && s.is_pending() => s,
+                _ => continue,
+            };
+            if let Some(bucket) = &mut stream.rate_limit {
+                bucket.refill(now);
+                if !bucket.is_ready() {
+                    if let Some(time) = bucket.ready_time() {
+                        streams = Some(streams.map_or(time, |x: Instant| x.min(time)));
+                    }
+                    self.rate_limited.push(id);
+                    continue;
+                }
+            }
+            push_pending(&mut self.pending, id, stream.priority);
+        }
+
+        // Streams in the pending queue were ready when last examined
+        let streams_limited = !self.rate_limited.is_empty() && !self.any_pending(|_| true);
+        let connection_limited = !is_ready(&self.rate_limit)
+            && (!self.rate_limited.is_empty() || self.any_pending(|_| true));
+        if let Some(since) = self.rate_limited_since.take() {
+            self.rate_limited_time += now.saturating_duration_since(since);
+        }
+        if !streams_limited && !connection_limited {
+            return None;
+        }
+        self.rate_limited_since = Some(now);
+
+        let connection = match &self.rate_limit {
+            Some(bucket) if connection_limited => Some(bucket.ready_time()?),
+            _ => None,
+        };
+        if streams_limited {
+            // Streams which are never ready don't need to be checked again
+            streams?;
+        }
+        connection.max(streams)
+    }
+
     /// Whether MAX_STREAM_DATA frames could be sent for stream `id`
     pub(crate) fn can_send_flow_control(&self, id: StreamId) -> bool {
         self.recv
@@ -354,6 +488,8 @@ impl StreamsState {
         retransmits: &mut ThinRetransmits,
         stats: &mut FrameStats,
         max_size: usize,
+        now: Instant,
+        rtt: Duration,
     ) {
         // RESET_STREAM
         while buf.len() + frame::ResetStream::SIZE_BOUND < max_size {
@@ -401,11 +537,12 @@ impl StreamsState {
         // MAX_DATA
         if pending.max_data && buf.len() + 9 < max_size {
             pending.max_data = false;
+            self.tune_receive_window(now, rtt);
 
             // `local_max_data` can grow bigger than `VarInt`.
             // For transmission inside QUIC frames we need to clamp it to the
             // maximum allowed `VarInt` size.
-            let max = VarInt::try_from(self.local_max_data).unwrap_or(VarInt::MAX);
+            let max = VarInt::try_from(self.max_data_to_advertise()).unwrap_or(VarInt::MAX);
 
             trace!(value = max.into_inner(), "MAX_DATA");
             if max > self.sent_max_data {
@@ -422,6 +559,7 @@ impl StreamsState {
         }
 
         // MAX_STREAM_DATA
+        let headroom = self.recv_headroom();
         while buf.len() + 17 < max_size {
             let id = match pending.max_stream_data.iter().next() {
                 Some(x) => *x,
@@ -437,7 +575,8 @@ impl StreamsState {
             }
             retransmits.get_or_create().max_stream_data.insert(id);
 
-            let (max, _) = rs.max_stream_data(self.stream_receive_window);
+            rs.tune_window(now, rtt, self.max_stream_receive_window);
+            let (max, _) = rs.max_stream_data(headroom);
             rs.record_sent_max_stream_data(max);
 
             trace!(stream = %id, max = max, "MAX_STREAM_DATA");
@@ -471,14 +610,80 @@ impl StreamsState {
                 Dir::Bi => stats.max_streams_bidi += 1,
             }
         }
+
+        // DATA_BLOCKED
+        if pending.data_blocked && buf.len() + 9 < max_size {
+            pending.data_blocked = false;
+            if self.data_blocked() {
+                trace!(offset = self.max_data, "DATA_BLOCKED");
+                retransmits.get_or_create().data_blocked = true;
+                buf.write(frame::Type::DATA_BLOCKED);
+                buf.write_var(self.max_data);
+                stats.data_blocked += 1;
+            }
+        }
+
+        // STREAM_DATA_BLOCKED
+        while buf.len() + 17 < max_size {
+            let id = match pending.stream_data_blocked.iter().next() {
+                Some(x) => *x,
+                None => break,
+            };
+            pending.stream_data_blocked.remove(&id);
+            if !self.stream_data_blocked(id) {
+                continue;
+            }
+            let offset = self.send[&id].max_data;
+            retransmits.get_or_create().stream_data_blocked.insert(id);
+
+            trace!(stream = %id, offset, "STREAM_DATA_BLOCKED");
+            buf.write(frame::Type::STREAM_DATA_BLOCKED);
+            buf.write(id);
+            buf.write_var(offset);
+            stats.stream_data_blocked += 1;
+        }
+
+        // STREAMS_BLOCKED
+        for dir in Dir::iter() {
+            if !pending.streams_blocked[dir as usize] || buf.len() + 9 >= max_size {
+                continue;
+            }
+
+            pending.streams_blocked[dir as usize] = false;
+            if !self.streams_blocked(dir) {
+                continue;
+            }
+            retransmits.get_or_create().streams_blocked[dir as usize] = true;
+            trace!(
+                value = self.max[dir as usize],
+                "STREAMS_BLOCKED ({:?})",
+                dir
+            );
+            buf.write(match dir {
+                Dir::Uni => frame::Type::STREAMS_BLOCKED_UNI,
+                Dir::Bi => frame::Type::STREAMS_BLOCKED_BIDI,
+            });
+            buf.write_var(self.max[dir as usize]);
+            match dir {
+                Dir::Uni => stats.streams_blocked_uni += 1,
+                Dir::Bi => stats.streams_blocked_bidi += 1,
+            }
+        }
     }
 
+    /// Write frames for pending stream data into `buf`
+    ///
+    /// `dscp` is the connection's DSCP, which applies to streams without an override.
     pub(crate) fn write_stream_frames(
         &mut self,
         buf: &mut BytesMut,
         max_buf_size: usize,
+        dscp: Option<u8>,
     ) -> StreamMetaVec {
         let mut stream_frames = StreamMetaVec::new();
+        // DSCP shared by all streams written into this packet
+        let mut packet_dscp = None;
+        let congestion_blocked = self.congestion_blocked_time();
         while buf.len() + frame::Stream::SIZE_BOUND < max_buf_size {
             if max_buf_size
                 .checked_sub(buf.len() + frame::Stream::SIZE_BOUND)
@@ -487,6 +692,10 @@ impl StreamsState {
                 break;
             }
 
+            if !is_ready(&self.rate_limit) {
+                break;
+            }
+
             let num_levels = self.pending.len();
             let mut level = match self.pending.peek_mut() {
                 Some(x) => x,
@@ -520,17 +729,58 @@ impl StreamsState {
                 continue;
             }
 
+            if let (Some(bucket), Some(now)) = (&mut stream.rate_limit, self.now) {
+                bucket.refill(now);
+            }
+            if !is_ready(&stream.rate_limit) {
+                // Let other streams send in the meantime
+                self.rate_limited.push(id);
+                if level.queue.borrow().is_empty() && num_levels != 1 {
+                    PeekMut::pop(level);
+                }
+                continue;
+            }
+
+            // Streams marked with a different DSCP must be sent in a separate packet
+            let stream_dscp = stream.dscp.or(dscp);
+            match packet_dscp {
+                None => packet_dscp = Some(stream_dscp),
+                Some(dscp) if dscp != stream_dscp => {
+                    level.queue.get_mut().push_front(id);
+                    break;
+                }
+                Some(_) => {}
+            }
+
             // Now that we know the `StreamId`, we can better account for how many bytes
             // are required to encode it.
             let max_buf_size = max_buf_size - buf.len() - 1 - VarInt::size(id.into());
+            let unsent = stream.pending.unsent();
             let (offsets, encode_length) = stream.pending.poll_transmit(max_buf_size);
+            if offsets.start < unsent {
+                stream.stats.bytes_retransmitted += offsets.end - offsets.start;
+            } else {
+                stream.stats.bytes_sent += offsets.end - offsets.start;
+            }
             let fin = offsets.end == stream.pending.offset()
                 && matches!(stream.state, SendState::DataSent { .. });
             if fin {
                 stream.fin_pending = false;
             }
+            for bucket in [&mut self.rate_limit, &mut stream.rate_limit]
+                .into_iter()
+                .flatten()
+            {
+                bucket.consume(offsets.end - offsets.start);
+            }
 
-            if stream.is_pending() {
+            if stream.is_pending() && !is_ready(&stream.rate_limit) {
+                // Held back until `update_rate_limits` finds that its limit allows it to send
+                self.rate_limited.push(id);
+                if level.queue.borrow().is_empty() && num_levels != 1 {
+                    PeekMut::pop(level);
+                }
+            } else if stream.is_pending() {
                 if level.priority == stream.priority {
                     // Enqueue for the same level
                     level.queue.get_mut().push_back(id);
@@ -545,10 +795,13 @@ impl StreamsState {
                     }
                     push_pending(&mut self.pending, id, stream.priority);
                 }
-            } else if level.queue.borrow().is_empty() && num_levels != 1 {
-                // We keep the last level around even in empty form so that
-                // the next insert doesn't have to reallocate the queue
-                PeekMut::pop(level);
+            } else {
+                stream.stop_waiting(congestion_blocked);
+                if level.queue.borrow().is_empty() && num_levels != 1 {
+                    // We keep the last level around even in empty form so that
+                    // the next insert doesn't have to reallocate the queue
+                    PeekMut::pop(level);
+                }
             }
 
             let meta = frame::StreamMeta { id, offsets, fin };
@@ -564,12 +817,37 @@ impl StreamsState {
                 offsets.start += data.len() as u64;
                 buf.put_slice(data);
             }
+
             stream_frames.push(meta);
         }
 
         stream_frames
     }
 
+    /// DSCP of the stream whose data will be sent next
+    ///
+    /// `dscp` is the connection's DSCP, which applies to streams without an override and is
+    /// returned if no stream data can be sent.
+    pub(crate) fn next_dscp(&self, dscp: Option<u8>) -> Option<u8> {
+        if !is_ready(&self.rate_limit) {
+            return dscp;
+        }
+        // Mirror `write_stream_frames`, which serves the highest priority level first and skips
+        // streams which are reset or held back by their rate limit
+        self.pending
+            .iter()
+            .filter_map(|level| {
+                let queue = level.queue.borrow();
+                let stream = queue
+                    .iter()
+                    .filter_map(|id| self.send.get(id))
+                    .find(|stream| !stream.is_reset() && is_ready(&stream.rate_limit))?;
+                Some((level.priority, stream.dscp))
+            })
+            .max_by_key(|&(priority, _)| priority)
+            .map_or(dscp, |(_, stream_dscp)| stream_dscp.or(dscp))
+    }
+
     /// Notify the application that new streams were opened or a stream became readable.
     fn on_stream_frame(&mut self, notify_readable: bool, stream: StreamId) {
         if stream.initiator() == self.side {
@@ -589,6 +867,7 @@ impl StreamsState {
     }
 
     pub(crate) fn received_ack_of(&mut self, frame: frame::StreamMeta) {
+        let congestion_blocked = self.congestion_blocked_time();
         let mut entry = match self.send.entry(frame.id) {
             hash_map::Entry::Vacant(_) => return,
             hash_map::Entry::Occupied(e) => e,
@@ -600,23 +879,37 @@ impl StreamsState {
         }
         let id = frame.id;
         self.unacked_data -= frame.offsets.end - frame.offsets.start;
+        if frame.offsets.start != frame.offsets.end {
+            if let Some(now) = self.now {
+                stream.stats.first_acked.get_or_insert(now);
+                stream.stats.last_acked = Some(now);
+            }
+        }
+        let was_full = stream.pending.headroom() == 0;
         if !stream.ack(frame) {
             // The stream is unfinished or may still need retransmits
+            if was_full && stream.pending.headroom() > 0 && stream.is_writable() {
+                self.events.push_back(StreamEvent::Writable { id });
+            }
             return;
         }
 
+        let stats = stream.stats(self.now, congestion_blocked);
+        self.finished_stats.insert(id, stats);
         entry.remove_entry();
         self.stream_freed(id, StreamHalf::Send);
         self.events.push_back(StreamEvent::Finished { id });
     }
 
     pub(crate) fn retransmit(&mut self, frame: frame::StreamMeta) {
+        let congestion_blocked = self.congestion_blocked_time();
         let stream = match self.send.get_mut(&frame.id) {
             // Loss of data on a closed stream is a noop
             None => return,
             Some(x) => x,
         };
         if !stream.is_pending() {
+            stream.start_waiting(congestion_blocked);
             push_pending(&mut self.pending, frame.id, stream.priority);
         }
         stream.fin_pending |= frame.fin;
@@ -624,6 +917,7 @@ impl StreamsState {
     }
 
     pub(crate) fn retransmit_all_for_0rtt(&mut self) {
+        let congestion_blocked = self.congestion_blocked_time();
         for dir in Dir::iter() {
             for index in 0..self.next[dir as usize] {
                 let id = StreamId::new(Side::Client, dir, index);
@@ -634,6 +928,7 @@ impl StreamsState {
                     continue;
                 }
                 if !stream.is_pending() {
+                    stream.start_waiting(congestion_blocked);
                     push_pending(&mut self.pending, id, stream.priority);
                 }
                 stream.pending.retransmit_all_for_0rtt();
@@ -661,6 +956,35 @@ impl StreamsState {
         Ok(())
     }
 
+    /// Handle the peer reporting that it is blocked by connection-level flow control at `offset`
+    ///
+    /// Reports which have been overtaken by a larger limit are ignored.
+    pub(crate) fn received_data_blocked(&mut self, offset: u64) {
+        if offset >= self.sent_max_data.into_inner() {
+            self.events
+                .push_back(StreamEvent::DataBlocked { limit: offset });
+        }
+    }
+
+    /// Handle the peer reporting that it is blocked by stream-level flow control at `offset`
+    pub(crate) fn received_stream_data_blocked(&mut self, id: StreamId, offset: u64) {
+        let blocked = self.recv.get(&id).map_or(false, |rs| {
+            rs.receiving_unknown_size() && offset >= rs.sent_max_stream_data()
+        });
+        if blocked {
+            self.events
+                .push_back(StreamEvent::StreamDataBlocked { id, limit: offset });
+        }
+    }
+
+    /// Handle the peer reporting that it is unable to open more than `limit` streams
+    pub(crate) fn received_streams_blocked(&mut self, dir: Dir, limit: u64) {
+        if limit >= self.max_remote[dir as usize] {
+            self.events
+                .push_back(StreamEvent::StreamsBlocked { dir, limit });
+        }
+    }
+
     /// Handle increase to connection-level flow control limit
     pub(crate) fn received_max_data(&mut self, n: VarInt) {
         self.max_data = self.max_data.max(n.into());
@@ -682,6 +1006,7 @@ impl StreamsState {
         if let Some(ss) = self.send.get_mut(&id) {
             if ss.increase_max_data(offset) {
                 if write_limit > 0 {
+                    ss.set_blocked(None, self.now);
                     self.events.push_back(StreamEvent::Writable { id });
                 } else if !ss.connection_blocked {
                     // The stream is still blocked on the connection flow control
@@ -690,6 +1015,9 @@ impl StreamsState {
                     ss.connection_blocked = true;
                     self.connection_blocked.push(id);
                 }
+                if write_limit == 0 {
+                    ss.set_blocked(Some(FlowControl::Connection), self.now);
+                }
             }
         } else if id.initiator() == self.side && self.is_local_unopened(id) {
             debug!("got MAX_STREAM_DATA on unopened {}", id);
@@ -704,11 +1032,14 @@ impl StreamsState {
 
     /// Returns the maximum amount of data this is allowed to be written on the connection
     pub(crate) fn write_limit(&self) -> u64 {
-        (self.max_data - self.data_sent).min(self.send_window - self.unacked_data)
+        (self.max_data - self.data_sent)
+            .min(self.send_window - self.unacked_data)
+            .min(self.send_headroom())
     }
 
     /// Yield stream events
     pub(crate) fn poll(&mut self) -> Option<StreamEvent> {
+        self.last_finished = None;
         if let Some(dir) = Dir::iter().find(|&i| mem::replace(&mut self.opened[i as usize], false))
         {
             return Some(StreamEvent::Opened { dir });
@@ -727,18 +1058,97 @@ impl StreamsState {
                 // If it's no longer sensible to write to a stream (even to detect an error) then don't
                 // report it.
                 if stream.is_writable() && stream.max_data > stream.offset() {
+                    stream.set_blocked(None, self.now);
                     return Some(StreamEvent::Writable { id });
+                } else if stream.is_writable() {
+                    stream.set_blocked(Some(FlowControl::Stream), self.now);
                 }
             }
         }
 
-        self.events.pop_front()
+        let event = self.events.pop_front();
+        if let Some(StreamEvent::Finished { id }) = event {
+            self.last_finished = self.finished_stats.remove(&id).map(|stats| (id, stats));
+        }
+        event
+    }
+
+    /// Date application calls until the next event with `now`
+    pub(crate) fn set_time(&mut self, now: Instant) {
+        self.now = Some(now);
+    }
+
+    /// Record whether the connection is blocked by congestion control or pacing as of `now`
+    pub(crate) fn set_congestion_blocked(&mut self, blocked: bool, now: Instant) {
+        match (self.congestion_blocked_since, blocked) {
+            (None, true) => self.congestion_blocked_since = Some(now),
+            (Some(since), false) => {
+                self.congestion_blocked_time += now.saturating_duration_since(since);
+                self.congestion_blocked_since = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Total time the connection has been blocked by congestion control or pacing
+    pub(super) fn congestion_blocked_time(&self) -> Duration {
+        match (self.congestion_blocked_since, self.now) {
+            (Some(since), Some(now)) => {
+                self.congestion_blocked_time + now.saturating_duration_since(since)
+            }
+            _ => self.congestion_blocked_time,
+        }
+    }
+
+    /// Statistics of a send stream, including one whose `Finished` event was just polled
+    pub(super) fn send_stats(&self, id: StreamId) -> Option<SendStreamStats> {
+        match self.send.get(&id) {
+            Some(stream) => Some(stream.stats(self.now, self.congestion_blocked_time())),
+            None => self
+                .last_finished
+                .filter(|&(finished, _)| finished == id)
+                .map(|(_, stats)| stats),
+        }
+    }
+
+    pub(super) fn recv_stats(&self, id: StreamId) -> Option<RecvStreamStats> {
+        self.recv.get(&id).map(Recv::stats)
     }
 
     pub(crate) fn take_max_streams_dirty(&mut self, dir: Dir) -> bool {
         mem::replace(&mut self.max_streams_dirty[dir as usize], false)
     }
 
+    /// Record that opening a stream in direction `dir` failed for lack of credit
+    pub(super) fn on_streams_blocked(&mut self, dir: Dir) {
+        let limit = self.max[dir as usize];
+        if self.streams_blocked_reported[dir as usize] != Some(limit) {
+            self.streams_blocked_reported[dir as usize] = Some(limit);
+            self.streams_blocked_dirty[dir as usize] = true;
+        }
+    }
+
+    pub(crate) fn take_streams_blocked_dirty(&mut self, dir: Dir) -> bool {
+        mem::replace(&mut self.streams_blocked_dirty[dir as usize], false)
+    }
+
+    /// Whether writes are blocked by connection-level flow control
+    pub(in crate::connection) fn data_blocked(&self) -> bool {
+        self.data_sent >= self.max_data
+    }
+
+    /// Whether writes to stream `id` are blocked by stream-level flow control
+    pub(in crate::connection) fn stream_data_blocked(&self, id: StreamId) -> bool {
+        self.send.get(&id).map_or(false, |s| {
+            matches!(s.state, SendState::Ready) && s.offset() >= s.max_data
+        })
+    }
+
+    /// Whether opening streams in direction `dir` is blocked by the stream count limit
+    pub(in crate::connection) fn streams_blocked(&self, dir: Dir) -> bool {
+        self.next[dir as usize] >= self.max[dir as usize]
+    }
+
     /// Check for errors entailed by the peer's use of `id` as a send stream
     fn validate_receive_id(&mut self, id: StreamId) -> Result<(), TransportError> {
         if self.side == id.initiator() {
@@ -797,6 +1207,94 @@ impl StreamsState {
         expanded
     }
 
+    /// Allow receive windows to be grown by auto-tuning, up to the given maximums
+    pub(crate) fn set_max_receive_windows(&mut self, window: VarInt, stream_window: VarInt) {
+        self.max_receive_window = window.into();
+        self.max_stream_receive_window = stream_window.into();
+    }
+
+    /// Account for buffered stream data in the endpoint's memory budget
+    pub(crate) fn set_memory_budget(&mut self, budget: Arc<MemoryBudget>) {
+        self.memory = Some(MemoryUsage::new(budget));
+        self.update_memory_usage();
+    }
+
+    /// Report the stream data currently buffered to the endpoint's memory budget
+    ///
+    /// Returns whether flow control credit previously held back by the budget may now be issued.
+    pub(crate) fn update_memory_usage(&mut self) -> bool {
+        let buffered = self.unacked_data + self.recv_buffered();
+        if let Some(ref mut memory) = self.memory {
+            memory.update(buffered);
+        }
+        match self.credit_held_back {
+            Some(headroom) if self.recv_headroom() > headroom => {
+                self.credit_held_back = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Queue flow control credit updates for any which the memory budget no longer holds back
+    pub(crate) fn queue_held_back_credit(&mut self, pending: &mut Retransmits) {
+        let headroom = self.recv_headroom();
+        pending.max_data |= self.max_data_to_advertise() > self.sent_max_data.into_inner();
+        for (&id, rs) in self.recv.iter_mut() {
+            if rs.max_stream_data(headroom).1 .0 {
+                pending.max_stream_data.insert(id);
+            }
+        }
+    }
+
+    /// Bytes received from the peer which haven't been read or discarded
+    fn recv_buffered(&self) -> u64 {
+        self.data_recvd.saturating_sub(self.data_read)
+    }
+
+    /// Bytes which may be written before the memory budget is exhausted
+    fn send_headroom(&self) -> u64 {
+        self.memory.as_ref().map_or(u64::MAX, |memory| {
+            memory.allowance().saturating_sub(self.unacked_data)
+        })
+    }
+
+    /// Bytes the peer may be allowed to send before the memory budget is exhausted
+    pub(super) fn recv_headroom(&self) -> u64 {
+        self.memory.as_ref().map_or(u64::MAX, |memory| {
+            memory.allowance().saturating_sub(self.recv_buffered())
+        })
+    }
+
+    /// Record that the memory budget, leaving room for `headroom` more bytes, held back credit
+    pub(super) fn hold_back_credit(&mut self, headroom: u64) {
+        self.credit_held_back = Some(self.credit_held_back.map_or(headroom, |x| x.min(headroom)));
+    }
+
+    /// Limit on incoming data to advertise in `MAX_DATA` frames, within the memory budget
+    fn max_data_to_advertise(&self) -> u64 {
+        self.local_max_data
+            .min(self.data_recvd.saturating_add(self.recv_headroom()))
+            .max(self.sent_max_data.into_inner())
+    }
+
+    /// Grow the connection's receive window if the application reads fast enough to need it
+    fn tune_receive_window(&mut self, now: Instant, rtt: Duration) {
+        let window = self.window_tuner.tune(
+            now,
+            rtt,
+            self.data_read,
+            self.receive_window,
+            self.max_receive_window,
+        );
+        if window > self.receive_window {
+            self.local_max_data = self
+                .local_max_data
+                .saturating_add(window - self.receive_window);
+            self.receive_window = window;
+        }
+    }
+
     pub(super) fn insert(&mut self, remote: bool, id: StreamId) {
         let bi = id.dir() == Dir::Bi;
         if bi || !remote {
@@ -827,6 +1325,7 @@ impl StreamsState {
     /// suppress sending further updates until the window increases significantly
     /// again.
     pub(super) fn add_read_credits(&mut self, credits: u64) -> ShouldTransmit {
+        self.data_read += credits;
         if credits > self.receive_window_shrink_debt {
             let net_credits = credits - self.receive_window_shrink_debt;
             self.local_max_data = self.local_max_data.saturating_add(net_credits);
@@ -839,13 +1338,19 @@ impl StreamsState {
             return ShouldTransmit(false);
         }
 
+        let headroom = self.recv_headroom();
+        let max_data = self.max_data_to_advertise();
+        if max_data < self.local_max_data {
+            self.hold_back_credit(headroom);
+        }
+
         // Only announce a window update if it's significant enough
         // to make it worthwhile sending a MAX_DATA frame.
         // We use a fraction of the configured connection receive window to make
         // the decision, to accommodate for connection using bigger windows requiring
         // less updates.
-        let diff = self.local_max_data - self.sent_max_data.into_inner();
-        ShouldTransmit(diff >= (self.receive_window / 8))
+        let diff = max_data - self.sent_max_data.into_inner();
+        ShouldTransmit(diff > 0 && diff >= (self.receive_window.min(headroom) / 8))
     }
 
     /// Update counters for removal of a stream
@@ -1267,7 +1772,7 @@ mod tests {
         high.write(b"high").unwrap();
 
         let mut buf = BytesMut::with_capacity(40);
-        let meta = server.write_stream_frames(&mut buf, 40);
+        let meta = server.write_stream_frames(&mut buf, 40, None);
         assert_eq!(meta[0].id, id_high);
         assert_eq!(meta[1].id, id_mid);
         assert_eq!(meta[2].id, id_low);
@@ -1326,7 +1831,7 @@ mod tests {
         high.set_priority(-1).unwrap();
 
         let mut buf = BytesMut::with_capacity(1000);
-        let meta = server.write_stream_frames(&mut buf, 40);
+        let meta = server.write_stream_frames(&mut buf, 40, None);
         assert_eq!(meta.len(), 1);
         assert_eq!(meta[0].id, id_high);
 
@@ -1334,7 +1839,7 @@ mod tests {
         assert_eq!(server.pending.len(), 2);
 
         // Send the remaining data. The initial mid priority one should go first now
-        let meta = server.write_stream_frames(&mut buf, 1000);
+        let meta = server.write_stream_frames(&mut buf, 1000, None);
         assert_eq!(meta.len(), 2);
         assert_eq!(meta[0].id, id_mid);
         assert_eq!(meta[1].id, id_high);
@@ -1669,4 +2174,46 @@ mod tests {
         assert_eq!(server.local_max_data, expected_local_max_data);
         assert!(should_transmit.should_transmit());
     }
+
+    #[test]
+    fn peer_blocked() {
+        let mut client = make(Side::Client);
+        let id = StreamId::new(Side::Server, Dir::Uni, 0);
+        let _ = client
+            .received(
+                frame::Stream {
+                    id,
+                    offset: 0,
+                    fin: false,
+                    data: Bytes::from_static(&[0; 10]),
+                },
+                10,
+            )
+            .unwrap();
+        assert_eq!(client.poll(), Some(StreamEvent::Opened { dir: Dir::Uni }));
+
+        let limit = 1024 * 1024;
+        client.received_data_blocked(limit);
+        client.received_stream_data_blocked(id, limit);
+        client.received_streams_blocked(Dir::Uni, 128);
+        assert_eq!(client.poll(), Some(StreamEvent::DataBlocked { limit }));
+        assert_eq!(
+            client.poll(),
+            Some(StreamEvent::StreamDataBlocked { id, limit })
+        );
+        assert_eq!(
+            client.poll(),
+            Some(StreamEvent::StreamsBlocked {
+                dir: Dir::Uni,
+                limit: 128
+            })
+        );
+
+        // Reports of limits which have since been raised, or about unknown streams, are ignored
+        client.received_data_blocked(limit - 1);
+        client.received_stream_data_blocked(id, limit - 1);
+        client.received_stream_data_blocked(StreamId::new(Side::Client, Dir::Bi, 0), limit);
+        client.received_streams_blocked(Dir::Uni, 127);
+        assert_eq!(client.poll(), None);
+    }
 }
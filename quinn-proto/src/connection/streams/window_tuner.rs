@@ -0,0 +1,84 @@
+use std::time::{Duration, Instant};
+
+/// Grows a receive window to keep up with the rate at which the application reads
+///
+/// The read rate is measured over intervals of at least a round trip, between window updates.
+/// Multiplied by the round-trip time it gives the bandwidth-delay product of the data the
+/// application is able to consume, and the window is grown to four times that. A sender limited by
+/// the window can deliver only about half of it per round trip, since each update takes time to
+/// arrive, so the margin both reveals when the window is what limits throughput and doubles the
+/// window in response. Windows never shrink.
+#[derive(Debug, Default, Copy, Clone)]
+pub(super) struct WindowTuner {
+    /// When the current measurement interval started, and how much had been read by then
+    epoch: Option<(Instant, u64)>,
+}
+
+impl WindowTuner {
+    /// Compute the window to advertise, given that `read` bytes have been read in total
+    ///
+    /// `window` is the current window, and `max` the largest it may grow to.
+    pub(super) fn tune(
+        &mut self,
+        now: Instant,
+        rtt: Duration,
+        read: u64,
+        window: u64,
+        max: u64,
+    ) -> u64 {
+        if window >= max {
+            return window;
+        }
+        let (start, start_read) = match self.epoch {
+            Some(x) => x,
+            None => {
+                self.epoch = Some((now, read));
+                return window;
+            }
+        };
+        let elapsed = now.saturating_duration_since(start);
+        if elapsed.is_zero() || elapsed < rtt {
+            return window;
+        }
+
+        self.epoch = Some((now, read));
+        let bdp = (read - start_read) as u128 * rtt.as_nanos() / elapsed.as_nanos();
+        let target = u64::try_from(4 * bdp).unwrap_or(u64::MAX);
+        window.max(target.min(max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RTT: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn grows_with_read_rate() {
+        let now = Instant::now();
+        let mut tuner = WindowTuner::default();
+        assert_eq!(tuner.tune(now, RTT, 0, 1000, 1_000_000), 1000);
+        // Measurements shorter than a round trip are ignored
+        assert_eq!(tuner.tune(now + RTT / 2, RTT, 1000, 1000, 1_000_000), 1000);
+
+        // 2000 bytes per round trip calls for four times as much window
+        let now = now + 2 * RTT;
+        assert_eq!(tuner.tune(now, RTT, 4000, 1000, 1_000_000), 8000);
+        // But never more than the maximum
+        assert_eq!(tuner.tune(now + RTT, RTT, 104_000, 8000, 100_000), 100_000);
+    }
+
+    #[test]
+    fn never_shrinks() {
+        let now = Instant::now();
+        let mut tuner = WindowTuner::default();
+        tuner.tune(now, RTT, 0, 10_000, 1_000_000);
+        assert_eq!(tuner.tune(now + RTT, RTT, 100, 10_000, 1_000_000), 10_000);
+        // Nor grows past a maximum below the current window
+        assert_eq!(
+            tuner.tune(now + 2 * RTT, RTT, 100_100, 10_000, 5000),
+            10_000
+        );
+    }
+}
@@ -22,7 +22,9 @@ use crate::{
     connection::{Connection, ConnectionError},
     crypto::{self, Keys, UnsupportedVersion},
     frame,
+    memory_budget::MemoryBudget,
     packet::{Header, Packet, PacketDecodeError, PacketNumber, PartialDecode},
+    path_cache::PathCache,
     shared::{
         ConnectionEvent, ConnectionEventInner, ConnectionId, EcnCodepoint, EndpointEvent,
         EndpointEventInner, IssuedCid,
@@ -45,6 +47,11 @@ pub struct Endpoint {
     server_config: Option<Arc<ServerConfig>>,
     /// Whether the underlying UDP socket promises not to fragment packets
     allow_mtud: bool,
+    /// Path properties learned by previous connections
+    path_cache: PathCache,
+    /// Stream data buffered by all connections
+    memory_budget: Arc<MemoryBudget>,
+    stats: EndpointStats,
 }
 
 impl Endpoint {
@@ -63,6 +70,9 @@ impl Endpoint {
             index: ConnectionIndex::default(),
             connections: Slab::new(),
             local_cid_generator: (config.connection_id_generator_factory.as_ref())(),
+            path_cache: PathCache::new(config.path_cache_capacity, config.path_cache_lifetime),
+            memory_budget: Arc::new(MemoryBudget::new(config.stream_memory_budget)),
+            stats: EndpointStats::default(),
             config,
             server_config,
             allow_mtud,
@@ -108,6 +118,9 @@ impl Endpoint {
                 let conn = self.connections.remove(ch.0);
                 self.index.remove(&conn);
             }
+            PathLearned(now, remote, properties) => {
+                self.path_cache.insert(now, remote, properties);
+            }
         }
         None
     }
@@ -136,9 +149,11 @@ impl Endpoint {
             }) => {
                 if self.server_config.is_none() {
                     debug!("dropping packet with unsupported version");
+                    self.stats.unsupported_versions += 1;
                     return None;
                 }
                 trace!("sending version negotiation");
+                self.stats.version_negotiations_sent += 1;
                 // Negotiate versions
                 let mut buf = BytesMut::new();
                 Header::VersionNegotiate {
@@ -159,13 +174,16 @@ impl Endpoint {
                 return Some(DatagramEvent::Response(Transmit {
                     destination: remote,
                     ecn: None,
+                    dscp: None,
                     contents: buf.freeze(),
                     segment_size: None,
                     src_ip: local_ip,
+                    departure_time: None,
                 }));
             }
             Err(e) => {
                 trace!("malformed header: {}", e);
+                self.stats.malformed_headers += 1;
                 return None;
             }
         };
@@ -206,6 +224,7 @@ impl Endpoint {
         if let Some(version) = first_decode.initial_version() {
             if datagram_len < MIN_INITIAL_SIZE as usize {
                 debug!("ignoring short initial for connection {}", dst_cid);
+                self.stats.short_initials += 1;
                 return None;
             }
 
@@ -221,6 +240,7 @@ impl Endpoint {
                         "ignoring initial packet version {:#x} unsupported by cryptographic layer",
                         version
                     );
+                    self.stats.unsupported_crypto_versions += 1;
                     return None;
                 }
             };
@@ -230,6 +250,7 @@ impl Endpoint {
                 }
                 Err(e) => {
                     trace!("unable to decode initial packet: {}", e);
+                    self.stats.initial_decryption_failures += 1;
                     None
                 }
             };
@@ -238,6 +259,7 @@ impl Endpoint {
                 "ignoring non-initial packet for unknown connection {}",
                 dst_cid
             );
+            self.stats.unknown_connection += 1;
             return None;
         }
 
@@ -252,9 +274,43 @@ impl Endpoint {
         }
 
         trace!("dropping unrecognized short packet without ID");
+        self.stats.unknown_connection += 1;
         None
     }
 
+    /// Process a report that a datagram sent to `remote` exceeded the path MTU
+    ///
+    /// `max_udp_payload_size` is the largest UDP payload the path reportedly supports, and `quoted`
+    /// the start of the offending datagram, as quoted by an ICMP "fragmentation needed" or ICMPv6
+    /// "packet too big" message. Such messages are easily spoofed, so each connection using the
+    /// path checks that the quoted datagram is one it might have sent before taking the report into
+    /// account.
+    pub fn handle_path_mtu_error(
+        &mut self,
+        now: Instant,
+        remote: SocketAddr,
+        max_udp_payload_size: u16,
+        quoted: &[u8],
+    ) -> Vec<(ConnectionHandle, ConnectionEvent)> {
+        let quoted = Bytes::copy_from_slice(quoted);
+        self.connections
+            .iter()
+            .filter(|(_, meta)| {
+                meta.addresses.remote == remote
+                    || meta.reset_token.map_or(false, |(addr, _)| addr == remote)
+            })
+            .map(|(ch, _)| {
+                let event = ConnectionEvent(ConnectionEventInner::PathMtuError {
+                    now,
+                    remote,
+                    max_udp_payload_size,
+                    quoted: quoted.clone(),
+                });
+                (ConnectionHandle(ch), event)
+            })
+            .collect()
+    }
+
     fn stateless_reset(
         &mut self,
         inciting_dgram_len: usize,
@@ -270,9 +326,11 @@ impl Endpoint {
             Some(headroom) if headroom > MIN_PADDING_LEN => headroom - 1,
             _ => {
                 debug!("ignoring unexpected {} byte packet: not larger than minimum stateless reset size", inciting_dgram_len);
+                self.stats.unknown_connection += 1;
                 return None;
             }
         };
+        self.stats.stateless_resets_sent += 1;
 
         debug!(
             "sending stateless reset for {} to {}",
@@ -297,9 +355,11 @@ impl Endpoint {
         Some(Transmit {
             destination: addresses.remote,
             ecn: None,
+            dscp: None,
             contents: buf.freeze(),
             segment_size: None,
             src_ip: addresses.local_ip,
+            departure_time: None,
         })
     }
 
@@ -417,11 +477,13 @@ impl Endpoint {
             .is_err()
         {
             debug!(packet_number, "failed to authenticate initial packet");
+            self.stats.initial_decryption_failures += 1;
             return None;
         };
 
         if !packet.reserved_bits_valid() {
             debug!("dropping connection attempt with invalid reserved bits");
+            self.stats.invalid_reserved_bits += 1;
             return None;
         }
 
@@ -430,6 +492,7 @@ impl Endpoint {
         if self.connections.len() >= server_config.concurrent_connections as usize || self.is_full()
         {
             debug!("refusing connection");
+            self.stats.handshakes_refused += 1;
             return Some(DatagramEvent::Response(self.initial_close(
                 version,
                 addresses,
@@ -446,6 +509,7 @@ impl Endpoint {
                 "rejecting connection due to invalid DCID length {}",
                 dst_cid.len()
             );
+            self.stats.invalid_initial_cids += 1;
             return Some(DatagramEvent::Response(self.initial_close(
                 version,
                 addresses,
@@ -486,12 +550,15 @@ impl Endpoint {
                 buf.extend_from_slice(&server_config.crypto.retry_tag(version, &dst_cid, &buf));
                 encode.finish(&mut buf, &*crypto.header.local, None);
 
+                self.stats.retries_sent += 1;
                 return Some(DatagramEvent::Response(Transmit {
                     destination: addresses.remote,
                     ecn: None,
+                    dscp: None,
                     contents: buf.freeze(),
                     segment_size: None,
                     src_ip: addresses.local_ip,
+                    departure_time: None,
                 }));
             }
 
@@ -508,6 +575,7 @@ impl Endpoint {
                 }
                 _ => {
                     debug!("rejecting invalid stateless retry token");
+                    self.stats.invalid_retry_tokens += 1;
                     return Some(DatagramEvent::Response(self.initial_close(
                         version,
                         addresses,
@@ -554,10 +622,12 @@ impl Endpoint {
         match conn.handle_first_packet(now, addresses.remote, ecn, packet_number, packet, rest) {
             Ok(()) => {
                 trace!(id = ch.0, icid = %dst_cid, "connection incoming");
+                self.stats.handshakes_accepted += 1;
                 Some(DatagramEvent::NewConnection(ch, conn))
             }
             Err(e) => {
                 debug!("handshake failed: {}", e);
+                self.stats.handshakes_failed += 1;
                 self.handle_event(ch, EndpointEvent(EndpointEventInner::Drained));
                 match e {
                     ConnectionError::TransportError(e) => Some(DatagramEvent::Response(
@@ -596,6 +666,8 @@ impl Endpoint {
             now,
             version,
             self.allow_mtud,
+            self.path_cache.get(now, addresses.remote),
+            self.memory_budget.clone(),
         );
 
         let id = self.connections.insert(ConnectionMeta {
@@ -647,9 +719,11 @@ impl Endpoint {
         Transmit {
             destination: addresses.remote,
             ecn: None,
+            dscp: None,
             contents: buf.freeze(),
             segment_size: None,
             src_ip: addresses.local_ip,
+            departure_time: None,
         }
     }
 
@@ -670,6 +744,19 @@ impl Endpoint {
         &self.config
     }
 
+    /// Bytes of stream data currently buffered across all connections
+    ///
+    /// See [`EndpointConfig::stream_memory_budget()`]. Connections report their usage as they are
+    /// driven, so the figure may briefly lag behind.
+    pub fn stream_memory_usage(&self) -> u64 {
+        self.memory_budget.used()
+    }
+
+    /// Statistics about the datagrams the endpoint handled outside of any connection
+    pub fn stats(&self) -> EndpointStats {
+        self.stats
+    }
+
     #[cfg(test)]
     pub(crate) fn known_connections(&self) -> usize {
         let x = self.connections.len();
@@ -707,10 +794,52 @@ impl fmt::Debug for Endpoint {
             .field("connections", &self.connections)
             .field("config", &self.config)
             .field("server_config", &self.server_config)
+            .field("stats", &self.stats)
             .finish()
     }
 }
 
+/// Statistics about the datagrams an endpoint handled outside of any connection
+///
+/// Counts why datagrams which couldn't be passed to a connection were dropped or answered, and
+/// what became of incoming connection attempts.
+#[derive(Debug, Default, Copy, Clone)]
+#[non_exhaustive]
+pub struct EndpointStats {
+    /// Datagrams whose first packet header couldn't be decoded
+    pub malformed_headers: u64,
+    /// Packets of an unsupported version dropped by a client
+    pub unsupported_versions: u64,
+    /// Version negotiation packets sent in response to packets of an unsupported version
+    pub version_negotiations_sent: u64,
+    /// Initial packets dropped for arriving in a datagram below the minimum size
+    pub short_initials: u64,
+    /// Initial packets dropped for a version the cryptographic layer doesn't support
+    pub unsupported_crypto_versions: u64,
+    /// Initial packets dropped because header protection couldn't be removed or they failed to
+    /// decrypt
+    pub initial_decryption_failures: u64,
+    /// Initial packets dropped for having invalid reserved bits
+    pub invalid_reserved_bits: u64,
+    /// Packets for unknown connections dropped without a response
+    pub unknown_connection: u64,
+    /// Stateless resets sent in response to packets for unknown connections
+    pub stateless_resets_sent: u64,
+    /// Retry packets sent in response to connection attempts
+    pub retries_sent: u64,
+    /// Connection attempts refused, because the endpoint was at its connection limit or rejecting
+    /// new connections
+    pub handshakes_refused: u64,
+    /// Connection attempts rejected for an invalid or expired Retry token
+    pub invalid_retry_tokens: u64,
+    /// Connection attempts rejected for an invalid destination connection ID length
+    pub invalid_initial_cids: u64,
+    /// Connection attempts whose first packet was rejected by the new connection
+    pub handshakes_failed: u64,
+    /// Connection attempts accepted
+    pub handshakes_accepted: u64,
+}
+
 /// Maps packets to existing connections
 #[derive(Default, Debug)]
 struct ConnectionIndex {
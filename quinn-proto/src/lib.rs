@@ -24,7 +24,7 @@ use std::{
     fmt,
     net::{IpAddr, SocketAddr},
     ops,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 mod cid_queue;
@@ -44,9 +44,9 @@ pub use varint::{VarInt, VarIntBoundsExceeded};
 mod connection;
 pub use crate::connection::{
     BytesSource, Chunk, Chunks, Connection, ConnectionError, ConnectionStats, Datagrams, Event,
-    FinishError, FrameStats, PathStats, ReadError, ReadableError, RecvStream, RttEstimator,
-    SendDatagramError, SendStream, StreamEvent, Streams, UdpStats, UnknownStream, WriteError,
-    Written,
+    FinishError, FrameStats, HandshakeStats, PacketSpaceStats, PathStats, ReadError, ReadableError,
+    RecvStream, RecvStreamStats, RttEstimator, SendDatagramError, SendStream, SendStreamStats,
+    SetDscpError, StreamEvent, Streams, UdpStats, UnknownStream, WriteError, Written,
 };
 
 mod config;
@@ -62,7 +62,7 @@ use crate::frame::Frame;
 pub use crate::frame::{ApplicationClose, ConnectionClose, Datagram};
 
 mod endpoint;
-pub use crate::endpoint::{ConnectError, ConnectionHandle, DatagramEvent, Endpoint};
+pub use crate::endpoint::{ConnectError, ConnectionHandle, DatagramEvent, Endpoint, EndpointStats};
 
 mod shared;
 pub use crate::shared::{ConnectionEvent, ConnectionId, EcnCodepoint, EndpointEvent};
@@ -78,6 +78,10 @@ pub use crate::cid_generator::{ConnectionIdGenerator, RandomConnectionIdGenerato
 mod token;
 use token::{ResetToken, RetryToken};
 
+mod path_cache;
+
+mod memory_budget;
+
 #[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
 
@@ -281,6 +285,8 @@ pub struct Transmit {
     pub destination: SocketAddr,
     /// Explicit congestion notification bits to set on the packet
     pub ecn: Option<EcnCodepoint>,
+    /// Differentiated services code point to set on the packet
+    pub dscp: Option<u8>,
     /// Contents of the datagram
     pub contents: Bytes,
     /// The segment size if this transmission contains multiple datagrams.
@@ -288,6 +294,11 @@ pub struct Transmit {
     pub segment_size: Option<usize>,
     /// Optional source IP address for the datagram
     pub src_ip: Option<IpAddr>,
+    /// Earliest time at which the datagrams should be sent
+    ///
+    /// Only set when [`TransportConfig::pacing_offload`] is enabled, in which case the I/O layer
+    /// is responsible for holding back the datagrams until this time, e.g. using `SO_TXTIME`.
+    pub departure_time: Option<Instant>,
 }
 
 //
@@ -305,3 +316,5 @@ const MAX_UDP_PAYLOAD: u16 = 65527;
 const TIMER_GRANULARITY: Duration = Duration::from_millis(1);
 /// Maximum number of streams that can be uniquely identified by a stream ID
 const MAX_STREAM_COUNT: u64 = 1 << 60;
+/// DSCP values occupy the upper 6 bits of the IPv4 TOS/IPv6 traffic class byte
+const MAX_DSCP: u8 = 0x3f;
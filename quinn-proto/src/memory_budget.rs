@@ -0,0 +1,106 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// Accounts for the stream data buffered by all connections of an endpoint
+///
+/// Shared between an endpoint and its connections, which may live on different threads.
+#[derive(Debug)]
+pub(crate) struct MemoryBudget {
+    /// Bytes which may be buffered before connections are held back
+    limit: u64,
+    used: AtomicU64,
+}
+
+impl MemoryBudget {
+    pub(crate) fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit: limit.unwrap_or(u64::MAX),
+            used: AtomicU64::new(0),
+        }
+    }
+
+    /// Bytes currently buffered across all connections
+    pub(crate) fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+}
+
+/// A connection's share of a [`MemoryBudget`]
+#[derive(Debug)]
+pub(crate) struct MemoryUsage {
+    budget: Arc<MemoryBudget>,
+    /// Bytes last reported to `budget`
+    reported: u64,
+}
+
+impl MemoryUsage {
+    pub(crate) fn new(budget: Arc<MemoryBudget>) -> Self {
+        Self {
+            budget,
+            reported: 0,
+        }
+    }
+
+    /// Report that the connection now buffers `bytes`
+    pub(crate) fn update(&mut self, bytes: u64) {
+        if bytes > self.reported {
+            self.budget
+                .used
+                .fetch_add(bytes - self.reported, Ordering::Relaxed);
+        } else {
+            self.budget
+                .used
+                .fetch_sub(self.reported - bytes, Ordering::Relaxed);
+        }
+        self.reported = bytes;
+    }
+
+    /// Total bytes the connection may buffer, given what other connections buffer
+    ///
+    /// Each connection is allowed a small amount regardless of the state of the budget, so that
+    /// it can always make progress.
+    pub(crate) fn allowance(&self) -> u64 {
+        let others = self.budget.used().saturating_sub(self.reported);
+        self.budget.limit.saturating_sub(others).max(MIN_ALLOWANCE)
+    }
+}
+
+impl Drop for MemoryUsage {
+    fn drop(&mut self) {
+        self.update(0);
+    }
+}
+
+/// Bytes each connection may buffer even when the budget is exhausted
+const MIN_ALLOWANCE: u64 = 16 * 1024;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_between_connections() {
+        let budget = Arc::new(MemoryBudget::new(Some(100_000)));
+        let mut a = MemoryUsage::new(budget.clone());
+        let mut b = MemoryUsage::new(budget.clone());
+        a.update(60_000);
+        assert_eq!(budget.used(), 60_000);
+        assert_eq!(a.allowance(), 100_000);
+        assert_eq!(b.allowance(), 40_000);
+
+        // Once the budget is exhausted, connections without a share get the minimum
+        b.update(50_000);
+        assert_eq!(budget.used(), 110_000);
+        assert_eq!(a.allowance(), 50_000);
+        assert_eq!(MemoryUsage::new(budget.clone()).allowance(), MIN_ALLOWANCE);
+
+        // Usage is released when connections go away
+        b.update(10_000);
+        assert_eq!(budget.used(), 70_000);
+        drop(a);
+        drop(b);
+        assert_eq!(budget.used(), 0);
+    }
+}
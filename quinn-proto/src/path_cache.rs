@@ -0,0 +1,119 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use rustc_hash::FxHashMap;
+
+/// Properties of a network path learned by a connection, for use by later connections
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct PathProperties {
+    /// Largest UDP payload known to be supported
+    pub(crate) mtu: u16,
+    /// Smoothed round-trip time when the connection finished
+    ///
+    /// Unlike the minimum, this doesn't make new connections' initial probe timeouts overly
+    /// aggressive if the path has slowed down since it was observed.
+    pub(crate) rtt: Duration,
+    /// Congestion window when the connection finished
+    pub(crate) cwnd: u64,
+}
+
+/// Bounded cache of path properties by remote address
+pub(crate) struct PathCache {
+    entries: FxHashMap<SocketAddr, (Instant, PathProperties)>,
+    capacity: usize,
+    lifetime: Duration,
+}
+
+impl PathCache {
+    pub(crate) fn new(capacity: usize, lifetime: Duration) -> Self {
+        Self {
+            entries: FxHashMap::default(),
+            capacity,
+            lifetime,
+        }
+    }
+
+    /// Remember the properties of the path to `remote`, replacing any previous entry
+    pub(crate) fn insert(&mut self, now: Instant, remote: SocketAddr, properties: PathProperties) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&remote) {
+            // Evict the least recently updated entry
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (updated, _))| *updated)
+                .map(|(&addr, _)| addr);
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.entries.insert(remote, (now, properties));
+    }
+
+    /// Look up the properties of the path to `remote`, if they were learned recently enough
+    pub(crate) fn get(&mut self, now: Instant, remote: SocketAddr) -> Option<PathProperties> {
+        let &(updated, properties) = self.entries.get(&remote)?;
+        if now.saturating_duration_since(updated) > self.lifetime {
+            self.entries.remove(&remote);
+            return None;
+        }
+        Some(properties)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(mtu: u16) -> PathProperties {
+        PathProperties {
+            mtu,
+            rtt: Duration::from_millis(10),
+            cwnd: 20_000,
+        }
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn evicts_least_recently_updated() {
+        let mut cache = PathCache::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+        cache.insert(now, addr(1), properties(1300));
+        cache.insert(now + Duration::from_secs(1), addr(2), properties(1400));
+        // Updating an entry refreshes it
+        cache.insert(now + Duration::from_secs(2), addr(1), properties(1350));
+        cache.insert(now + Duration::from_secs(3), addr(3), properties(1500));
+
+        let now = now + Duration::from_secs(4);
+        assert_eq!(cache.get(now, addr(1)), Some(properties(1350)));
+        assert_eq!(cache.get(now, addr(2)), None);
+        assert_eq!(cache.get(now, addr(3)), Some(properties(1500)));
+    }
+
+    #[test]
+    fn expires_entries() {
+        let mut cache = PathCache::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+        cache.insert(now, addr(1), properties(1300));
+        assert!(cache.get(now + Duration::from_secs(60), addr(1)).is_some());
+        assert!(cache.get(now + Duration::from_secs(61), addr(1)).is_none());
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn disabled() {
+        let mut cache = PathCache::new(0, Duration::from_secs(60));
+        let now = Instant::now();
+        cache.insert(now, addr(1), properties(1300));
+        assert_eq!(cache.get(now, addr(1)), None);
+    }
+}
@@ -1,8 +1,10 @@
 use std::{fmt, net::SocketAddr, time::Instant};
 
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
-use crate::{coding::BufExt, packet::PartialDecode, ResetToken, MAX_CID_SIZE};
+use crate::{
+    coding::BufExt, packet::PartialDecode, path_cache::PathProperties, ResetToken, MAX_CID_SIZE,
+};
 
 /// Events sent from an Endpoint to a Connection
 #[derive(Debug)]
@@ -20,6 +22,13 @@ pub(crate) enum ConnectionEventInner {
     },
     /// New connection identifiers have been issued for the Connection
     NewIdentifiers(Vec<IssuedCid>, Instant),
+    /// The network reported a datagram sent by the Connection as exceeding the path MTU
+    PathMtuError {
+        now: Instant,
+        remote: SocketAddr,
+        max_udp_payload_size: u16,
+        quoted: Bytes,
+    },
 }
 
 /// Events sent from a Connection to an Endpoint
@@ -54,6 +63,8 @@ pub(crate) enum EndpointEventInner {
     /// Stop routing connection ID for this sequence number to the connection
     /// When `bool == true`, a new connection ID will be issued to peer
     RetireConnectionId(Instant, u64, bool),
+    /// The connection is closing, having learned the properties of the path to this address
+    PathLearned(Instant, SocketAddr, PathProperties),
 }
 
 /// Protocol-level identifier for a connection.
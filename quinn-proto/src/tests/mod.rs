@@ -1,12 +1,13 @@
 use std::{
     convert::TryInto,
+    iter,
     net::{Ipv4Addr, Ipv6Addr, SocketAddr},
     sync::Arc,
     time::{Duration, Instant},
 };
 
 use assert_matches::assert_matches;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use hex_literal::hex;
 use rand::RngCore;
 use ring::hmac;
@@ -16,7 +17,9 @@ use tracing::info;
 use super::*;
 use crate::{
     cid_generator::{ConnectionIdGenerator, RandomConnectionIdGenerator},
+    crypto::rustls::initial_keys,
     frame::FrameStruct,
+    packet::{Header, PacketNumber},
 };
 mod util;
 use util::*;
@@ -35,13 +38,16 @@ fn version_negotiate_server() {
         // Long-header packet with reserved version number
         hex!("80 0a1a2a3a 04 00000000 04 00000000 00")[..].into(),
     );
-    let Some(DatagramEvent::Response(Transmit { contents, .. })) = event else { panic!("expected a response"); };
+    let Some(DatagramEvent::Response(Transmit { contents, .. })) = event else {
+        panic!("expected a response");
+    };
 
     assert_ne!(contents[0] & 0x80, 0);
     assert_eq!(&contents[1..15], hex!("00000000 04 00000000 04 00000000"));
     assert!(contents[15..].chunks(4).any(|x| {
         DEFAULT_SUPPORTED_VERSIONS.contains(&u32::from_be_bytes(x.try_into().unwrap()))
     }));
+    assert_eq!(server.stats().version_negotiations_sent, 1);
 }
 
 #[test]
@@ -158,7 +164,14 @@ fn stateless_retry() {
             ..server_config()
         },
     );
-    pair.connect();
+    let (client_ch, server_ch) = pair.connect();
+    let stats = pair.server.endpoint.stats();
+    assert_eq!(stats.retries_sent, 1);
+    assert_eq!(stats.handshakes_accepted, 1);
+    let stats = pair.client_conn_mut(client_ch).stats();
+    assert_eq!(stats.handshake.retries, 1);
+    let stats = pair.server_conn_mut(server_ch).stats();
+    assert_eq!(stats.handshake.retries, 1);
 }
 
 #[test]
@@ -185,6 +198,7 @@ fn server_stateless_reset() {
             reason: ConnectionError::Reset
         })
     );
+    assert_eq!(pair.server.endpoint.stats().stateless_resets_sent, 1);
 }
 
 #[test]
@@ -216,6 +230,146 @@ fn client_stateless_reset() {
     );
 }
 
+/// Encode a client Initial packet for `dst_cid` carrying `payload`, padded to `size` bytes
+fn client_initial(
+    dst_cid: &[u8],
+    token: &[u8],
+    payload: &[u8],
+    size: usize,
+    reserved_bits: bool,
+) -> BytesMut {
+    let dst_cid = ConnectionId::new(dst_cid);
+    let keys = initial_keys(rustls::quic::Version::V1, &dst_cid, Side::Client);
+    let header = Header::Initial {
+        number: PacketNumber::U8(0),
+        src_cid: ConnectionId::new(&[0; 8]),
+        dst_cid,
+        token: Bytes::copy_from_slice(token),
+        version: 1,
+    };
+    let mut buf = BytesMut::new();
+    let encode = header.encode(&mut buf);
+    if reserved_bits {
+        buf[0] |= 0x0c;
+    }
+    buf.extend_from_slice(payload);
+    buf.resize(size, 0);
+    encode.finish(
+        &mut buf,
+        &*keys.header.local,
+        Some((0, &*keys.packet.local)),
+    );
+    buf
+}
+
+#[test]
+fn dropped_datagram_stats() {
+    let _guard = subscribe();
+    let client_addr = "[::2]:7890".parse().unwrap();
+    let now = Instant::now();
+    let mut endpoint_config = EndpointConfig::default();
+    endpoint_config.supported_versions(vec![1, 2]);
+    let mut server = Endpoint::new(
+        Arc::new(endpoint_config),
+        Some(Arc::new(server_config())),
+        true,
+    );
+    let mut handle = |data: BytesMut| server.handle(now, client_addr, None, None, data);
+
+    assert!(handle(hex!("c0 00000001")[..].into()).is_none());
+    assert!(handle(client_initial(&[0; 8], &[], &[], 100, false)).is_none());
+    let mut unsupported = client_initial(&[0; 8], &[], &[], 1200, false);
+    unsupported[1..5].copy_from_slice(&2u32.to_be_bytes());
+    assert!(handle(unsupported).is_none());
+    let mut corrupt = client_initial(&[0; 8], &[], &[], 1200, false);
+    corrupt[1199] ^= 0xff;
+    assert!(handle(corrupt).is_none());
+    assert!(handle(client_initial(&[0; 8], &[], &[], 1200, true)).is_none());
+    // Handshake packet for an unknown connection
+    assert!(handle(hex!("e0 00000001 08 0000000000000000 00 05 0000000000")[..].into()).is_none());
+
+    let stats = server.stats();
+    assert_eq!(stats.malformed_headers, 1);
+    assert_eq!(stats.short_initials, 1);
+    assert_eq!(stats.unsupported_crypto_versions, 1);
+    assert_eq!(stats.initial_decryption_failures, 1);
+    assert_eq!(stats.invalid_reserved_bits, 1);
+    assert_eq!(stats.unknown_connection, 1);
+    assert_eq!(stats.handshakes_accepted, 0);
+
+    // Clients drop packets of versions they don't support rather than negotiating
+    let mut client = Endpoint::new(Default::default(), None, true);
+    assert!(client
+        .handle(
+            now,
+            client_addr,
+            None,
+            None,
+            hex!("80 0a1a2a3a 04 00000000 04 00000000 00")[..].into(),
+        )
+        .is_none());
+    assert_eq!(client.stats().unsupported_versions, 1);
+    assert_eq!(client.stats().version_negotiations_sent, 0);
+}
+
+#[test]
+fn rejected_handshake_stats() {
+    let _guard = subscribe();
+    let client_addr = "[::2]:7890".parse().unwrap();
+    let now = Instant::now();
+    let mut server = Endpoint::new(Default::default(), Some(Arc::new(server_config())), true);
+
+    // Destination CIDs must be at least 8 bytes long
+    assert!(matches!(
+        server.handle(
+            now,
+            client_addr,
+            None,
+            None,
+            client_initial(&[0; 4], &[], &[], 1200, false)
+        ),
+        Some(DatagramEvent::Response(_))
+    ));
+    assert_eq!(server.stats().invalid_initial_cids, 1);
+
+    // HANDSHAKE_DONE is not allowed in Initial packets
+    assert!(matches!(
+        server.handle(
+            now,
+            client_addr,
+            None,
+            None,
+            client_initial(&[0; 8], &[], &[0x1e], 1200, false)
+        ),
+        Some(DatagramEvent::Response(_))
+    ));
+    assert_eq!(server.stats().handshakes_failed, 1);
+    assert_eq!(server.known_connections(), 0);
+
+    let mut server = Endpoint::new(
+        Default::default(),
+        Some(Arc::new(ServerConfig {
+            use_retry: true,
+            ..server_config()
+        })),
+        true,
+    );
+    assert!(matches!(
+        server.handle(
+            now,
+            client_addr,
+            None,
+            None,
+            client_initial(&[0; 8], b"forged", &[], 1200, false)
+        ),
+        Some(DatagramEvent::Response(_))
+    ));
+    let stats = server.stats();
+    assert_eq!(stats.invalid_retry_tokens, 1);
+    assert_eq!(stats.retries_sent, 0);
+    assert_eq!(stats.handshakes_accepted, 0);
+}
+
 #[test]
 fn export_keying_material() {
     let _guard = subscribe();
@@ -485,6 +639,9 @@ fn zero_rtt_happypath() {
     );
 
     assert!(pair.client_conn_mut(client_ch).accepted_0rtt());
+    let stats = pair.client_conn_mut(client_ch).stats();
+    assert!(stats.handshake.accepted_0rtt);
+    assert!(stats.handshake.data.sent_packets > 0);
     let server_ch = pair.server.assert_accept();
 
     assert_matches!(
@@ -712,6 +869,14 @@ fn stream_id_limit() {
         Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
     );
     assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+    // The client reported being blocked once, however often it tried
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::StreamsBlocked {
+            dir: Dir::Uni,
+            limit: 1
+        }))
+    );
 
     let mut recv = pair.server_recv(server_ch, s);
     let mut chunks = recv.read(false).unwrap();
@@ -972,6 +1137,57 @@ fn idle_timeout() {
     );
 }
 
+#[test]
+fn handshake_timeout() {
+    let _guard = subscribe();
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(500);
+    let mut pair = Pair::default();
+    let mut transport = TransportConfig::default();
+    transport.handshake_timeout(Some(HANDSHAKE_TIMEOUT));
+    let mut client_config = client_config();
+    client_config.transport_config(Arc::new(transport));
+    let client_ch = pair.begin_connect(client_config);
+    let start = pair.time;
+
+    while !pair.client_conn_mut(client_ch).is_closed() {
+        if !pair.step() {
+            if let Some(t) = min_opt(pair.client.next_wakeup(), pair.server.next_wakeup()) {
+                pair.time = t;
+            }
+        }
+        pair.client.inbound.clear(); // Simulate total S->C packet loss
+    }
+
+    assert!(pair.time - start >= HANDSHAKE_TIMEOUT);
+    assert!(pair.time - start < 2 * HANDSHAKE_TIMEOUT);
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::ConnectionLost {
+            reason: ConnectionError::TimedOut,
+        })
+    );
+    assert!(pair.client_conn_mut(client_ch).stats().handshake.timed_out);
+}
+
+#[test]
+fn handshake_timeout_after_connected() {
+    let _guard = subscribe();
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(100);
+    let mut transport = TransportConfig::default();
+    transport.handshake_timeout(Some(HANDSHAKE_TIMEOUT));
+    let mut server_config = server_config();
+    server_config.transport_config(Arc::new(transport));
+    let mut pair = Pair::new(Default::default(), server_config);
+    let (client_ch, server_ch) = pair.connect();
+
+    pair.time += 2 * HANDSHAKE_TIMEOUT;
+    pair.client_conn_mut(client_ch).ping();
+    pair.drive();
+    assert!(!pair.client_conn_mut(client_ch).is_closed());
+    assert!(!pair.server_conn_mut(server_ch).is_closed());
+    assert!(!pair.server_conn_mut(server_ch).stats().handshake.timed_out);
+}
+
 #[test]
 fn connection_close_sends_acks() {
     let _guard = subscribe();
@@ -2067,6 +2283,117 @@ fn connect_runs_mtud_again_after_600_seconds() {
     assert_eq!(pair.server_conn_mut(server_ch).path_mtu(), 1452);
 }
 
+#[test]
+fn path_mtu_error_lowers_mtu() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.mtu = 1500;
+    let (client_ch, server_ch) = pair.connect();
+    pair.drive();
+    assert_eq!(pair.client_conn_mut(client_ch).path_mtu(), 1452);
+
+    // Capture the start of a datagram sent by the client
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(b"hello").unwrap();
+    let now = pair.time;
+    let server_addr = pair.server.addr;
+    pair.client.drive(now, server_addr);
+    let quoted = pair.client.outbound.back().unwrap().contents.slice(..16);
+
+    // Reports which don't quote a datagram sent on the connection are ignored
+    let mut spoofed = quoted.to_vec();
+    spoofed[1] ^= 0xff;
+    for (ch, event) in pair
+        .client
+        .endpoint
+        .handle_path_mtu_error(now, server_addr, 1300, &spoofed)
+    {
+        pair.client_conn_mut(ch).handle_event(event);
+    }
+    assert_eq!(pair.client_conn_mut(client_ch).path_mtu(), 1452);
+
+    for (ch, event) in pair
+        .client
+        .endpoint
+        .handle_path_mtu_error(now, server_addr, 1300, &quoted)
+    {
+        pair.client_conn_mut(ch).handle_event(event);
+    }
+    assert_eq!(pair.client_conn_mut(client_ch).path_mtu(), 1300);
+
+    // The connection keeps working on the narrower path
+    pair.mtu = 1300;
+    pair.client_send(client_ch, s).write(&[0xab; 4000]).unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    let client_conn = pair.client_conn_mut(client_ch);
+    assert_eq!(client_conn.path_mtu(), 1300);
+    assert_eq!(client_conn.stats().path.black_holes_detected, 0);
+    let recv = pair.server_recv(server_ch, s);
+    assert_eq!(stream_chunks(recv).len(), 4005);
+}
+
+#[test]
+fn path_cache_seeds_new_connections() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.mtu = 1500;
+    pair.latency = Duration::from_millis(10);
+    let (client_ch, _) = pair.connect();
+
+    // Grow the congestion window
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[0; 200_000]).unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    let stats = pair.client_conn_mut(client_ch).stats();
+    assert_eq!(pair.client_conn_mut(client_ch).path_mtu(), 1452);
+    assert_eq!(stats.path.sent_plpmtud_probes, 4);
+    let initial_window = TransportConfig::default()
+        .congestion_controller_factory
+        .build(pair.time, 1200)
+        .initial_window();
+    assert!(stats.path.cwnd > 4 * initial_window);
+    // The smoothed RTT, rather than the minimum, carries over
+    let rtt = stats.path.rtt;
+
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .close(now, VarInt(0), Bytes::new());
+    pair.drive();
+
+    // A new connection to the same address starts from what the first one learned
+    let client_ch = pair.begin_connect(client_config());
+    let stats = pair.client_conn_mut(client_ch).stats();
+    assert_eq!(stats.path.rtt, rtt);
+    assert_eq!(stats.path.cwnd, 4 * initial_window);
+    pair.drive();
+    pair.server.assert_accept();
+    let client_conn = pair.client_conn_mut(client_ch);
+    assert_eq!(client_conn.path_mtu(), 1452);
+    assert_eq!(client_conn.stats().path.sent_plpmtud_probes, 1);
+}
+
+#[test]
+fn path_cache_disabled() {
+    let _guard = subscribe();
+    let mut endpoint_config = EndpointConfig::default();
+    endpoint_config.path_cache_capacity(0);
+    let mut pair = Pair::new(Arc::new(endpoint_config), server_config());
+    pair.latency = Duration::from_millis(10);
+    let (client_ch, _) = pair.connect();
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .close(now, VarInt(0), Bytes::new());
+    pair.drive();
+
+    let client_ch = pair.begin_connect(client_config());
+    assert_eq!(
+        pair.client_conn_mut(client_ch).stats().path.rtt,
+        TransportConfig::default().initial_rtt
+    );
+}
+
 #[test]
 fn packet_loss_and_retry_too_low_mtu() {
     let _guard = subscribe();
@@ -2181,6 +2508,872 @@ fn packet_splitting_not_necessary_after_higher_mtu_discovered() {
     assert_eq!(pair.server.inbound.len(), 1);
 }
 
+#[test]
+fn pacing_offload_sets_departure_time() {
+    let _guard = subscribe();
+    let mut congestion = congestion::NewRenoConfig::default();
+    congestion.initial_window(1_000_000);
+    let mut transport = TransportConfig::default();
+    transport
+        .pacing_offload(true)
+        .congestion_controller_factory(Arc::new(congestion));
+    let client_config = ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    };
+
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(50);
+    let (client_ch, _) = pair.connect_with(client_config);
+    pair.drive();
+    pair.client_conn_mut(client_ch)
+        .set_departure_times_supported(true);
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s)
+        .write(&[42; 100_000])
+        .unwrap();
+    pair.client.drive(pair.time, pair.server.addr);
+
+    // The first burst leaves immediately, later ones are deferred rather than timer-paced
+    let departures = pair
+        .client
+        .outbound
+        .iter()
+        .map(|t| t.departure_time)
+        .collect::<Vec<_>>();
+    assert_eq!(departures[0], None);
+    let deferred = departures.iter().flatten().collect::<Vec<_>>();
+    assert!(!deferred.is_empty());
+    assert!(deferred.windows(2).all(|w| w[0] <= w[1]));
+    for &&departure in &deferred {
+        assert!(departure > pair.time);
+        assert!(departure <= pair.time + Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn pacing_offload_unsupported() {
+    let _guard = subscribe();
+    let mut congestion = congestion::NewRenoConfig::default();
+    congestion.initial_window(1_000_000);
+    let mut transport = TransportConfig::default();
+    transport
+        .pacing_offload(true)
+        .congestion_controller_factory(Arc::new(congestion));
+    let client_config = ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    };
+
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(50);
+    let (client_ch, _) = pair.connect_with(client_config);
+    pair.drive();
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s)
+        .write(&[42; 100_000])
+        .unwrap();
+    pair.client.drive(pair.time, pair.server.addr);
+
+    // Without support from the I/O layer, the pacer's timer holds back everything past the first
+    // burst
+    assert!(pair
+        .client
+        .outbound
+        .iter()
+        .all(|t| t.departure_time.is_none()));
+    let sent = pair
+        .client
+        .outbound
+        .iter()
+        .map(|t| t.contents.len())
+        .sum::<usize>();
+    assert!(sent < 100_000);
+    assert!(pair.client.next_wakeup().unwrap() > pair.time);
+}
+
+#[test]
+fn dscp_marking() {
+    let _guard = subscribe();
+    const EF: u8 = 46;
+    const CS1: u8 = 8;
+    let mut transport = TransportConfig::default();
+    assert_eq!(
+        transport.dscp(Some(64)).unwrap_err(),
+        ConfigError::OutOfBounds
+    );
+    transport.dscp(Some(EF)).unwrap();
+    let client_config = ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    };
+
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+    pair.drive();
+
+    let interactive = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let bulk = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, bulk)
+        .set_dscp(Some(CS1))
+        .unwrap();
+    assert_eq!(
+        pair.client_send(client_ch, bulk).set_dscp(Some(64)),
+        Err(SetDscpError::Config(ConfigError::OutOfBounds))
+    );
+    assert_eq!(pair.client_send(client_ch, bulk).dscp(), Ok(Some(CS1)));
+    assert_eq!(pair.client_send(client_ch, interactive).dscp(), Ok(None));
+    pair.client_send(client_ch, interactive)
+        .write(&[1; 3000])
+        .unwrap();
+    pair.client_send(client_ch, bulk).write(&[2; 3000]).unwrap();
+    pair.client.drive(pair.time, pair.server.addr);
+
+    let marks = pair
+        .client
+        .outbound
+        .iter()
+        .map(|t| t.dscp)
+        .collect::<Vec<_>>();
+    assert!(marks.contains(&Some(EF)));
+    assert!(marks.contains(&Some(CS1)));
+    assert!(marks.iter().all(|&x| x == Some(EF) || x == Some(CS1)));
+
+    pair.drive();
+    let recv = pair.server_recv(server_ch, interactive);
+    assert_eq!(stream_chunks(recv), vec![1; 3000]);
+    let recv = pair.server_recv(server_ch, bulk);
+    assert_eq!(stream_chunks(recv), vec![2; 3000]);
+}
+
+#[test]
+fn dscp_override_matching_connection() {
+    let _guard = subscribe();
+    const EF: u8 = 46;
+    let mut transport = TransportConfig::default();
+    transport.dscp(Some(EF)).unwrap();
+    let client_config = ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    };
+
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect_with(client_config);
+    pair.drive();
+
+    // A stream overriding the DSCP with the connection's own shares packets with other streams
+    let plain = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let marked = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, marked)
+        .set_dscp(Some(EF))
+        .unwrap();
+    pair.client_send(client_ch, plain).write(&[1; 100]).unwrap();
+    pair.client_send(client_ch, marked)
+        .write(&[2; 100])
+        .unwrap();
+    pair.client.drive(pair.time, pair.server.addr);
+    assert_eq!(pair.client.outbound.len(), 1);
+    assert_eq!(pair.client.outbound[0].dscp, Some(EF));
+}
+
+#[test]
+fn dscp_skips_rate_limited_stream() {
+    let _guard = subscribe();
+    const EF: u8 = 46;
+    const CS1: u8 = 8;
+    let mut transport = TransportConfig::default();
+    transport.dscp(Some(EF)).unwrap();
+    let client_config = ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    };
+
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+    pair.drive();
+
+    // The blocked stream comes first, but can't send, so mustn't decide the marking
+    let blocked = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let sending = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let mut stream = pair.client_send(client_ch, blocked);
+    stream.set_dscp(Some(CS1)).unwrap();
+    stream.set_rate_limit(Some(0)).unwrap();
+    stream.write(&[1; 100]).unwrap();
+    pair.client_send(client_ch, sending)
+        .write(&[2; 100])
+        .unwrap();
+    pair.client.drive(pair.time, pair.server.addr);
+    assert!(!pair.client.outbound.is_empty());
+    assert!(pair.client.outbound.iter().all(|t| t.dscp == Some(EF)));
+
+    pair.drive();
+    let recv = pair.server_recv(server_ch, sending);
+    assert_eq!(stream_chunks(recv), vec![2; 100]);
+}
+
+#[test]
+fn ecn_ect1() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    transport
+        .send_ect1(true)
+        .congestion_controller_factory(Arc::new(congestion::PragueConfig::default()));
+    let client_config = ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    };
+
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[0; 3000]).unwrap();
+    pair.client.drive(pair.time, pair.server.addr);
+    assert!(pair
+        .client
+        .outbound
+        .iter()
+        .all(|t| t.ecn == Some(EcnCodepoint::Ect1)));
+
+    pair.drive();
+    // The peer's ECN counts for ECT(1) packets were accepted
+    assert!(pair.client_conn_mut(client_ch).using_ecn());
+    assert!(pair.server_conn_mut(server_ch).using_ecn());
+    let recv = pair.server_recv(server_ch, s);
+    assert_eq!(stream_chunks(recv), vec![0; 3000]);
+}
+
+#[test]
+fn delivery_rate_samples() {
+    let _guard = subscribe();
+
+    /// Records the rate samples passed to a wrapped controller
+    #[derive(Clone)]
+    struct Recorder {
+        inner: Arc<congestion::NewRenoConfig>,
+        samples: Arc<std::sync::Mutex<Vec<congestion::RateSample>>>,
+    }
+
+    struct RecordingController {
+        inner: Box<dyn congestion::Controller>,
+        samples: Arc<std::sync::Mutex<Vec<congestion::RateSample>>>,
+    }
+
+    impl congestion::ControllerFactory for Recorder {
+        fn build(&self, now: Instant, current_mtu: u16) -> Box<dyn congestion::Controller> {
+            Box::new(RecordingController {
+                inner: self.inner.build(now, current_mtu),
+                samples: self.samples.clone(),
+            })
+        }
+    }
+
+    impl congestion::Controller for RecordingController {
+        fn on_ack(
+            &mut self,
+            now: Instant,
+            sent: Instant,
+            bytes: u64,
+            app_limited: bool,
+            rtt: &RttEstimator,
+        ) {
+            self.inner.on_ack(now, sent, bytes, app_limited, rtt);
+        }
+
+        fn on_rate_sample(&mut self, _now: Instant, sample: &congestion::RateSample) {
+            self.samples.lock().unwrap().push(*sample);
+        }
+
+        fn on_congestion_event(
+            &mut self,
+            now: Instant,
+            sent: Instant,
+            is_persistent_congestion: bool,
+            lost_bytes: u64,
+        ) {
+            self.inner
+                .on_congestion_event(now, sent, is_persistent_congestion, lost_bytes);
+        }
+
+        fn on_mtu_update(&mut self, new_mtu: u16) {
+            self.inner.on_mtu_update(new_mtu);
+        }
+
+        fn window(&self) -> u64 {
+            self.inner.window()
+        }
+
+        fn clone_box(&self) -> Box<dyn congestion::Controller> {
+            Box::new(Self {
+                inner: self.inner.clone_box(),
+                samples: self.samples.clone(),
+            })
+        }
+
+        fn initial_window(&self) -> u64 {
+            self.inner.initial_window()
+        }
+
+        fn into_any(self: Box<Self>) -> Box<dyn std::any::Any> {
+            self
+        }
+    }
+
+    let recorder = Recorder {
+        inner: Arc::new(congestion::NewRenoConfig::default()),
+        samples: Arc::default(),
+    };
+    let mut transport = TransportConfig::default();
+    transport.congestion_controller_factory(recorder.clone());
+    let client_config = ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    };
+
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(10);
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[0; 100_000]).unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    let recv = pair.server_recv(server_ch, s);
+    assert_eq!(stream_chunks(recv).len(), 100_000);
+
+    let samples = recorder.samples.lock().unwrap();
+    assert!(!samples.is_empty());
+    let last = samples.last().unwrap();
+    assert!(last.total_delivered >= 100_000);
+    assert_eq!(last.lost, 0);
+    assert!(last.rtt >= 2 * pair.latency);
+    assert!(samples
+        .iter()
+        .any(|x| x.delivery_rate.map_or(false, |rate| rate > 0)));
+    assert!(samples
+        .windows(2)
+        .all(|w| w[0].total_delivered < w[1].total_delivered));
+}
+
+#[test]
+fn bbr3_rate_samples() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    transport.congestion_controller_factory(Arc::new(congestion::Bbr3Config::default()));
+    let mut client_config = client_config();
+    client_config.transport_config(Arc::new(transport));
+
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(10);
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+    let initial_cwnd = pair.client_conn_mut(client_ch).stats().path.cwnd;
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[0; 200_000]).unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    let recv = pair.server_recv(server_ch, s);
+    assert_eq!(stream_chunks(recv).len(), 200_000);
+
+    // Bbr3 only grows its window in response to rate samples
+    assert!(pair.client_conn_mut(client_ch).stats().path.cwnd > initial_cwnd);
+}
+
+#[test]
+fn switch_congestion_controller() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[0; 100_000]).unwrap();
+    pair.drive();
+    let conn = pair.client_conn_mut(client_ch);
+    let window = conn.congestion_window();
+    assert!(window > conn.congestion_state().initial_window());
+
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .set_congestion_controller(now, Arc::new(congestion::LedbatConfig::default()));
+    let conn = pair.client_conn_mut(client_ch);
+    // The new controller picks up where the old one left off
+    assert_eq!(conn.congestion_window(), window);
+    assert!(conn
+        .congestion_state()
+        .clone_box()
+        .into_any()
+        .downcast::<congestion::Ledbat>()
+        .is_ok());
+
+    pair.client_send(client_ch, s).write(&[0; 100_000]).unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    let recv = pair.server_recv(server_ch, s);
+    assert_eq!(stream_chunks(recv).len(), 200_000);
+}
+
+#[test]
+fn spurious_loss() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let mut transport = TransportConfig::default();
+    transport.adaptive_reordering_threshold(true);
+    let mut client_config = client_config();
+    client_config.transport_config(Arc::new(transport));
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+    let window = pair.client_conn_mut(client_ch).congestion_window();
+
+    // Delay the first of several packets until after the rest have been acknowledged
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[0; 10_000]).unwrap();
+    pair.client.drive(pair.time, pair.server.addr);
+    let delayed = pair.client.outbound.pop_front().unwrap();
+    pair.drive();
+    let stats = pair.client_conn_mut(client_ch).stats();
+    assert_eq!(stats.path.lost_packets, 1);
+    assert_eq!(stats.path.congestion_events, 1);
+    assert!(pair.client_conn_mut(client_ch).congestion_window() < window);
+
+    pair.server
+        .inbound
+        .push_back((pair.time, delayed.ecn, delayed.contents.as_ref().into()));
+    pair.drive();
+    // Process the final ACK
+    pair.drive_client();
+    let conn = pair.client_conn_mut(client_ch);
+    let stats = conn.stats();
+    assert_eq!(stats.path.spurious_lost_packets, 1);
+    assert_eq!(stats.path.spurious_congestion_events, 1);
+    // The congestion controller's response was undone
+    assert!(conn.congestion_window() >= window);
+    let recv = pair.server_recv(server_ch, s);
+    assert_eq!(stream_chunks(recv).len(), 10_000);
+}
+
+#[test]
+fn reordering_threshold_decay() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let mut transport = TransportConfig::default();
+    transport.adaptive_reordering_threshold(true);
+    let mut client_config = client_config();
+    client_config.transport_config(Arc::new(transport));
+    let (client_ch, _) = pair.connect_with(client_config);
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+
+    // A reordered packet raises the thresholds
+    pair.client_send(client_ch, s).write(&[0; 10_000]).unwrap();
+    pair.client.drive(pair.time, pair.server.addr);
+    let delayed = pair.client.outbound.pop_front().unwrap();
+    pair.drive();
+    pair.server
+        .inbound
+        .push_back((pair.time, delayed.ecn, delayed.contents.as_ref().into()));
+    pair.drive();
+    pair.drive_client();
+    let (packets, time) = pair.client_conn_mut(client_ch).reordering_thresholds();
+    assert!(packets > 0);
+
+    // Losses which really are losses relax them again
+    for _ in 0..8 {
+        pair.client_send(client_ch, s).write(&[0; 10_000]).unwrap();
+        pair.client.drive(pair.time, pair.server.addr);
+        assert!(pair.client.outbound.pop_front().is_some());
+        pair.drive();
+    }
+    let conn = pair.client_conn_mut(client_ch);
+    assert_eq!(conn.stats().path.spurious_lost_packets, 1);
+    assert!(conn.stats().path.congestion_events >= 8);
+    let (decayed_packets, decayed_time) = conn.reordering_thresholds();
+    assert!(decayed_packets < packets, "{decayed_packets} vs {packets}");
+    assert!(decayed_time <= time);
+}
+
+#[test]
+fn send_rate_limit() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let mut transport = TransportConfig::default();
+    transport.send_rate_limit(Some(100_000));
+    let mut client_config = client_config();
+    client_config.transport_config(Arc::new(transport));
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+    assert_eq!(
+        pair.client_conn_mut(client_ch).send_rate_limit(),
+        Some(100_000)
+    );
+
+    let start = pair.time;
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[0; 100_000]).unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    let recv = pair.server_recv(server_ch, s);
+    assert_eq!(stream_chunks(recv).len(), 100_000);
+    // A burst of 10ms worth of data may be sent immediately, and the final frame may exceed the
+    // limit
+    let elapsed = pair.time - start;
+    assert!(elapsed >= Duration::from_millis(950), "{elapsed:?}");
+    let stats = pair.client_conn_mut(client_ch).stats();
+    assert!(stats.send_rate_limited >= Duration::from_millis(900));
+
+    // Lifting the limit lets data through immediately
+    pair.client_conn_mut(client_ch).set_send_rate_limit(None);
+    let start = pair.time;
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[0; 100_000]).unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    let recv = pair.server_recv(server_ch, s);
+    assert_eq!(stream_chunks(recv).len(), 100_000);
+    assert!(pair.time - start < Duration::from_millis(100));
+}
+
+#[test]
+fn stream_rate_limit() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    let limited = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let unlimited = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, limited)
+        .set_rate_limit(Some(10_000))
+        .unwrap();
+    assert_eq!(
+        pair.client_send(client_ch, limited).rate_limit().unwrap(),
+        Some(10_000)
+    );
+    for &s in &[limited, unlimited] {
+        pair.client_send(client_ch, s).write(&[0; 10_000]).unwrap();
+        pair.client_send(client_ch, s).finish().unwrap();
+    }
+
+    // The unlimited stream isn't held back by the limited one
+    let start = pair.time;
+    while pair.time - start < Duration::from_millis(50) && pair.step() {}
+    let recv = pair.server_recv(server_ch, unlimited);
+    assert_eq!(stream_chunks(recv).len(), 10_000);
+    let recv = pair.server_recv(server_ch, limited);
+    let mut received = stream_chunks(recv).len();
+    assert!(received < 10_000);
+
+    pair.drive();
+    let recv = pair.server_recv(server_ch, limited);
+    received += stream_chunks(recv).len();
+    assert_eq!(received, 10_000);
+    assert!(pair.time - start >= Duration::from_millis(800));
+}
+
+#[test]
+fn receive_window_auto_tuning() {
+    let _guard = subscribe();
+    const SIZE: usize = 300_000;
+
+    // Time taken to transfer `SIZE` bytes over a stream with a small initial window
+    let transfer = |auto_tuning: bool| {
+        let mut transport = TransportConfig::default();
+        transport
+            .stream_receive_window(10_000u32.into())
+            .receive_window_auto_tuning(auto_tuning);
+        let mut server_config = server_config();
+        server_config.transport_config(Arc::new(transport));
+        let mut pair = Pair::new(Default::default(), server_config);
+        pair.latency = Duration::from_millis(10);
+        let (client_ch, server_ch) = pair.connect();
+
+        let start = pair.time;
+        let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+        let (mut sent, mut received) = (0, 0);
+        while received < SIZE {
+            // Writes are limited by flow control
+            if let Ok(n) = pair.client_send(client_ch, s).write(&[0; SIZE][sent..]) {
+                sent += n;
+            }
+            let active = pair.step();
+            let recv = pair.server_recv(server_ch, s);
+            let read = stream_chunks(recv).len();
+            assert!(active || read > 0, "transfer stalled");
+            received += read;
+        }
+        pair.time - start
+    };
+
+    let fixed = transfer(false);
+    let tuned = transfer(true);
+    // 10kB per 20ms round trip
+    assert!(fixed >= Duration::from_millis(600), "{fixed:?}");
+    assert!(tuned < fixed / 4, "{tuned:?}");
+}
+
+#[test]
+fn stream_memory_budget() {
+    let _guard = subscribe();
+    let mut endpoint_config = EndpointConfig::default();
+    endpoint_config.stream_memory_budget(Some(50_000));
+    let mut transport = TransportConfig::default();
+    transport.stream_receive_window(30_000u32.into());
+    let mut server_config = server_config();
+    server_config.transport_config(Arc::new(transport));
+    let mut pair = Pair::new(Arc::new(endpoint_config), server_config);
+    let (client_ch, server_ch) = pair.connect();
+
+    // Writes are held back once the sender's budget is used up
+    let data = [0; 100_000];
+    let a = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let b = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    assert_eq!(pair.client_send(client_ch, a).write(&data), Ok(30_000));
+    assert_eq!(pair.client_send(client_ch, b).write(&data), Ok(20_000));
+    pair.drive();
+    assert_eq!(pair.client.endpoint.stream_memory_usage(), 0);
+    assert_eq!(pair.client_send(client_ch, b).write(&data), Ok(10_000));
+    pair.drive();
+    assert_eq!(pair.server.endpoint.stream_memory_usage(), 60_000);
+
+    // Reading `a` only makes room for as much as the budget allows while `b` is unread
+    let recv = pair.server_recv(server_ch, a);
+    assert_eq!(stream_chunks(recv).len(), 30_000);
+    pair.drive();
+    assert_eq!(pair.client_send(client_ch, a).write(&data), Ok(20_000));
+    pair.drive();
+
+    // Reading `b` releases the credit held back from `a`
+    let recv = pair.server_recv(server_ch, b);
+    assert_eq!(stream_chunks(recv).len(), 30_000);
+    pair.drive();
+    assert_eq!(pair.client_send(client_ch, a).write(&data), Ok(10_000));
+}
+
+#[test]
+fn per_stream_flow_control() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    transport.stream_receive_window(10_000u32.into());
+    let mut server_config = server_config();
+    server_config.transport_config(Arc::new(transport));
+    let mut pair = Pair::new(Default::default(), server_config);
+    let (client_ch, server_ch) = pair.connect();
+
+    // A larger receive window is announced right away
+    let data = [0; 100_000];
+    let a = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    assert_eq!(pair.client_send(client_ch, a).write(&data), Ok(10_000));
+    pair.drive();
+    pair.server_recv(server_ch, a)
+        .set_receive_window(50_000u32.into())
+        .unwrap();
+    assert_eq!(pair.server_recv(server_ch, a).receive_window(), Ok(50_000));
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Writable { id })) if id == a
+    );
+    assert_eq!(pair.client_send(client_ch, a).write(&data), Ok(40_000));
+
+    // The send buffer limit blocks writes until data is acknowledged
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s)
+        .set_send_buffer_limit(Some(6000))
+        .unwrap();
+    assert_eq!(pair.client_send(client_ch, s).write(&data), Ok(6000));
+    assert_eq!(
+        pair.client_send(client_ch, s).write(&data),
+        Err(WriteError::Blocked)
+    );
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Writable { id })) if id == s
+    );
+    assert_eq!(pair.client_send(client_ch, s).write(&data), Ok(4000));
+}
+
+#[test]
+fn stream_stats() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    transport.stream_receive_window(10_000u32.into());
+    let mut server_config = server_config();
+    server_config.transport_config(Arc::new(transport));
+    let mut pair = Pair::new(Default::default(), server_config);
+    pair.latency = Duration::from_millis(10);
+    let (client_ch, server_ch) = pair.connect();
+
+    let data = [0; 20_000];
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    assert_eq!(pair.client_send(client_ch, s).write(&data), Ok(10_000));
+    assert_eq!(
+        pair.client_send(client_ch, s).write(&data),
+        Err(WriteError::Blocked)
+    );
+    pair.drive();
+    let stats = pair.server_recv(server_ch, s).stats().unwrap();
+    assert_eq!(stats.max_offset_received, 10_000);
+    assert_eq!(stats.bytes_read, 0);
+    assert!(stats.first_received.unwrap() <= stats.last_received.unwrap());
+    let stats = pair.client_send(client_ch, s).stats().unwrap();
+    assert_eq!(stats.bytes_written, 10_000);
+    assert_eq!(stats.bytes_sent, 10_000);
+    assert_eq!(stats.bytes_acked, 10_000);
+    assert_eq!(stats.bytes_retransmitted, 0);
+
+    // Reading unblocks the writer, which has been blocked by stream-level flow control meanwhile
+    let recv = pair.server_recv(server_ch, s);
+    assert_eq!(stream_chunks(recv).len(), 10_000);
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Writable { id })) if id == s
+    );
+    let stats = pair.client_send(client_ch, s).stats().unwrap();
+    assert!(stats.stream_blocked >= Duration::from_millis(20));
+    assert_eq!(stats.connection_blocked, Duration::ZERO);
+
+    // Statistics of a finished stream remain available until the next poll
+    assert_eq!(pair.client_send(client_ch, s).write(&data), Ok(10_000));
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Finished { id })) if id == s
+    );
+    let stats = pair.client_send(client_ch, s).stats().unwrap();
+    assert_eq!(stats.bytes_acked, 20_000);
+    assert!(stats.first_acked.unwrap() < stats.last_acked.unwrap());
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+    assert!(pair.client_send(client_ch, s).stats().is_err());
+}
+
+#[test]
+fn data_blocked() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    transport.receive_window(5_000u32.into());
+    let mut server_config = server_config();
+    server_config.transport_config(Arc::new(transport));
+    let mut pair = Pair::new(Default::default(), server_config);
+    let (client_ch, server_ch) = pair.connect();
+
+    let data = [0; 10_000];
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    assert_eq!(pair.client_send(client_ch, s).write(&data), Ok(5_000));
+    for _ in 0..2 {
+        assert_eq!(
+            pair.client_send(client_ch, s).write(&data),
+            Err(WriteError::Blocked)
+        );
+    }
+    pair.drive();
+    // Repeated writes at the same limit are only reported once
+    assert_eq!(
+        pair.client_conn_mut(client_ch)
+            .stats()
+            .frame_tx
+            .data_blocked,
+        1
+    );
+    let mut events = iter::from_fn(|| pair.server_conn_mut(server_ch).poll());
+    assert!(events.any(|e| matches!(e, Event::Stream(StreamEvent::DataBlocked { limit: 5_000 }))));
+}
+
+#[test]
+fn stream_data_blocked() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    transport.stream_receive_window(1_000u32.into());
+    let mut server_config = server_config();
+    server_config.transport_config(Arc::new(transport));
+    let mut pair = Pair::new(Default::default(), server_config);
+    let (client_ch, server_ch) = pair.connect();
+
+    let data = [0; 2_000];
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    assert_eq!(pair.client_send(client_ch, s).write(&data), Ok(1_000));
+    assert_eq!(
+        pair.client_send(client_ch, s).write(&data),
+        Err(WriteError::Blocked)
+    );
+    pair.drive();
+    assert_eq!(
+        pair.client_conn_mut(client_ch)
+            .stats()
+            .frame_tx
+            .stream_data_blocked,
+        1
+    );
+    let mut events = iter::from_fn(|| pair.server_conn_mut(server_ch).poll());
+    assert!(events.any(|e| matches!(
+        e,
+        Event::Stream(StreamEvent::StreamDataBlocked { id, limit: 1_000 }) if id == s
+    )));
+}
+
+#[test]
+fn streams_blocked() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    transport.max_concurrent_uni_streams(1u32.into());
+    let mut server_config = server_config();
+    server_config.transport_config(Arc::new(transport));
+    let mut pair = Pair::new(Default::default(), server_config);
+    let (client_ch, server_ch) = pair.connect();
+
+    assert!(pair.client_streams(client_ch).open(Dir::Uni).is_some());
+    assert!(pair.client_streams(client_ch).open(Dir::Uni).is_none());
+    pair.drive();
+    assert_eq!(
+        pair.client_conn_mut(client_ch)
+            .stats()
+            .frame_tx
+            .streams_blocked_uni,
+        1
+    );
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::StreamsBlocked {
+            dir: Dir::Uni,
+            limit: 1
+        }))
+    );
+}
+
+#[test]
+fn switch_congestion_controller_in_congestion_avoidance() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    // Lose a packet to leave slow start
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[0; 10_000]).unwrap();
+    pair.client.drive(pair.time, pair.server.addr);
+    assert!(pair.client.outbound.pop_front().is_some());
+    pair.drive();
+    let conn = pair.client_conn_mut(client_ch);
+    assert_eq!(conn.stats().path.congestion_events, 1);
+    let ssthresh = conn.congestion_state().ssthresh();
+    assert_ne!(ssthresh, u64::MAX);
+
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .set_congestion_controller(now, Arc::new(congestion::NewRenoConfig::default()));
+    let conn = pair.client_conn_mut(client_ch);
+    assert_eq!(conn.congestion_state().ssthresh(), ssthresh);
+    let window = conn.congestion_window();
+
+    // The new controller stays in congestion avoidance rather than doubling its window each
+    // round trip
+    pair.client_send(client_ch, s).write(&[0; 100_000]).unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    let conn = pair.client_conn_mut(client_ch);
+    assert!(
+        conn.congestion_window() < 2 * window,
+        "{} vs {window}",
+        conn.congestion_window()
+    );
+    let recv = pair.server_recv(server_ch, s);
+    assert_eq!(stream_chunks(recv).len(), 110_000);
+}
+
 fn stream_chunks(mut recv: RecvStream) -> Vec<u8> {
     let mut buf = Vec::new();
 
@@ -2205,4 +3398,41 @@ fn reject_new_connections() {
     pair.drive();
     pair.server.assert_no_accept();
     assert!(pair.client.connections.get(&client_ch).unwrap().is_closed());
+    let stats = pair.server.endpoint.stats();
+    assert_eq!(stats.handshakes_refused, 1);
+    assert_eq!(stats.handshakes_accepted, 0);
+}
+
+#[test]
+fn handshake_stats() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(10);
+    let (client_ch, server_ch) = pair.connect();
+    pair.drive();
+
+    let stats = pair.client_conn_mut(client_ch).stats().handshake;
+    // One round trip to receive the server's first flight, and another for HANDSHAKE_DONE
+    assert!(stats.first_byte.unwrap() >= Duration::from_millis(10));
+    assert_eq!(stats.completed, stats.first_byte);
+    assert_eq!(
+        stats.confirmed.unwrap() - stats.completed.unwrap(),
+        Duration::from_millis(20)
+    );
+    assert_eq!(stats.retries, 0);
+    assert!(!stats.accepted_0rtt);
+    assert!(stats.initial.sent_bytes >= 1200);
+    assert!(stats.initial.received_packets > 0);
+    assert!(stats.handshake.sent_packets > 0);
+    assert!(stats.handshake.received_packets > 0);
+    assert_eq!(stats.initial.lost_packets + stats.handshake.lost_packets, 0);
+
+    let stats = pair.server_conn_mut(server_ch).stats().handshake;
+    assert_eq!(stats.first_byte, Some(Duration::ZERO));
+    assert_eq!(stats.completed, Some(Duration::from_millis(20)));
+    assert_eq!(stats.confirmed, stats.completed);
+    assert_eq!(stats.retries, 0);
+    assert!(stats.initial.received_bytes >= 1200);
+    assert!(stats.handshake.received_packets > 0);
+    assert!(stats.data.sent_packets > 0);
 }
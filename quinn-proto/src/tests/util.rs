@@ -497,9 +497,11 @@ fn split_transmit(mut transmit: Transmit) -> Vec<Transmit> {
         transmits.push(Transmit {
             destination: transmit.destination,
             ecn: transmit.ecn,
+            dscp: transmit.dscp,
             contents,
             segment_size: None,
             src_ip: transmit.src_ip,
+            departure_time: transmit.departure_time,
         });
     }
 
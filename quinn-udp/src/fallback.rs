@@ -6,7 +6,9 @@ use std::{
 
 use proto::Transmit;
 
-use super::{log_sendmsg_error, RecvMeta, UdpSockRef, UdpState, IO_ERROR_LOG_INTERVAL};
+use super::{
+    log_sendmsg_error, PathMtuError, RecvMeta, UdpSockRef, UdpState, IO_ERROR_LOG_INTERVAL,
+};
 
 /// Fallback UDP socket interface that stubs out all special functionality
 ///
@@ -29,6 +31,31 @@ impl UdpSocketState {
         socket.0.set_nonblocking(true)
     }
 
+    /// Opt in to receiving ICMP errors reporting that a sent datagram exceeded the path MTU
+    ///
+    /// Not supported on this platform.
+    pub fn enable_path_mtu_errors(_socket: UdpSockRef<'_>) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Take the oldest path MTU error received since the last call, if any
+    pub fn path_mtu_error(&self) -> Option<PathMtuError> {
+        None
+    }
+
+    /// Opt in to passing [`Transmit::departure_time`](crate::Transmit::departure_time) on to the
+    /// kernel
+    ///
+    /// Not supported on this platform.
+    pub fn enable_txtime(&mut self, _socket: UdpSockRef<'_>) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Whether departure times are passed on to the kernel, which is never the case here
+    pub fn txtime(&self) -> bool {
+        false
+    }
+
     pub fn send(
         &self,
         socket: UdpSockRef<'_>,
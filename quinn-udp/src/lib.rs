@@ -138,6 +138,11 @@ pub struct Transmit {
     pub destination: SocketAddr,
     /// Explicit congestion notification bits to set on the packet
     pub ecn: Option<EcnCodepoint>,
+    /// Differentiated services code point to set on the packet
+    ///
+    /// Combined with [`ecn`](Self::ecn) to form the IPv4 TOS or IPv6 traffic class byte. Only the
+    /// lower 6 bits are significant.
+    pub dscp: Option<u8>,
     /// Contents of the datagram
     pub contents: Bytes,
     /// The segment size if this transmission contains multiple datagrams.
@@ -145,6 +150,26 @@ pub struct Transmit {
     pub segment_size: Option<usize>,
     /// Optional source IP address for the datagram
     pub src_ip: Option<IpAddr>,
+    /// Earliest time at which the datagrams should leave the host
+    ///
+    /// Passed to the kernel via `SCM_TXTIME` if [`UdpSocketState::txtime`] is true, and ignored
+    /// otherwise.
+    pub departure_time: Option<Instant>,
+}
+
+/// A datagram which the network reported as exceeding the path MTU
+///
+/// Produced from ICMP "fragmentation needed" and ICMPv6 "packet too big" messages if enabled via
+/// [`UdpSocketState::enable_path_mtu_errors`]. The contents of such messages are not
+/// authenticated, so the reported size must be validated before use.
+#[derive(Debug, Clone)]
+pub struct PathMtuError {
+    /// The destination of the datagram
+    pub destination: SocketAddr,
+    /// The largest UDP payload the path reportedly supports
+    pub max_udp_payload_size: u16,
+    /// The start of the datagram's payload, as quoted by the error message
+    pub contents: Bytes,
 }
 
 /// Log at most 1 IO error per minute
@@ -1,6 +1,7 @@
 #[cfg(not(any(target_os = "macos", target_os = "ios")))]
 use std::ptr;
 use std::{
+    collections::VecDeque,
     io,
     io::IoSliceMut,
     mem::{self, MaybeUninit},
@@ -16,7 +17,7 @@ use std::{
 use socket2::SockRef;
 
 use super::{
-    cmsg, log_sendmsg_error, EcnCodepoint, RecvMeta, Transmit, UdpSockRef, UdpState,
+    cmsg, log_sendmsg_error, EcnCodepoint, PathMtuError, RecvMeta, Transmit, UdpSockRef, UdpState,
     IO_ERROR_LOG_INTERVAL,
 };
 
@@ -32,6 +33,9 @@ type IpTosTy = libc::c_int;
 #[derive(Debug)]
 pub struct UdpSocketState {
     last_send_error: Mutex<Instant>,
+    path_mtu_errors: Mutex<VecDeque<PathMtuError>>,
+    /// Whether `SO_TXTIME` was enabled on the socket
+    txtime: bool,
 }
 
 impl UdpSocketState {
@@ -39,6 +43,8 @@ impl UdpSocketState {
         let now = Instant::now();
         Self {
             last_send_error: Mutex::new(now.checked_sub(2 * IO_ERROR_LOG_INTERVAL).unwrap_or(now)),
+            path_mtu_errors: Mutex::new(VecDeque::new()),
+            txtime: false,
         }
     }
 
@@ -46,13 +52,51 @@ impl UdpSocketState {
         init(sock.0)
     }
 
+    /// Opt in to receiving ICMP errors reporting that a sent datagram exceeded the path MTU
+    ///
+    /// Reported errors are retrieved via [`path_mtu_error`](Self::path_mtu_error). Only supported
+    /// on Linux, via `IP_RECVERR` and `IPV6_RECVERR`.
+    pub fn enable_path_mtu_errors(sock: UdpSockRef<'_>) -> io::Result<()> {
+        errqueue::enable(sock.0)
+    }
+
+    /// Take the oldest path MTU error received since the last call, if any
+    pub fn path_mtu_error(&self) -> Option<PathMtuError> {
+        self.path_mtu_errors.lock().unwrap().pop_front()
+    }
+
+    /// Opt in to passing [`Transmit::departure_time`] on to the kernel
+    ///
+    /// Departure times are ignored unless this succeeded for the socket. Only supported on Linux,
+    /// via `SO_TXTIME`, and only enforced if the socket's queueing discipline supports it, e.g.
+    /// `fq`.
+    pub fn enable_txtime(&mut self, sock: UdpSockRef<'_>) -> io::Result<()> {
+        txtime::enable(&*sock.0)?;
+        self.txtime = true;
+        Ok(())
+    }
+
+    /// Whether departure times are passed on to the kernel
+    ///
+    /// See [`enable_txtime`](Self::enable_txtime).
+    pub fn txtime(&self) -> bool {
+        self.txtime
+    }
+
     pub fn send(
         &self,
         socket: UdpSockRef<'_>,
         state: &UdpState,
         transmits: &[Transmit],
     ) -> Result<usize, io::Error> {
-        send(state, socket.0, &self.last_send_error, transmits)
+        send(
+            state,
+            self.txtime,
+            socket.0,
+            &self.last_send_error,
+            &self.path_mtu_errors,
+            transmits,
+        )
     }
 
     pub fn recv(
@@ -61,7 +105,17 @@ impl UdpSocketState {
         bufs: &mut [IoSliceMut<'_>],
         meta: &mut [RecvMeta],
     ) -> io::Result<usize> {
-        recv(socket.0, bufs, meta)
+        let io = &*socket.0;
+        loop {
+            match recv(io.into(), bufs, meta) {
+                // A queued ICMP error is reported by the next socket operation. Once it's been read
+                // from the error queue, datagrams can be received normally again.
+                Err(e)
+                    if e.kind() != io::ErrorKind::WouldBlock
+                        && errqueue::drain(io, &self.path_mtu_errors) => {}
+                result => return result,
+            }
+        }
     }
 }
 
@@ -158,8 +212,10 @@ fn init(io: SockRef<'_>) -> io::Result<()> {
 fn send(
     #[allow(unused_variables)] // only used on Linux
     state: &UdpState,
+    txtime: bool,
     io: SockRef<'_>,
     last_send_error: &Mutex<Instant>,
+    path_mtu_errors: &Mutex<VecDeque<PathMtuError>>,
     transmits: &[Transmit],
 ) -> io::Result<usize> {
     #[allow(unused_mut)] // only mutable on FreeBSD
@@ -200,7 +256,8 @@ fn send(
             &mut iovecs[i],
             &mut cmsgs[i],
             encode_src_ip,
-            state.sendmsg_einval(),
+            state,
+            txtime,
         );
     }
     let num_transmits = transmits.len().min(BATCH_SIZE);
@@ -239,6 +296,9 @@ fn send(
                         state.set_sendmsg_einval();
                     }
 
+                    // A queued ICMP error may be reported by `sendmsg` rather than `recvmsg`
+                    errqueue::drain(&io, path_mtu_errors);
+
                     // Other errors are ignored, since they will usually be handled
                     // by higher level retransmits and timeouts.
                     // - PermissionDenied errors have been observed due to iptable rules.
@@ -262,8 +322,10 @@ fn send(
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 fn send(
     state: &UdpState,
+    txtime: bool,
     io: SockRef<'_>,
     last_send_error: &Mutex<Instant>,
+    path_mtu_errors: &Mutex<VecDeque<PathMtuError>>,
     transmits: &[Transmit],
 ) -> io::Result<usize> {
     let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
@@ -281,7 +343,8 @@ fn send(
             &mut ctrl,
             // Only tested on macOS
             cfg!(target_os = "macos"),
-            state.sendmsg_einval(),
+            state,
+            txtime,
         );
         let n = unsafe { libc::sendmsg(io.as_raw_fd(), &hdr, 0) };
         if n == -1 {
@@ -293,6 +356,8 @@ fn send(
                 io::ErrorKind::WouldBlock if sent != 0 => return Ok(sent),
                 io::ErrorKind::WouldBlock => return Err(e),
                 _ => {
+                    errqueue::drain(&io, path_mtu_errors);
+
                     // Other errors are ignored, since they will usually be handled
                     // by higher level retransmits and timeouts.
                     // - PermissionDenied errors have been observed due to iptable rules.
@@ -514,8 +579,9 @@ pub(crate) fn udp_state() -> UdpState {
     }
 }
 
-const CMSG_LEN: usize = 88;
+const CMSG_LEN: usize = 112;
 
+#[allow(clippy::too_many_arguments)]
 fn prepare_msg(
     transmit: &Transmit,
     dst_addr: &socket2::SockAddr,
@@ -524,7 +590,8 @@ fn prepare_msg(
     ctrl: &mut cmsg::Aligned<[u8; CMSG_LEN]>,
     #[allow(unused_variables)] // only used on FreeBSD & macOS
     encode_src_ip: bool,
-    sendmsg_einval: bool,
+    state: &UdpState,
+    txtime: bool,
 ) {
     iov.iov_base = transmit.contents.as_ptr() as *const _ as *mut _;
     iov.iov_len = transmit.contents.len();
@@ -545,18 +612,26 @@ fn prepare_msg(
     hdr.msg_controllen = CMSG_LEN as _;
     let mut encoder = unsafe { cmsg::Encoder::new(hdr) };
     let ecn = transmit.ecn.map_or(0, |x| x as libc::c_int);
+    let dscp = transmit.dscp.map_or(0, |x| libc::c_int::from(x & 0x3f));
+    let tos = dscp << 2 | ecn;
     if transmit.destination.is_ipv4() {
-        if !sendmsg_einval {
-            encoder.push(libc::IPPROTO_IP, libc::IP_TOS, ecn as IpTosTy);
+        if !state.sendmsg_einval() {
+            encoder.push(libc::IPPROTO_IP, libc::IP_TOS, tos as IpTosTy);
         }
     } else {
-        encoder.push(libc::IPPROTO_IPV6, libc::IPV6_TCLASS, ecn);
+        encoder.push(libc::IPPROTO_IPV6, libc::IPV6_TCLASS, tos);
     }
 
     if let Some(segment_size) = transmit.segment_size {
         gso::set_segment_size(&mut encoder, segment_size as u16);
     }
 
+    if let Some(departure_time) = transmit.departure_time {
+        if txtime {
+            txtime::set_departure_time(&mut encoder, departure_time);
+        }
+    }
+
     if let Some(ip) = &transmit.src_ip {
         match ip {
             IpAddr::V4(v4) => {
@@ -665,11 +740,21 @@ fn decode_recv(
         }
     }
 
-    let addr = match libc::c_int::from(name.ss_family) {
+    RecvMeta {
+        len,
+        stride,
+        addr: decode_sockaddr(&name),
+        ecn: EcnCodepoint::from_bits(ecn_bits),
+        dst_ip,
+    }
+}
+
+fn decode_sockaddr(name: &libc::sockaddr_storage) -> SocketAddr {
+    match libc::c_int::from(name.ss_family) {
         libc::AF_INET => {
             // Safety: if the ss_family field is AF_INET then storage must be a sockaddr_in.
             let addr: &libc::sockaddr_in =
-                unsafe { &*(&name as *const _ as *const libc::sockaddr_in) };
+                unsafe { &*(name as *const _ as *const libc::sockaddr_in) };
             SocketAddr::V4(SocketAddrV4::new(
                 Ipv4Addr::from(addr.sin_addr.s_addr.to_ne_bytes()),
                 u16::from_be(addr.sin_port),
@@ -678,7 +763,7 @@ fn decode_recv(
         libc::AF_INET6 => {
             // Safety: if the ss_family field is AF_INET6 then storage must be a sockaddr_in6.
             let addr: &libc::sockaddr_in6 =
-                unsafe { &*(&name as *const _ as *const libc::sockaddr_in6) };
+                unsafe { &*(name as *const _ as *const libc::sockaddr_in6) };
             SocketAddr::V6(SocketAddrV6::new(
                 Ipv6Addr::from(addr.sin6_addr.s6_addr),
                 u16::from_be(addr.sin6_port),
@@ -687,14 +772,6 @@ fn decode_recv(
             ))
         }
         _ => unreachable!(),
-    };
-
-    RecvMeta {
-        len,
-        stride,
-        addr,
-        ecn: EcnCodepoint::from_bits(ecn_bits),
-        dst_ip,
     }
 }
 
@@ -752,6 +829,200 @@ mod gso {
     }
 }
 
+#[cfg(target_os = "linux")]
+mod txtime {
+    use std::time::Instant;
+
+    use super::*;
+
+    /// Enables `SCM_TXTIME` control messages, expressed in `CLOCK_MONOTONIC` time
+    pub(crate) fn enable(socket: &impl AsRawFd) -> io::Result<()> {
+        let config = libc::sock_txtime {
+            clockid: libc::CLOCK_MONOTONIC,
+            flags: 0,
+        };
+        set_socket_option(socket, libc::SOL_SOCKET, libc::SO_TXTIME, config)
+    }
+
+    pub(crate) fn set_departure_time(encoder: &mut cmsg::Encoder, departure_time: Instant) {
+        // `Instant` is opaque, so translate it to the kernel's clock relative to the present
+        let delay = departure_time.saturating_duration_since(Instant::now());
+        let mut now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) } != 0 {
+            return;
+        }
+        let departure_nanos = (now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64)
+            .saturating_add(delay.as_nanos() as u64);
+        encoder.push(libc::SOL_SOCKET, libc::SCM_TXTIME, departure_nanos);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod txtime {
+    use std::time::Instant;
+
+    use super::*;
+
+    pub(super) fn enable(_socket: &impl AsRawFd) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub(super) fn set_departure_time(_encoder: &mut cmsg::Encoder, _departure_time: Instant) {
+        panic!("Setting a departure time is not supported on current platform");
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod errqueue {
+    use bytes::Bytes;
+
+    use super::*;
+
+    /// Maximum number of unretrieved path MTU errors to buffer
+    const MAX_QUEUED: usize = 64;
+
+    /// Amount of each quoted datagram to retain, enough to identify the QUIC connection
+    const QUOTE_LEN: usize = 64;
+
+    const IPV4_UDP_OVERHEAD: u32 = 20 + 8;
+    const IPV6_UDP_OVERHEAD: u32 = 40 + 8;
+
+    pub(super) fn enable(io: SockRef<'_>) -> io::Result<()> {
+        let addr = io.local_addr()?;
+        let is_ipv4 = addr.family() == libc::AF_INET as libc::sa_family_t;
+        if is_ipv4 || !io.only_v6()? {
+            set_socket_option(&*io, libc::IPPROTO_IP, libc::IP_RECVERR, OPTION_ON)?;
+        }
+        if !is_ipv4 {
+            set_socket_option(&*io, libc::IPPROTO_IPV6, libc::IPV6_RECVERR, OPTION_ON)?;
+        }
+        Ok(())
+    }
+
+    /// Read everything from the socket's error queue, retaining path MTU errors
+    ///
+    /// Returns whether anything was read.
+    pub(super) fn drain(io: &socket2::Socket, errors: &Mutex<VecDeque<PathMtuError>>) -> bool {
+        let mut drained = false;
+        loop {
+            let mut buf = [0u8; QUOTE_LEN];
+            let mut name = MaybeUninit::<libc::sockaddr_storage>::zeroed();
+            let mut ctrl = cmsg::Aligned(MaybeUninit::<[u8; CMSG_LEN]>::uninit());
+            let mut hdr = unsafe { mem::zeroed::<libc::msghdr>() };
+            prepare_recv(
+                &mut IoSliceMut::new(&mut buf),
+                &mut name,
+                &mut ctrl,
+                &mut hdr,
+            );
+            let n = unsafe {
+                libc::recvmsg(
+                    io.as_raw_fd(),
+                    &mut hdr,
+                    libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT,
+                )
+            };
+            if n == -1 {
+                if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return drained;
+            }
+            drained = true;
+
+            let error = match decode(&name, &hdr, &buf[..n as usize]) {
+                Some(error) => error,
+                None => continue,
+            };
+            tracing::debug!(
+                "path MTU to {} reported as {}",
+                error.destination,
+                error.max_udp_payload_size
+            );
+            let mut errors = errors.lock().unwrap();
+            if errors.len() == MAX_QUEUED {
+                errors.pop_front();
+            }
+            errors.push_back(error);
+        }
+    }
+
+    fn decode(
+        name: &MaybeUninit<libc::sockaddr_storage>,
+        hdr: &libc::msghdr,
+        contents: &[u8],
+    ) -> Option<PathMtuError> {
+        if hdr.msg_namelen == 0 {
+            return None;
+        }
+        // Safety: `name` is zero-initialized, and possibly overwritten by the kernel
+        let name = unsafe { name.assume_init_ref() };
+        if name.ss_family != libc::AF_INET as libc::sa_family_t
+            && name.ss_family != libc::AF_INET6 as libc::sa_family_t
+        {
+            return None;
+        }
+        let destination = decode_sockaddr(name);
+        for cmsg in unsafe { cmsg::Iter::new(hdr) } {
+            if !matches!(
+                (cmsg.cmsg_level, cmsg.cmsg_type),
+                (libc::IPPROTO_IP, libc::IP_RECVERR) | (libc::IPPROTO_IPV6, libc::IPV6_RECVERR)
+            ) {
+                continue;
+            }
+            // The error is followed by the address of the node which reported it, so can't be
+            // read with `cmsg::decode`.
+            #[allow(clippy::unnecessary_cast)] // cmsg.cmsg_len defined as size_t
+            if (cmsg.cmsg_len as usize)
+                < unsafe { libc::CMSG_LEN(mem::size_of::<libc::sock_extended_err>() as _) as usize }
+            {
+                continue;
+            }
+            let err = unsafe {
+                ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err)
+            };
+            if err.ee_errno != libc::EMSGSIZE as u32 {
+                continue;
+            }
+            // `ee_info` holds the MTU at the IP layer
+            let overhead = match err.ee_origin {
+                libc::SO_EE_ORIGIN_ICMP => IPV4_UDP_OVERHEAD,
+                libc::SO_EE_ORIGIN_ICMP6 => IPV6_UDP_OVERHEAD,
+                libc::SO_EE_ORIGIN_LOCAL => match destination {
+                    SocketAddr::V6(addr) if addr.ip().to_ipv4_mapped().is_none() => {
+                        IPV6_UDP_OVERHEAD
+                    }
+                    _ => IPV4_UDP_OVERHEAD,
+                },
+                _ => continue,
+            };
+            let max_udp_payload_size = err.ee_info.checked_sub(overhead)?;
+            return Some(PathMtuError {
+                destination,
+                max_udp_payload_size: max_udp_payload_size.min(u16::MAX.into()) as u16,
+                contents: Bytes::copy_from_slice(contents),
+            });
+        }
+        None
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod errqueue {
+    use super::*;
+
+    pub(super) fn enable(_io: SockRef<'_>) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub(super) fn drain(_io: &socket2::Socket, _errors: &Mutex<VecDeque<PathMtuError>>) -> bool {
+        false
+    }
+}
+
 #[cfg(target_os = "linux")]
 mod gro {
     use super::*;
@@ -778,11 +1049,11 @@ mod gro {
     }
 }
 
-fn set_socket_option(
+fn set_socket_option<T>(
     socket: &impl AsRawFd,
     level: libc::c_int,
     name: libc::c_int,
-    value: libc::c_int,
+    value: T,
 ) -> Result<(), io::Error> {
     let rc = unsafe {
         libc::setsockopt(
@@ -808,3 +1079,52 @@ mod gro {
         1
     }
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::net::UdpSocket;
+
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn local_path_mtu_error() {
+        let socket = match UdpSocket::bind("[::1]:0") {
+            Ok(socket) => socket,
+            // IPv6 is unavailable
+            Err(_) => return,
+        };
+        let destination = UdpSocket::bind("[::1]:0").unwrap().local_addr().unwrap();
+        UdpSocketState::configure((&socket).into()).unwrap();
+        UdpSocketState::enable_path_mtu_errors((&socket).into()).unwrap();
+        // Loopback's MTU exceeds the largest UDP datagram, so lower the socket's instead
+        set_socket_option(
+            &socket,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_DO,
+        )
+        .unwrap();
+        set_socket_option(&socket, libc::IPPROTO_IPV6, libc::IPV6_MTU, 1280).unwrap();
+
+        let state = UdpSocketState::new();
+        let transmit = Transmit {
+            destination,
+            ecn: None,
+            dscp: None,
+            contents: Bytes::from(vec![0xab; 2000]),
+            segment_size: None,
+            src_ip: None,
+            departure_time: None,
+        };
+        // The oversized datagram is dropped
+        let sent = state.send((&socket).into(), &UdpState::new(), &[transmit]);
+        assert_eq!(sent.unwrap(), 1);
+
+        let error = state.path_mtu_error().expect("EMSGSIZE was reported");
+        assert_eq!(error.destination, destination);
+        assert_eq!(error.max_udp_payload_size, 1280 - 40 - 8);
+        assert!(state.path_mtu_error().is_none());
+    }
+}
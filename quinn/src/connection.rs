@@ -1,18 +1,23 @@
 use std::{
     any::Any,
+    collections::VecDeque,
     fmt,
     future::Future,
     net::{IpAddr, SocketAddr},
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Weak},
     task::{Context, Poll, Waker},
     time::{Duration, Instant},
 };
 
+#[cfg(feature = "metrics")]
+use crate::metrics::{ConnectionMetrics, MetricsConfig};
 use crate::runtime::{AsyncTimer, Runtime};
 use bytes::Bytes;
 use pin_project_lite::pin_project;
-use proto::{ConnectionError, ConnectionHandle, ConnectionStats, Dir, StreamEvent, StreamId};
+use proto::{
+    ConnectionError, ConnectionHandle, ConnectionStats, Dir, SendStreamStats, StreamEvent, StreamId,
+};
 use rustc_hash::FxHashMap;
 use thiserror::Error;
 use tokio::sync::{futures::Notified, mpsc, oneshot, Notify};
@@ -25,7 +30,7 @@ use crate::{
     send_stream::{SendStream, WriteError},
     ConnectionEvent, EndpointEvent, VarInt,
 };
-use proto::congestion::Controller;
+use proto::congestion::{Controller, ControllerFactory};
 
 /// In-progress connection attempt future
 #[derive(Debug)]
@@ -44,6 +49,7 @@ impl Connecting {
         conn_events: mpsc::UnboundedReceiver<ConnectionEvent>,
         udp_state: Arc<UdpState>,
         runtime: Arc<dyn Runtime>,
+        #[cfg(feature = "metrics")] metrics: Option<Arc<MetricsConfig>>,
     ) -> Self {
         let (on_handshake_data_send, on_handshake_data_recv) = oneshot::channel();
         let (on_connected_send, on_connected_recv) = oneshot::channel();
@@ -56,6 +62,8 @@ impl Connecting {
             on_connected_send,
             udp_state,
             runtime.clone(),
+            #[cfg(feature = "metrics")]
+            metrics.map(|config| ConnectionMetrics::new(config, Instant::now())),
         );
 
         runtime.spawn(Box::pin(ConnectionDriver(conn.clone())));
@@ -158,6 +166,10 @@ impl Connecting {
         inner.inner.local_ip()
     }
 
+    pub(crate) fn weak_handle(&self) -> WeakConnectionHandle {
+        self.conn.as_ref().unwrap().weak_handle()
+    }
+
     /// The peer's UDP address.
     ///
     /// Will panic if called after `poll` has returned `Ready`.
@@ -234,6 +246,10 @@ impl Future for ConnectionDriver {
         keep_going |= conn.drive_timer(cx);
         conn.forward_endpoint_events();
         conn.forward_app_events(&self.0.shared);
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = conn.metrics.as_mut() {
+            metrics.poll(Instant::now(), &mut conn.inner);
+        }
 
         if !conn.inner.is_drained() {
             if keep_going {
@@ -339,6 +355,55 @@ impl Connection {
             .clone()
     }
 
+    /// Wait for the peer to report that it is blocked by flow control or the stream count limit
+    ///
+    /// Lets the application raise limits on demand, e.g. with
+    /// [`set_max_concurrent_bi_streams()`](Self::set_max_concurrent_bi_streams) or
+    /// [`RecvStream::set_receive_window()`](crate::RecvStream::set_receive_window), rather than
+    /// provisioning generous ones up front. Reports which were overtaken by a raised limit before
+    /// they arrived are skipped, and only the most recent reports are kept until they are waited
+    /// for.
+    pub async fn peer_blocked(&self) -> Result<PeerBlocked, ConnectionError> {
+        loop {
+            let notified = {
+                let mut conn = self.0.state.lock("peer_blocked");
+                if let Some(blocked) = conn.peer_blocked.pop_front() {
+                    return Ok(blocked);
+                }
+                if let Some(error) = conn.error.as_ref() {
+                    return Err(error.clone());
+                }
+                // Constructed while the lock is held so that no report can be missed
+                self.0.shared.peer_blocked.notified()
+            };
+            notified.await;
+        }
+    }
+
+    /// Wait for the endpoint to start draining, see [`Endpoint::drain()`]
+    ///
+    /// Lets the application wind the connection down gracefully before the endpoint's deadline,
+    /// e.g. by telling the peer to go away at the application protocol level. Fails if the
+    /// connection is lost first.
+    ///
+    /// [`Endpoint::drain()`]: crate::Endpoint::drain
+    pub async fn endpoint_draining(&self) -> Result<(), ConnectionError> {
+        loop {
+            let notified = {
+                let conn = self.0.state.lock("endpoint_draining");
+                if conn.endpoint_draining {
+                    return Ok(());
+                }
+                if let Some(error) = conn.error.as_ref() {
+                    return Err(error.clone());
+                }
+                // Constructed while the lock is held so that the signal can't be missed
+                self.0.shared.endpoint_draining.notified()
+            };
+            notified.await;
+        }
+    }
+
     /// If the connection is closed, the reason why.
     ///
     /// Returns `None` if the connection is still open.
@@ -469,6 +534,53 @@ impl Connection {
             .clone_box()
     }
 
+    /// Replace the congestion controller with one built by `factory`
+    ///
+    /// The new controller starts from the current congestion window. See
+    /// [`proto::Connection::set_congestion_controller`].
+    pub fn set_congestion_controller(
+        &self,
+        factory: impl ControllerFactory + Send + Sync + 'static,
+    ) {
+        let mut conn = self.0.state.lock("set_congestion_controller");
+        conn.inner
+            .set_congestion_controller(Instant::now(), factory);
+        conn.wake();
+    }
+
+    /// Limit the rate at which stream data is sent to `rate` bytes per second
+    ///
+    /// See [`proto::Connection::set_send_rate_limit`].
+    pub fn set_send_rate_limit(&self, rate: Option<u64>) {
+        let mut conn = self.0.state.lock("set_send_rate_limit");
+        conn.inner.set_send_rate_limit(rate);
+        conn.wake();
+    }
+
+    /// The current limit on the rate at which stream data is sent, in bytes per second
+    pub fn send_rate_limit(&self) -> Option<u64> {
+        self.0.state.lock("send_rate_limit").inner.send_rate_limit()
+    }
+
+    /// Set this connection's share of its endpoint's egress bandwidth
+    ///
+    /// While [`Endpoint::set_max_egress_rate()`](crate::Endpoint::set_max_egress_rate) limits the
+    /// endpoint's aggregate send rate, busy connections divide it in proportion to their weights.
+    /// Initially [`ServerConfig::egress_weight()`](crate::ServerConfig::egress_weight) for incoming
+    /// connections, or 1 for outgoing ones.
+    pub fn set_egress_weight(&self, weight: u32) {
+        self.0
+            .state
+            .lock("set_egress_weight")
+            .inner
+            .set_egress_weight(weight);
+    }
+
+    /// This connection's share of its endpoint's egress bandwidth
+    pub fn egress_weight(&self) -> u32 {
+        self.0.state.lock("egress_weight").inner.egress_weight()
+    }
+
     /// Parameters negotiated during the handshake
     ///
     /// Guaranteed to return `Some` on fully established connections or after
@@ -623,6 +735,8 @@ fn poll_open<'a>(
         drop(state); // Release the lock so clone can take it
         return Poll::Ready(Ok((conn.clone(), id, is_0rtt)));
     }
+    // Let the driver report that we're blocked
+    state.wake();
     loop {
         match notify.as_mut().poll(ctx) {
             // `state` lock ensures we didn't race with readiness
@@ -735,6 +849,51 @@ impl Future for ReadDatagram<'_> {
     }
 }
 
+/// A handle to one of an endpoint's connections, see [`Endpoint::connections()`]
+///
+/// Unlike [`Connection`], doesn't keep the connection alive. Methods return `None` once it's been
+/// dropped.
+///
+/// [`Endpoint::connections()`]: crate::Endpoint::connections
+#[derive(Debug, Clone)]
+pub struct WeakConnectionHandle {
+    conn: Weak<ConnectionInner>,
+    stable_id: usize,
+}
+
+impl WeakConnectionHandle {
+    /// A stable identifier for the connection, as returned by [`Connection::stable_id()`]
+    pub fn stable_id(&self) -> usize {
+        self.stable_id
+    }
+
+    /// Whether the connection hasn't been dropped yet
+    pub fn is_alive(&self) -> bool {
+        self.conn.strong_count() > 0
+    }
+
+    /// The peer's UDP address
+    pub fn remote_address(&self) -> Option<SocketAddr> {
+        let conn = self.conn.upgrade()?;
+        let address = conn.state.lock("remote_address").inner.remote_address();
+        Some(address)
+    }
+
+    /// Parameters negotiated during the handshake, see [`Connection::handshake_data()`]
+    pub fn handshake_data(&self) -> Option<Box<dyn Any>> {
+        let conn = self.conn.upgrade()?;
+        let state = conn.state.lock("handshake_data");
+        state.inner.crypto_session().handshake_data()
+    }
+
+    /// Connection statistics, see [`Connection::stats()`]
+    pub fn stats(&self) -> Option<ConnectionStats> {
+        let conn = self.conn.upgrade()?;
+        let stats = conn.state.lock("stats").inner.stats();
+        Some(stats)
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct ConnectionRef(Arc<ConnectionInner>);
 
@@ -749,6 +908,7 @@ impl ConnectionRef {
         on_connected: oneshot::Sender<bool>,
         udp_state: Arc<UdpState>,
         runtime: Arc<dyn Runtime>,
+        #[cfg(feature = "metrics")] metrics: Option<ConnectionMetrics>,
     ) -> Self {
         Self(Arc::new(ConnectionInner {
             state: Mutex::new(State {
@@ -766,10 +926,16 @@ impl ConnectionRef {
                 blocked_readers: FxHashMap::default(),
                 finishing: FxHashMap::default(),
                 stopped: FxHashMap::default(),
+                finished_stats: FxHashMap::default(),
+                peer_blocked: VecDeque::new(),
+                endpoint_draining: false,
+                egress_blocked: false,
                 error: None,
                 ref_count: 0,
                 udp_state,
                 runtime,
+                #[cfg(feature = "metrics")]
+                metrics,
             }),
             shared: Shared::default(),
         }))
@@ -778,6 +944,13 @@ impl ConnectionRef {
     fn stable_id(&self) -> usize {
         &*self.0 as *const _ as usize
     }
+
+    fn weak_handle(&self) -> WeakConnectionHandle {
+        WeakConnectionHandle {
+            conn: Arc::downgrade(&self.0),
+            stable_id: self.stable_id(),
+        }
+    }
 }
 
 impl Clone for ConnectionRef {
@@ -824,6 +997,10 @@ pub(crate) struct Shared {
     /// Notified when the peer has initiated a new stream
     stream_incoming: [Notify; 2],
     datagrams: Notify,
+    /// Notified when the peer reports that it is blocked
+    peer_blocked: Notify,
+    /// Notified when the endpoint starts draining
+    endpoint_draining: Notify,
     closed: Notify,
 }
 
@@ -842,20 +1019,35 @@ pub(crate) struct State {
     pub(crate) blocked_readers: FxHashMap<StreamId, Waker>,
     pub(crate) finishing: FxHashMap<StreamId, oneshot::Sender<Option<WriteError>>>,
     pub(crate) stopped: FxHashMap<StreamId, Waker>,
+    /// Final statistics of finished streams whose completion was awaited, until they're dropped
+    pub(crate) finished_stats: FxHashMap<StreamId, SendStreamStats>,
+    /// Reports from the peer of being blocked, not yet taken by the application
+    peer_blocked: VecDeque<PeerBlocked>,
+    /// Whether the endpoint has started draining
+    endpoint_draining: bool,
+    /// Whether the endpoint has asked for no more datagrams until its egress queue drains
+    egress_blocked: bool,
     /// Always set to Some before the connection becomes drained
     pub(crate) error: Option<ConnectionError>,
     /// Number of live handles that can be used to initiate or handle I/O; excludes the driver
     ref_count: usize,
     udp_state: Arc<UdpState>,
     runtime: Arc<dyn Runtime>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<ConnectionMetrics>,
 }
 
 impl State {
     fn drive_transmit(&mut self) -> bool {
+        if self.egress_blocked {
+            // Woken when the endpoint unblocks us
+            return false;
+        }
         let now = Instant::now();
         let mut transmits = 0;
 
         let max_datagrams = self.udp_state.max_gso_segments();
+        let weight = self.inner.egress_weight();
 
         while let Some(t) = self.inner.poll_transmit(now, max_datagrams) {
             transmits += match t.segment_size {
@@ -865,7 +1057,7 @@ impl State {
             // If the endpoint driver is gone, noop.
             let _ = self
                 .endpoint_events
-                .send((self.handle, EndpointEvent::Transmit(t)));
+                .send((self.handle, EndpointEvent::Transmit(t, weight)));
 
             if transmits >= MAX_TRANSMIT_DATAGRAMS {
                 // TODO: What isn't ideal here yet is that if we don't poll all
@@ -896,9 +1088,18 @@ impl State {
     ) -> Result<(), ConnectionError> {
         loop {
             match self.conn_events.poll_recv(cx) {
-                Poll::Ready(Some(ConnectionEvent::Ping)) => {
+                Poll::Ready(Some(ConnectionEvent::Rebind { txtime })) => {
+                    self.inner.set_departure_times_supported(txtime);
+                    // Generate some activity so peers notice the rebind
                     self.inner.ping();
                 }
+                Poll::Ready(Some(ConnectionEvent::EgressBlocked(blocked))) => {
+                    self.egress_blocked = blocked;
+                }
+                Poll::Ready(Some(ConnectionEvent::EndpointDraining)) => {
+                    self.endpoint_draining = true;
+                    shared.endpoint_draining.notify_waiters();
+                }
                 Poll::Ready(Some(ConnectionEvent::Proto(event))) => {
                     self.inner.handle_event(event);
                 }
@@ -930,6 +1131,10 @@ impl State {
                 }
                 Connected => {
                     self.connected = true;
+                    #[cfg(feature = "metrics")]
+                    if let Some(metrics) = self.metrics.as_ref() {
+                        metrics.connected(Instant::now());
+                    }
                     if let Some(x) = self.on_connected.take() {
                         // We don't care if the on-connected future was dropped
                         let _ = x.send(self.inner.accepted_0rtt());
@@ -963,6 +1168,9 @@ impl State {
                 }
                 Stream(StreamEvent::Finished { id }) => {
                     if let Some(finishing) = self.finishing.remove(&id) {
+                        if let Ok(stats) = self.inner.send_stream(id).stats() {
+                            self.finished_stats.insert(id, stats);
+                        }
                         // If the finishing stream was already dropped, there's nothing more to do.
                         let _ = finishing.send(None);
                     }
@@ -981,10 +1189,27 @@ impl State {
                         writer.wake();
                     }
                 }
+                Stream(StreamEvent::DataBlocked { limit }) => {
+                    self.on_peer_blocked(PeerBlocked::Data { limit }, shared);
+                }
+                Stream(StreamEvent::StreamDataBlocked { id, limit }) => {
+                    self.on_peer_blocked(PeerBlocked::StreamData { id, limit }, shared);
+                }
+                Stream(StreamEvent::StreamsBlocked { dir, limit }) => {
+                    self.on_peer_blocked(PeerBlocked::Streams { dir, limit }, shared);
+                }
             }
         }
     }
 
+    fn on_peer_blocked(&mut self, blocked: PeerBlocked, shared: &Shared) {
+        if self.peer_blocked.len() == MAX_PEER_BLOCKED {
+            self.peer_blocked.pop_front();
+        }
+        self.peer_blocked.push_back(blocked);
+        shared.peer_blocked.notify_waiters();
+    }
+
     fn drive_timer(&mut self, cx: &mut Context) -> bool {
         // Check whether we need to (re)set the timer. If so, we must poll again to ensure the
         // timer is registered with the runtime (and check whether it's already
@@ -1059,6 +1284,8 @@ impl State {
         shared.stream_incoming[Dir::Uni as usize].notify_waiters();
         shared.stream_incoming[Dir::Bi as usize].notify_waiters();
         shared.datagrams.notify_waiters();
+        shared.peer_blocked.notify_waiters();
+        shared.endpoint_draining.notify_waiters();
         for (_, x) in self.finishing.drain() {
             let _ = x.send(Some(WriteError::ConnectionLost(reason.clone())));
         }
@@ -1096,6 +1323,10 @@ impl State {
 
 impl Drop for State {
     fn drop(&mut self) {
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.take() {
+            metrics.finish(&mut self.inner);
+        }
         if !self.inner.is_drained() {
             // Ensure the endpoint can tidy up
             let _ = self.endpoint_events.send((
@@ -1132,6 +1363,41 @@ pub enum SendDatagramError {
     ConnectionLost(#[from] ConnectionError),
 }
 
+/// A report from the peer that it is blocked, see [`Connection::peer_blocked()`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PeerBlocked {
+    /// The peer has data to send but is blocked by connection-level flow control
+    ///
+    /// The limit may be raised with [`Connection::set_receive_window()`].
+    Data {
+        /// The connection-level limit at which the peer is blocked
+        limit: u64,
+    },
+    /// The peer has data to send on a stream but is blocked by stream-level flow control
+    ///
+    /// The limit may be raised with
+    /// [`RecvStream::set_receive_window()`](crate::RecvStream::set_receive_window).
+    StreamData {
+        /// Which stream is blocked
+        id: StreamId,
+        /// The stream-level limit at which the peer is blocked
+        limit: u64,
+    },
+    /// The peer wants to open a stream but is blocked by the stream count limit
+    ///
+    /// The limit may be raised with [`Connection::set_max_concurrent_bi_streams()`] or
+    /// [`Connection::set_max_concurrent_uni_streams()`].
+    Streams {
+        /// Directionality of the streams the peer wants to open
+        dir: Dir,
+        /// The number of streams the peer may open in total
+        limit: u64,
+    },
+}
+
+/// Number of reports from the peer of being blocked kept until the application takes them
+const MAX_PEER_BLOCKED: usize = 32;
+
 /// The maximum amount of datagrams which will be produced in a single `drive_transmit` call
 ///
 /// This limits the amount of CPU resources consumed by datagram generation,
@@ -0,0 +1,297 @@
+use std::{
+    collections::VecDeque,
+    mem,
+    time::{Duration, Instant},
+};
+
+use proto::ConnectionHandle;
+use rustc_hash::FxHashMap;
+
+/// Limits the aggregate rate at which an endpoint sends, dividing it among connections
+///
+/// While a rate is set, datagrams are queued per connection and released by deficit round robin:
+/// each time a connection's turn comes around it may send a quantum of bytes in proportion to its
+/// weight, so that busy connections share the rate according to their weights while idle ones
+/// leave their share to the others. Datagrams may be released whenever the budget isn't
+/// exhausted; any excess is paid back before anything more is released.
+///
+/// Connections whose queue grows beyond a limit are reported as blocked, and should stop
+/// producing datagrams until reported as unblocked once their queue has drained by half.
+#[derive(Debug)]
+pub(crate) struct EgressBudget {
+    /// Bytes per second, or `None` if unlimited
+    rate: Option<u64>,
+    /// Bytes which may be released, negative while in debt
+    tokens: f64,
+    /// When `tokens` was last brought up to date, if ever
+    updated: Option<Instant>,
+    /// Queued datagrams of each connection that has any
+    queues: FxHashMap<ConnectionHandle, Queue>,
+    /// Connections with queued datagrams, in the order they are served
+    active: VecDeque<ConnectionHandle>,
+    /// Blocked connections whose queue has since drained, not yet reported
+    unblocked: Vec<ConnectionHandle>,
+}
+
+impl EgressBudget {
+    pub(crate) fn new() -> Self {
+        Self {
+            rate: None,
+            tokens: 0.0,
+            updated: None,
+            queues: FxHashMap::default(),
+            active: VecDeque::new(),
+            unblocked: Vec::new(),
+        }
+    }
+
+    pub(crate) fn rate(&self) -> Option<u64> {
+        self.rate
+    }
+
+    /// Limit the aggregate send rate to `rate` bytes per second, or remove the limit
+    pub(crate) fn set_rate(&mut self, rate: Option<u64>) {
+        if self.rate.is_none() {
+            self.tokens = rate.map_or(0.0, burst);
+        }
+        self.rate = rate;
+        if let Some(rate) = rate {
+            self.tokens = self.tokens.min(burst(rate));
+        }
+    }
+
+    /// Whether datagrams must be queued with [`push`](Self::push) rather than sent directly
+    pub(crate) fn is_limited(&self) -> bool {
+        self.rate.is_some() || !self.active.is_empty()
+    }
+
+    /// Queue `transmit` on behalf of the connection `handle`, which has weight `weight`
+    ///
+    /// Returns whether the connection has just become blocked by the size of its queue.
+    pub(crate) fn push(
+        &mut self,
+        handle: ConnectionHandle,
+        weight: u32,
+        transmit: udp::Transmit,
+    ) -> bool {
+        let quantum = QUANTUM * u64::from(weight.max(1));
+        let queue = self.queues.entry(handle).or_insert_with(|| Queue {
+            quantum,
+            deficit: quantum,
+            bytes: 0,
+            blocked: false,
+            transmits: VecDeque::new(),
+        });
+        queue.quantum = quantum;
+        if queue.transmits.is_empty() {
+            self.active.push_back(handle);
+        }
+        queue.bytes += transmit.contents.len() as u64;
+        queue.transmits.push_back(transmit);
+        if queue.blocked || queue.bytes < QUEUE_LIMIT {
+            return false;
+        }
+        queue.blocked = true;
+        true
+    }
+
+    /// Connections reported blocked by [`push`](Self::push) whose queues have since drained
+    pub(crate) fn take_unblocked(&mut self) -> Vec<ConnectionHandle> {
+        mem::take(&mut self.unblocked)
+    }
+
+    /// Move the datagrams the budget allows at `now` to `outgoing`
+    ///
+    /// Returns when more may be released, if anything is left queued and the rate isn't zero.
+    pub(crate) fn poll(
+        &mut self,
+        now: Instant,
+        outgoing: &mut VecDeque<udp::Transmit>,
+    ) -> Option<Instant> {
+        self.refill(now);
+        while let Some(&handle) = self.active.front() {
+            match self.rate {
+                Some(0) => return None,
+                Some(rate) if self.tokens < 0.0 => {
+                    // Round up, so that the debt has certainly been paid off by then
+                    let micros = (-self.tokens * 1_000_000.0 / rate as f64).ceil();
+                    return Some(now + Duration::from_micros(micros as u64));
+                }
+                _ => {}
+            }
+
+            let queue = self.queues.get_mut(&handle).unwrap();
+            let size = queue.transmits.front().unwrap().contents.len() as u64;
+            if queue.deficit < size {
+                // The connection's turn is over, and it has earned another quantum for its next
+                queue.deficit += queue.quantum;
+                self.active.rotate_left(1);
+                continue;
+            }
+
+            queue.deficit -= size;
+            queue.bytes -= size;
+            if self.rate.is_some() {
+                self.tokens -= size as f64;
+            }
+            outgoing.push_back(queue.transmits.pop_front().unwrap());
+            if queue.blocked && queue.bytes <= QUEUE_LIMIT / 2 {
+                queue.blocked = false;
+                self.unblocked.push(handle);
+            }
+            if queue.transmits.is_empty() {
+                // Idle connections don't save up their share
+                self.queues.remove(&handle);
+                self.active.pop_front();
+            }
+        }
+        None
+    }
+
+    /// Add the bytes which accrued up to `now`
+    fn refill(&mut self, now: Instant) {
+        if let (Some(rate), Some(updated)) = (self.rate, self.updated) {
+            let elapsed = now.saturating_duration_since(updated).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate as f64).min(burst(rate));
+        }
+        self.updated = Some(now);
+    }
+}
+
+#[derive(Debug)]
+struct Queue {
+    /// Bytes the connection may send per turn
+    quantum: u64,
+    /// Bytes the connection may still send in its current turn
+    deficit: u64,
+    /// Total size of `transmits`
+    bytes: u64,
+    /// Whether the connection was reported blocked by the size of its queue
+    blocked: bool,
+    transmits: VecDeque<udp::Transmit>,
+}
+
+/// Largest number of bytes which may be released at once after idling at `rate` bytes per second
+fn burst(rate: u64) -> f64 {
+    rate as f64 * BURST_INTERVAL.as_secs_f64()
+}
+
+/// Time over which unused budget may accumulate
+const BURST_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Bytes a connection of weight 1 may send per turn
+const QUANTUM: u64 = 1500;
+
+/// Bytes a connection may have queued before it is blocked
+const QUEUE_LIMIT: u64 = 256 * 1024;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transmit(size: usize) -> udp::Transmit {
+        udp::Transmit {
+            destination: "[::1]:4433".parse().unwrap(),
+            ecn: None,
+            dscp: None,
+            contents: vec![0; size].into(),
+            segment_size: None,
+            src_ip: None,
+            departure_time: None,
+        }
+    }
+
+    #[test]
+    fn shares_by_weight() {
+        let now = Instant::now();
+        let mut budget = EgressBudget::new();
+        budget.set_rate(Some(1_000_000));
+        for _ in 0..100 {
+            assert!(!budget.push(ConnectionHandle(0), 1, transmit(1000)));
+            assert!(!budget.push(ConnectionHandle(1), 3, transmit(1001)));
+        }
+
+        let mut outgoing = VecDeque::new();
+        let mut time = now;
+        while outgoing.len() < 80 {
+            time = budget.poll(time, &mut outgoing).unwrap();
+        }
+        // 80kB at 1MB/s, less the initial burst
+        assert_eq!(time - now, Duration::from_micros(70_060));
+        // The connection with weight 3 gets three times the bandwidth of the other
+        let heavy = outgoing
+            .iter()
+            .take(80)
+            .filter(|t| t.contents.len() == 1001)
+            .count();
+        assert!((57..=63).contains(&heavy), "{heavy}");
+    }
+
+    #[test]
+    fn limits_rate() {
+        let now = Instant::now();
+        let mut budget = EgressBudget::new();
+        assert!(!budget.push(ConnectionHandle(0), 1, transmit(1000)));
+        let mut outgoing = VecDeque::new();
+        // Without a rate, everything is released at once
+        assert_eq!(budget.poll(now, &mut outgoing), None);
+        assert_eq!(outgoing.len(), 1);
+
+        budget.set_rate(Some(100_000));
+        for _ in 0..10 {
+            assert!(!budget.push(ConnectionHandle(0), 1, transmit(1000)));
+        }
+        outgoing.clear();
+        // The initial burst of 1000 bytes is released, then one datagram per 10ms
+        let next = budget.poll(now, &mut outgoing);
+        assert_eq!(outgoing.len(), 2);
+        assert_eq!(next, Some(now + Duration::from_millis(10)));
+        let next = budget.poll(next.unwrap(), &mut outgoing);
+        assert_eq!(outgoing.len(), 3);
+        assert_eq!(next, Some(now + Duration::from_millis(20)));
+
+        // A zero rate holds everything back until it is raised again
+        budget.set_rate(Some(0));
+        assert_eq!(
+            budget.poll(now + Duration::from_secs(1), &mut outgoing),
+            None
+        );
+        assert_eq!(outgoing.len(), 3);
+        budget.set_rate(None);
+        assert_eq!(
+            budget.poll(now + Duration::from_secs(1), &mut outgoing),
+            None
+        );
+        assert_eq!(outgoing.len(), 10);
+    }
+
+    #[test]
+    fn blocks_full_queues() {
+        let now = Instant::now();
+        let mut budget = EgressBudget::new();
+        budget.set_rate(Some(1_000_000));
+        let datagrams = ((QUEUE_LIMIT + 999) / 1000) as usize;
+        for _ in 0..datagrams - 1 {
+            assert!(!budget.push(ConnectionHandle(0), 1, transmit(1000)));
+        }
+        // The connection is reported blocked once, when its queue fills up
+        assert!(budget.push(ConnectionHandle(0), 1, transmit(1000)));
+        assert!(!budget.push(ConnectionHandle(0), 1, transmit(1000)));
+        assert!(!budget.push(ConnectionHandle(1), 1, transmit(1000)));
+
+        // And unblocked once half of it has been released
+        let mut outgoing = VecDeque::new();
+        let mut time = now;
+        while outgoing.len() < datagrams / 2 {
+            assert!(budget.take_unblocked().is_empty());
+            time = budget.poll(time, &mut outgoing).unwrap();
+        }
+        let mut unblocked = budget.take_unblocked();
+        while unblocked.is_empty() {
+            time = budget.poll(time, &mut outgoing).unwrap();
+            unblocked = budget.take_unblocked();
+        }
+        assert_eq!(unblocked, [ConnectionHandle(0)]);
+        assert!(outgoing.len() >= datagrams / 2);
+    }
+}
@@ -12,19 +12,25 @@ use std::{
     time::Instant,
 };
 
-use crate::runtime::{default_runtime, AsyncUdpSocket, Runtime};
+#[cfg(feature = "metrics")]
+use crate::metrics::{EndpointMetrics, MetricsConfig};
+use crate::runtime::{default_runtime, AsyncTimer, AsyncUdpSocket, Runtime};
 use bytes::{Bytes, BytesMut};
 use pin_project_lite::pin_project;
 use proto::{
-    self as proto, ClientConfig, ConnectError, ConnectionHandle, DatagramEvent, ServerConfig,
+    self as proto, ClientConfig, ConnectError, ConnectionHandle, DatagramEvent, EndpointStats,
+    ServerConfig,
 };
 use rustc_hash::FxHashMap;
 use tokio::sync::{futures::Notified, mpsc, Notify};
 use udp::{RecvMeta, UdpState, BATCH_SIZE};
 
 use crate::{
-    connection::Connecting, work_limiter::WorkLimiter, ConnectionEvent, EndpointConfig,
-    EndpointEvent, VarInt, IO_LOOP_BOUND, RECV_TIME_BOUND, SEND_TIME_BOUND,
+    connection::{Connecting, WeakConnectionHandle},
+    egress::EgressBudget,
+    work_limiter::WorkLimiter,
+    ConnectionEvent, EndpointConfig, EndpointEvent, VarInt, IO_LOOP_BOUND, RECV_TIME_BOUND,
+    SEND_TIME_BOUND,
 };
 
 /// A QUIC endpoint.
@@ -179,7 +185,7 @@ impl Endpoint {
         server_name: &str,
     ) -> Result<Connecting, ConnectError> {
         let mut endpoint = self.inner.state.lock().unwrap();
-        if endpoint.driver_lost {
+        if endpoint.driver_lost || endpoint.connections.draining {
             return Err(ConnectError::EndpointStopping);
         }
         if addr.is_ipv6() && !endpoint.ipv6 {
@@ -190,11 +196,19 @@ impl Endpoint {
         } else {
             addr
         };
-        let (ch, conn) = endpoint.inner.connect(config, addr, server_name)?;
+        let (ch, mut conn) = endpoint.inner.connect(config, addr, server_name)?;
+        conn.set_departure_times_supported(endpoint.socket.txtime());
         let udp_state = endpoint.udp_state.clone();
-        Ok(endpoint
-            .connections
-            .insert(ch, conn, udp_state, self.runtime.clone()))
+        #[cfg(feature = "metrics")]
+        let metrics = endpoint.metrics.as_ref().map(EndpointMetrics::config);
+        Ok(endpoint.connections.insert(
+            ch,
+            conn,
+            udp_state,
+            self.runtime.clone(),
+            #[cfg(feature = "metrics")]
+            metrics,
+        ))
     }
 
     /// Switch to a new UDP socket
@@ -207,13 +221,13 @@ impl Endpoint {
         let addr = socket.local_addr()?;
         let socket = self.runtime.wrap_udp_socket(socket)?;
         let mut inner = self.inner.state.lock().unwrap();
+        let txtime = socket.txtime();
         inner.socket = socket;
         inner.ipv6 = addr.is_ipv6();
 
-        // Generate some activity so peers notice the rebind
         for sender in inner.connections.senders.values() {
             // Ignoring errors from dropped connections
-            let _ = sender.send(ConnectionEvent::Ping);
+            let _ = sender.send(ConnectionEvent::Rebind { txtime });
         }
 
         Ok(())
@@ -231,6 +245,54 @@ impl Endpoint {
             .set_server_config(server_config.map(Arc::new))
     }
 
+    /// Limit the rate at which the endpoint sends to `rate` bytes per second, or remove the limit
+    ///
+    /// Counts the UDP payload of every datagram sent on behalf of the endpoint's connections.
+    /// Connections which have more to send than the limit allows divide it in proportion to their
+    /// [weights](crate::Connection::set_egress_weight), while idle connections leave their share to
+    /// the others. Datagrams held back are queued rather than dropped, and are accounted for by
+    /// each connection's congestion controller as delay. Unlimited by default.
+    pub fn set_max_egress_rate(&self, rate: Option<u64>) {
+        let mut endpoint = self.inner.state.lock().unwrap();
+        endpoint.egress.set_rate(rate);
+        if let Some(driver) = endpoint.driver.as_ref() {
+            driver.wake_by_ref();
+        }
+    }
+
+    /// The current limit on the rate at which the endpoint sends, in bytes per second
+    pub fn max_egress_rate(&self) -> Option<u64> {
+        self.inner.state.lock().unwrap().egress.rate()
+    }
+
+    /// Bytes of stream data currently buffered across all of the endpoint's connections
+    ///
+    /// See [`EndpointConfig::stream_memory_budget()`].
+    pub fn stream_memory_usage(&self) -> u64 {
+        self.inner.state.lock().unwrap().inner.stream_memory_usage()
+    }
+
+    /// Statistics about the datagrams the endpoint handled outside of any connection
+    ///
+    /// Counts dropped datagrams by reason and incoming connection attempts by outcome, e.g. for
+    /// alerting on attacks or misconfiguration.
+    pub fn stats(&self) -> EndpointStats {
+        self.inner.state.lock().unwrap().inner.stats()
+    }
+
+    /// Publish the statistics of the endpoint and its connections through the `metrics` facade
+    ///
+    /// Replaces any previous configuration, or stops publishing if `None`. Connections keep the
+    /// configuration in effect when they were created.
+    #[cfg(feature = "metrics")]
+    pub fn set_metrics(&self, config: Option<MetricsConfig>) {
+        let mut endpoint = self.inner.state.lock().unwrap();
+        endpoint.metrics = config.map(EndpointMetrics::new);
+        if let Some(driver) = endpoint.driver.as_ref() {
+            driver.wake_by_ref();
+        }
+    }
+
     /// Get the local `SocketAddr` the underlying socket is bound to
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
         self.inner.state.lock().unwrap().socket.local_addr()
@@ -251,6 +313,43 @@ impl Endpoint {
             .reject_new_connections();
     }
 
+    /// Handles to the endpoint's connections, including those still being established
+    ///
+    /// Useful e.g. to monitor connections, or to select some to evict with
+    /// [`close_connection()`](Self::close_connection).
+    pub fn connections(&self) -> Vec<WeakConnectionHandle> {
+        let endpoint = self.inner.state.lock().unwrap();
+        endpoint.connections.handles.values().cloned().collect()
+    }
+
+    /// Close the connection with the given [`stable_id`] immediately
+    ///
+    /// Returns `false` if the endpoint has no such connection. See [`Connection::close()`] for
+    /// details.
+    ///
+    /// [`stable_id`]: crate::Connection::stable_id
+    /// [`Connection::close()`]: crate::Connection::close
+    pub fn close_connection(&self, stable_id: usize, error_code: VarInt, reason: &[u8]) -> bool {
+        let endpoint = self.inner.state.lock().unwrap();
+        let sender = endpoint
+            .connections
+            .handles
+            .iter()
+            .find(|(_, conn)| conn.stable_id() == stable_id)
+            .and_then(|(handle, _)| endpoint.connections.senders.get(handle));
+        match sender {
+            Some(sender) => {
+                // Ignoring errors from dropped connections
+                let _ = sender.send(ConnectionEvent::Close {
+                    error_code,
+                    reason: Bytes::copy_from_slice(reason),
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Close all of this endpoint's connections immediately and cease accepting new connections.
     ///
     /// See [`Connection::close()`] for details.
@@ -259,15 +358,32 @@ impl Endpoint {
     pub fn close(&self, error_code: VarInt, reason: &[u8]) {
         let reason = Bytes::copy_from_slice(reason);
         let mut endpoint = self.inner.state.lock().unwrap();
-        endpoint.connections.close = Some((error_code, reason.clone()));
-        for sender in endpoint.connections.senders.values() {
-            // Ignoring errors from dropped connections
-            let _ = sender.send(ConnectionEvent::Close {
-                error_code,
-                reason: reason.clone(),
-            });
+        endpoint.connections.close(error_code, reason);
+        self.inner.shared.incoming.notify_waiters();
+    }
+
+    /// Shut down gracefully, closing the connections still open at `deadline`
+    ///
+    /// Rejects new incoming connections, fails new outgoing ones, and signals existing connections
+    /// through [`Connection::endpoint_draining()`] so that the application can wind them down.
+    /// Completes once all connections are closed. Any still open at `deadline` are closed with
+    /// `error_code` and `reason` as if by [`close()`](Self::close), even if the returned future
+    /// is dropped.
+    ///
+    /// [`Connection::endpoint_draining()`]: crate::Connection::endpoint_draining
+    pub async fn drain(&self, deadline: Instant, error_code: VarInt, reason: &[u8]) {
+        {
+            let mut endpoint = self.inner.state.lock().unwrap();
+            endpoint.inner.reject_new_connections();
+            endpoint.connections.drain();
+            endpoint.drain_timer = Some(self.runtime.new_timer(deadline));
+            endpoint.drain_close = Some((error_code, Bytes::copy_from_slice(reason)));
+            if let Some(driver) = endpoint.driver.as_ref() {
+                driver.wake_by_ref();
+            }
         }
         self.inner.shared.incoming.notify_waiters();
+        self.wait_idle().await;
     }
 
     /// Wait for all connections on the endpoint to be cleanly shut down
@@ -322,8 +438,12 @@ impl Future for EndpointDriver {
         let now = Instant::now();
         let mut keep_going = false;
         keep_going |= endpoint.drive_recv(cx, now)?;
+        endpoint.handle_path_mtu_errors(now);
         keep_going |= endpoint.handle_events(cx, &self.0.shared);
-        keep_going |= endpoint.drive_send(cx)?;
+        keep_going |= endpoint.drive_send(cx, now)?;
+        endpoint.drive_drain(cx);
+        #[cfg(feature = "metrics")]
+        endpoint.publish_metrics(now);
 
         if !endpoint.incoming.is_empty() {
             self.0.shared.incoming.notify_waiters();
@@ -352,6 +472,7 @@ impl Drop for EndpointDriver {
         // Drop all outgoing channels, signaling the termination of the endpoint to the associated
         // connections.
         endpoint.connections.senders.clear();
+        endpoint.connections.handles.clear();
     }
 }
 
@@ -378,7 +499,16 @@ pub(crate) struct State {
     recv_limiter: WorkLimiter,
     recv_buf: Box<[u8]>,
     send_limiter: WorkLimiter,
+    egress: EgressBudget,
+    /// Set while datagrams are held back by `egress`
+    egress_timer: Option<Pin<Box<dyn AsyncTimer>>>,
+    /// Set while draining, until the deadline to close the remaining connections
+    drain_timer: Option<Pin<Box<dyn AsyncTimer>>>,
+    /// What to close the connections remaining at the drain deadline with
+    drain_close: Option<(VarInt, Bytes)>,
     runtime: Arc<dyn Runtime>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<EndpointMetrics>,
 }
 
 #[derive(Debug)]
@@ -417,12 +547,15 @@ impl State {
                                 meta.ecn.map(proto_ecn),
                                 buf,
                             ) {
-                                Some(DatagramEvent::NewConnection(handle, conn)) => {
+                                Some(DatagramEvent::NewConnection(handle, mut conn)) => {
+                                    conn.set_departure_times_supported(self.socket.txtime());
                                     let conn = self.connections.insert(
                                         handle,
                                         conn,
                                         self.udp_state.clone(),
                                         self.runtime.clone(),
+                                        #[cfg(feature = "metrics")]
+                                        self.metrics.as_ref().map(EndpointMetrics::config),
                                     );
                                     self.incoming.push_back(conn);
                                 }
@@ -465,7 +598,77 @@ impl State {
         Ok(false)
     }
 
-    fn drive_send(&mut self, cx: &mut Context) -> Result<bool, io::Error> {
+    fn handle_path_mtu_errors(&mut self, now: Instant) {
+        while let Some(error) = self.socket.path_mtu_error() {
+            for (handle, event) in self.inner.handle_path_mtu_error(
+                now,
+                error.destination,
+                error.max_udp_payload_size,
+                &error.contents,
+            ) {
+                // Ignoring errors from dropped connections that haven't yet been cleaned up
+                let _ = self
+                    .connections
+                    .senders
+                    .get_mut(&handle)
+                    .unwrap()
+                    .send(ConnectionEvent::Proto(event));
+            }
+        }
+    }
+
+    /// Release the datagrams the egress budget allows into `outgoing`
+    fn drive_egress(&mut self, cx: &mut Context, mut now: Instant) {
+        loop {
+            let deadline = self.egress.poll(now, &mut self.outgoing);
+            for ch in self.egress.take_unblocked() {
+                self.send_egress_blocked(ch, false);
+            }
+            let deadline = match deadline {
+                Some(x) => x,
+                None => {
+                    self.egress_timer = None;
+                    return;
+                }
+            };
+            let timer = match self.egress_timer {
+                Some(ref mut timer) => {
+                    timer.as_mut().reset(deadline);
+                    timer
+                }
+                None => self.egress_timer.insert(self.runtime.new_timer(deadline)),
+            };
+            if timer.as_mut().poll(cx).is_pending() {
+                return;
+            }
+            now = Instant::now();
+        }
+    }
+
+    fn send_egress_blocked(&mut self, ch: ConnectionHandle, blocked: bool) {
+        if let Some(sender) = self.connections.senders.get_mut(&ch) {
+            // Ignoring errors from dropped connections that haven't yet been cleaned up
+            let _ = sender.send(ConnectionEvent::EgressBlocked(blocked));
+        }
+    }
+
+    /// Close the remaining connections once the drain deadline passes
+    fn drive_drain(&mut self, cx: &mut Context) {
+        let timer = match self.drain_timer {
+            Some(ref mut timer) => timer,
+            None => return,
+        };
+        if timer.as_mut().poll(cx).is_pending() {
+            return;
+        }
+        self.drain_timer = None;
+        if let Some((error_code, reason)) = self.drain_close.take() {
+            self.connections.close(error_code, reason);
+        }
+    }
+
+    fn drive_send(&mut self, cx: &mut Context, now: Instant) -> Result<bool, io::Error> {
+        self.drive_egress(cx, now);
         self.send_limiter.start_cycle();
 
         let result = loop {
@@ -500,6 +703,13 @@ impl State {
         result
     }
 
+    #[cfg(feature = "metrics")]
+    fn publish_metrics(&mut self, now: Instant) {
+        if let Some(metrics) = self.metrics.as_mut() {
+            metrics.poll(now, self.inner.stats(), self.connections.senders.len());
+        }
+    }
+
     fn handle_events(&mut self, cx: &mut Context, shared: &Shared) -> bool {
         use EndpointEvent::*;
 
@@ -509,6 +719,7 @@ impl State {
                     Proto(e) => {
                         if e.is_drained() {
                             self.connections.senders.remove(&ch);
+                            self.connections.handles.remove(&ch);
                             if self.connections.is_empty() {
                                 shared.idle.notify_waiters();
                             }
@@ -523,7 +734,15 @@ impl State {
                                 .send(ConnectionEvent::Proto(event));
                         }
                     }
-                    Transmit(t) => self.outgoing.push_back(udp_transmit(t)),
+                    Transmit(t, weight) => {
+                        if self.egress.is_limited() {
+                            if self.egress.push(ch, weight, udp_transmit(t)) {
+                                self.send_egress_blocked(ch, true);
+                            }
+                        } else {
+                            self.outgoing.push_back(udp_transmit(t));
+                        }
+                    }
                 },
                 Poll::Ready(None) => unreachable!("EndpointInner owns one sender"),
                 Poll::Pending => {
@@ -541,9 +760,11 @@ fn udp_transmit(t: proto::Transmit) -> udp::Transmit {
     udp::Transmit {
         destination: t.destination,
         ecn: t.ecn.map(udp_ecn),
+        dscp: t.dscp,
         contents: t.contents,
         segment_size: t.segment_size,
         src_ip: t.src_ip,
+        departure_time: t.departure_time,
     }
 }
 
@@ -569,10 +790,14 @@ fn proto_ecn(ecn: udp::EcnCodepoint) -> proto::EcnCodepoint {
 struct ConnectionSet {
     /// Senders for communicating with the endpoint's connections
     senders: FxHashMap<ConnectionHandle, mpsc::UnboundedSender<ConnectionEvent>>,
+    /// Handles given out by `Endpoint::connections`
+    handles: FxHashMap<ConnectionHandle, WeakConnectionHandle>,
     /// Stored to give out clones to new ConnectionInners
     sender: mpsc::UnboundedSender<(ConnectionHandle, EndpointEvent)>,
     /// Set if the endpoint has been manually closed
     close: Option<(VarInt, Bytes)>,
+    /// Whether the endpoint is draining
+    draining: bool,
 }
 
 impl ConnectionSet {
@@ -582,6 +807,7 @@ impl ConnectionSet {
         conn: proto::Connection,
         udp_state: Arc<UdpState>,
         runtime: Arc<dyn Runtime>,
+        #[cfg(feature = "metrics")] metrics: Option<Arc<MetricsConfig>>,
     ) -> Connecting {
         let (send, recv) = mpsc::unbounded_channel();
         if let Some((error_code, ref reason)) = self.close {
@@ -591,13 +817,46 @@ impl ConnectionSet {
             })
             .unwrap();
         }
+        if self.draining {
+            send.send(ConnectionEvent::EndpointDraining).unwrap();
+        }
         self.senders.insert(handle, send);
-        Connecting::new(handle, conn, self.sender.clone(), recv, udp_state, runtime)
+        let conn = Connecting::new(
+            handle,
+            conn,
+            self.sender.clone(),
+            recv,
+            udp_state,
+            runtime,
+            #[cfg(feature = "metrics")]
+            metrics,
+        );
+        self.handles.insert(handle, conn.weak_handle());
+        conn
     }
 
     fn is_empty(&self) -> bool {
         self.senders.is_empty()
     }
+
+    fn close(&mut self, error_code: VarInt, reason: Bytes) {
+        for sender in self.senders.values() {
+            // Ignoring errors from dropped connections
+            let _ = sender.send(ConnectionEvent::Close {
+                error_code,
+                reason: reason.clone(),
+            });
+        }
+        self.close = Some((error_code, reason));
+    }
+
+    fn drain(&mut self) {
+        self.draining = true;
+        for sender in self.senders.values() {
+            // Ignoring errors from dropped connections
+            let _ = sender.send(ConnectionEvent::EndpointDraining);
+        }
+    }
 }
 
 fn ensure_ipv6(x: SocketAddr) -> SocketAddrV6 {
@@ -627,7 +886,7 @@ impl<'a> Future for Accept<'a> {
         if let Some(conn) = endpoint.incoming.pop_front() {
             return Poll::Ready(Some(conn));
         }
-        if endpoint.connections.close.is_some() {
+        if endpoint.connections.close.is_some() || endpoint.connections.draining {
             return Poll::Ready(None);
         }
         loop {
@@ -677,15 +936,23 @@ impl EndpointRef {
                 driver: None,
                 connections: ConnectionSet {
                     senders: FxHashMap::default(),
+                    handles: FxHashMap::default(),
                     sender,
                     close: None,
+                    draining: false,
                 },
                 ref_count: 0,
                 driver_lost: false,
                 recv_buf: recv_buf.into(),
                 recv_limiter: WorkLimiter::new(RECV_TIME_BOUND),
                 send_limiter: WorkLimiter::new(SEND_TIME_BOUND),
+                egress: EgressBudget::new(),
+                egress_timer: None,
+                drain_timer: None,
+                drain_close: None,
                 runtime,
+                #[cfg(feature = "metrics")]
+                metrics: None,
             }),
         }))
     }
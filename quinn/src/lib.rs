@@ -53,7 +53,10 @@ macro_rules! ready {
 }
 
 mod connection;
+mod egress;
 mod endpoint;
+#[cfg(feature = "metrics")]
+mod metrics;
 mod mutex;
 mod recv_stream;
 mod runtime;
@@ -63,15 +66,17 @@ mod work_limiter;
 pub use proto::{
     congestion, crypto, ApplicationClose, Chunk, ClientConfig, ConfigError, ConnectError,
     ConnectionClose, ConnectionError, EndpointConfig, IdleTimeout, MtuDiscoveryConfig,
-    ServerConfig, StreamId, Transmit, TransportConfig, VarInt,
+    ServerConfig, SetDscpError, StreamId, Transmit, TransportConfig, VarInt,
 };
 pub use udp;
 
 pub use crate::connection::{
-    AcceptBi, AcceptUni, Connecting, Connection, OpenBi, OpenUni, ReadDatagram, SendDatagramError,
-    UnknownStream, ZeroRttAccepted,
+    AcceptBi, AcceptUni, Connecting, Connection, OpenBi, OpenUni, PeerBlocked, ReadDatagram,
+    SendDatagramError, UnknownStream, WeakConnectionHandle, ZeroRttAccepted,
 };
 pub use crate::endpoint::{Accept, Endpoint};
+#[cfg(feature = "metrics")]
+pub use crate::metrics::MetricsConfig;
 pub use crate::recv_stream::{ReadError, ReadExactError, ReadToEndError, RecvStream};
 #[cfg(feature = "runtime-async-std")]
 pub use crate::runtime::AsyncStdRuntime;
@@ -90,13 +95,22 @@ enum ConnectionEvent {
         reason: bytes::Bytes,
     },
     Proto(proto::ConnectionEvent),
-    Ping,
+    /// The endpoint switched to a new socket, which enforces departure times if `txtime` is set
+    Rebind {
+        txtime: bool,
+    },
+    /// The endpoint has started draining, see `Endpoint::drain`
+    EndpointDraining,
+    /// The endpoint's queue of the connection's datagrams held back by the egress rate limit has
+    /// filled up, or drained again
+    EgressBlocked(bool),
 }
 
 #[derive(Debug)]
 enum EndpointEvent {
     Proto(proto::EndpointEvent),
-    Transmit(proto::Transmit),
+    /// A datagram to send, and the sending connection's egress weight
+    Transmit(proto::Transmit, u32),
 }
 
 /// Maximum number of datagrams processed in send/recv calls to make before moving on to other processing
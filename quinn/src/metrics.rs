@@ -0,0 +1,252 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use metrics::{counter, decrement_gauge, histogram, increment_gauge, Label};
+use proto::{ConnectionStats, EndpointStats};
+
+/// Configuration for publishing statistics through the [`metrics`] crate facade
+///
+/// Statistics are published to the installed recorder, under names prefixed with
+/// `quinn.endpoint.` for those of the endpoint and `quinn.connection.` for those of its
+/// connections. Connection statistics are aggregated across the endpoint's connections: counters
+/// are summed, histograms are sampled from every connection, and gauges report totals.
+#[derive(Debug, Clone)]
+pub struct MetricsConfig {
+    pub(crate) interval: Duration,
+    pub(crate) labels: Vec<Label>,
+}
+
+impl MetricsConfig {
+    /// Create a configuration which publishes every second, without labels
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Minimum time between publications of the endpoint's or a connection's statistics
+    ///
+    /// Statistics are published as the endpoint or connection does work, so nothing is published
+    /// while they're idle. A connection's final statistics are published when it's dropped.
+    pub fn interval(&mut self, value: Duration) -> &mut Self {
+        self.interval = value;
+        self
+    }
+
+    /// Attach a label to every metric published
+    ///
+    /// Useful to tell apart the metrics of multiple endpoints in one process.
+    pub fn label(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.labels.push(Label::new(key.into(), value.into()));
+        self
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            labels: Vec::new(),
+        }
+    }
+}
+
+/// Publishes the statistics of an endpoint
+#[derive(Debug)]
+pub(crate) struct EndpointMetrics {
+    config: Arc<MetricsConfig>,
+    next_publish: Option<Instant>,
+    /// Statistics as of the last publication
+    published: EndpointStats,
+    /// Number of connections as of the last publication
+    connections: usize,
+}
+
+impl EndpointMetrics {
+    pub(crate) fn new(config: MetricsConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            next_publish: None,
+            published: EndpointStats::default(),
+            connections: 0,
+        }
+    }
+
+    /// Configuration to publish the statistics of new connections with
+    pub(crate) fn config(&self) -> Arc<MetricsConfig> {
+        self.config.clone()
+    }
+
+    /// Publish changes since the last publication, if the interval has elapsed
+    pub(crate) fn poll(&mut self, now: Instant, stats: EndpointStats, connections: usize) {
+        if self.next_publish.map_or(false, |t| now < t) {
+            return;
+        }
+        self.next_publish = Some(now + self.config.interval);
+
+        let labels = &self.config.labels;
+        macro_rules! publish {
+            ($($field:ident),*) => {$(
+                let (new, old) = (stats.$field, self.published.$field);
+                if new > old {
+                    let name = concat!("quinn.endpoint.", stringify!($field));
+                    counter!(name, new - old, labels.iter());
+                }
+            )*};
+        }
+        publish!(
+            malformed_headers,
+            unsupported_versions,
+            version_negotiations_sent,
+            short_initials,
+            unsupported_crypto_versions,
+            initial_decryption_failures,
+            invalid_reserved_bits,
+            unknown_connection,
+            stateless_resets_sent,
+            retries_sent,
+            handshakes_refused,
+            invalid_retry_tokens,
+            invalid_initial_cids,
+            handshakes_failed,
+            handshakes_accepted
+        );
+        self.published = stats;
+
+        adjust_gauge(
+            "quinn.endpoint.connections",
+            &self.config,
+            self.connections,
+            connections,
+        );
+        self.connections = connections;
+    }
+}
+
+impl Drop for EndpointMetrics {
+    fn drop(&mut self) {
+        adjust_gauge(
+            "quinn.endpoint.connections",
+            &self.config,
+            self.connections,
+            0,
+        );
+    }
+}
+
+/// Publishes the statistics of a connection, to be aggregated with those of its endpoint's others
+pub(crate) struct ConnectionMetrics {
+    config: Arc<MetricsConfig>,
+    started: Instant,
+    next_publish: Instant,
+    /// Statistics as of the last publication
+    published: ConnectionStats,
+    /// Gauge values as of the last publication, withdrawn when the connection is dropped
+    send_streams: usize,
+    remote_streams: usize,
+}
+
+impl ConnectionMetrics {
+    pub(crate) fn new(config: Arc<MetricsConfig>, now: Instant) -> Self {
+        Self {
+            config,
+            started: now,
+            next_publish: now,
+            published: ConnectionStats::default(),
+            send_streams: 0,
+            remote_streams: 0,
+        }
+    }
+
+    /// Record the time taken by the handshake, which completed at `now`
+    pub(crate) fn connected(&self, now: Instant) {
+        histogram!(
+            "quinn.connection.handshake_duration",
+            now.saturating_duration_since(self.started),
+            self.config.labels.iter()
+        );
+    }
+
+    /// Publish changes since the last publication, if the interval has elapsed
+    pub(crate) fn poll(&mut self, now: Instant, conn: &mut proto::Connection) {
+        if now < self.next_publish {
+            return;
+        }
+        self.next_publish = now + self.config.interval;
+        self.publish(conn);
+    }
+
+    /// Publish the connection's final statistics, and withdraw its contribution to gauges
+    pub(crate) fn finish(mut self, conn: &mut proto::Connection) {
+        self.publish(conn);
+        adjust_gauge(
+            "quinn.connection.send_streams",
+            &self.config,
+            self.send_streams,
+            0,
+        );
+        adjust_gauge(
+            "quinn.connection.remote_streams_open",
+            &self.config,
+            self.remote_streams,
+            0,
+        );
+    }
+
+    fn publish(&mut self, conn: &mut proto::Connection) {
+        let stats = conn.stats();
+        let labels = &self.config.labels;
+        macro_rules! publish {
+            ($($name:literal => $($field:ident).+),*) => {$(
+                let (new, old) = (stats.$($field).+, self.published.$($field).+);
+                if new > old {
+                    counter!(concat!("quinn.connection.", $name), new - old, labels.iter());
+                }
+            )*};
+        }
+        publish!(
+            "bytes_sent" => udp_tx.bytes,
+            "bytes_received" => udp_rx.bytes,
+            "datagrams_sent" => udp_tx.datagrams,
+            "datagrams_received" => udp_rx.datagrams,
+            "lost_packets" => path.lost_packets,
+            "lost_bytes" => path.lost_bytes,
+            "congestion_events" => path.congestion_events
+        );
+        histogram!("quinn.connection.rtt", stats.path.rtt, labels.iter());
+        histogram!(
+            "quinn.connection.cwnd",
+            stats.path.cwnd as f64,
+            labels.iter()
+        );
+        self.published = stats;
+
+        let streams = conn.streams();
+        let send_streams = streams.send_streams();
+        let remote_streams = (streams.remote_open_streams(proto::Dir::Bi)
+            + streams.remote_open_streams(proto::Dir::Uni)) as usize;
+        adjust_gauge(
+            "quinn.connection.send_streams",
+            &self.config,
+            self.send_streams,
+            send_streams,
+        );
+        adjust_gauge(
+            "quinn.connection.remote_streams_open",
+            &self.config,
+            self.remote_streams,
+            remote_streams,
+        );
+        self.send_streams = send_streams;
+        self.remote_streams = remote_streams;
+    }
+}
+
+/// Move a gauge shared with other endpoints or connections by our change in contribution
+fn adjust_gauge(name: &'static str, config: &MetricsConfig, old: usize, new: usize) {
+    if new > old {
+        increment_gauge!(name, (new - old) as f64, config.labels.iter());
+    } else if old > new {
+        decrement_gauge!(name, (old - new) as f64, config.labels.iter());
+    }
+}
@@ -6,7 +6,7 @@ use std::{
 };
 
 use bytes::Bytes;
-use proto::{Chunk, Chunks, ConnectionError, ReadableError, StreamId};
+use proto::{Chunk, Chunks, ConnectionError, ReadableError, RecvStreamStats, StreamId};
 use thiserror::Error;
 use tokio::io::ReadBuf;
 
@@ -256,6 +256,37 @@ impl RecvStream {
         Ok(())
     }
 
+    /// Set the number of bytes beyond those read which the peer may send on this stream
+    ///
+    /// Overrides [`TransportConfig::stream_receive_window()`] for this stream, e.g. to give a bulk
+    /// transfer more room than small request streams, and stops it from being auto-tuned. A larger
+    /// window is announced to the peer right away, while a smaller one only takes effect as data
+    /// is read.
+    ///
+    /// [`TransportConfig::stream_receive_window()`]: crate::TransportConfig::stream_receive_window
+    pub fn set_receive_window(&self, window: VarInt) -> Result<(), UnknownStream> {
+        let mut conn = self.conn.state.lock("RecvStream::set_receive_window");
+        conn.inner
+            .recv_stream(self.stream)
+            .set_receive_window(window)?;
+        conn.wake();
+        Ok(())
+    }
+
+    /// Get the receive window of this stream
+    pub fn receive_window(&self) -> Result<u64, UnknownStream> {
+        let mut conn = self.conn.state.lock("RecvStream::receive_window");
+        Ok(conn.inner.recv_stream(self.stream).receive_window()?)
+    }
+
+    /// Get statistics about this stream
+    ///
+    /// Available until all of the stream's data has been read, or it has been stopped or reset.
+    pub fn stats(&self) -> Result<RecvStreamStats, UnknownStream> {
+        let mut conn = self.conn.state.lock("RecvStream::stats");
+        Ok(conn.inner.recv_stream(self.stream).stats()?)
+    }
+
     /// Check if this stream has been opened during 0-RTT.
     ///
     /// In which case any non-idempotent request should be considered dangerous at the application
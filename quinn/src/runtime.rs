@@ -9,7 +9,7 @@ use std::{
     time::Instant,
 };
 
-use udp::{RecvMeta, Transmit, UdpState};
+use udp::{PathMtuError, RecvMeta, Transmit, UdpState};
 
 /// Abstracts I/O and timer operations for runtime independence
 pub trait Runtime: Send + Sync + Debug + 'static {
@@ -58,6 +58,22 @@ pub trait AsyncUdpSocket: Send + Debug + 'static {
     fn may_fragment(&self) -> bool {
         true
     }
+
+    /// Take the oldest report of a sent datagram exceeding the path MTU, if any
+    ///
+    /// Reports are only produced by sockets which opted in, e.g. via
+    /// [`UdpSocketState::enable_path_mtu_errors`](udp::UdpSocketState::enable_path_mtu_errors).
+    fn path_mtu_error(&self) -> Option<PathMtuError> {
+        None
+    }
+
+    /// Whether [`Transmit::departure_time`] is enforced for datagrams sent on this socket
+    ///
+    /// Connections only offload pacing to sockets which do, see
+    /// [`TransportConfig::pacing_offload`](crate::TransportConfig::pacing_offload).
+    fn txtime(&self) -> bool {
+        false
+    }
 }
 
 /// Automatically select an appropriate runtime from those enabled at compile time
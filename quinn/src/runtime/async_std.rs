@@ -25,9 +25,12 @@ impl Runtime for AsyncStdRuntime {
 
     fn wrap_udp_socket(&self, sock: std::net::UdpSocket) -> io::Result<Box<dyn AsyncUdpSocket>> {
         udp::UdpSocketState::configure((&sock).into())?;
+        let mut inner = udp::UdpSocketState::new();
+        // Opportunistically enable departure times, only used if pacing offload is configured
+        let _ = inner.enable_txtime((&sock).into());
         Ok(Box::new(UdpSocket {
             io: Async::new(sock)?,
-            inner: udp::UdpSocketState::new(),
+            inner,
         }))
     }
 }
@@ -84,4 +87,12 @@ impl AsyncUdpSocket for UdpSocket {
     fn may_fragment(&self) -> bool {
         udp::may_fragment()
     }
+
+    fn path_mtu_error(&self) -> Option<udp::PathMtuError> {
+        self.inner.path_mtu_error()
+    }
+
+    fn txtime(&self) -> bool {
+        self.inner.txtime()
+    }
 }
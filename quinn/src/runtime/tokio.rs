@@ -28,9 +28,12 @@ impl Runtime for TokioRuntime {
 
     fn wrap_udp_socket(&self, sock: std::net::UdpSocket) -> io::Result<Box<dyn AsyncUdpSocket>> {
         udp::UdpSocketState::configure((&sock).into())?;
+        let mut inner = udp::UdpSocketState::new();
+        // Opportunistically enable departure times, only used if pacing offload is configured
+        let _ = inner.enable_txtime((&sock).into());
         Ok(Box::new(UdpSocket {
             io: tokio::net::UdpSocket::from_std(sock)?,
-            inner: udp::UdpSocketState::new(),
+            inner,
         }))
     }
 }
@@ -92,4 +95,12 @@ impl AsyncUdpSocket for UdpSocket {
     fn may_fragment(&self) -> bool {
         udp::may_fragment()
     }
+
+    fn path_mtu_error(&self) -> Option<udp::PathMtuError> {
+        self.inner.path_mtu_error()
+    }
+
+    fn txtime(&self) -> bool {
+        self.inner.txtime()
+    }
 }
@@ -6,7 +6,7 @@ use std::{
 };
 
 use bytes::Bytes;
-use proto::{ConnectionError, FinishError, StreamId, Written};
+use proto::{ConnectionError, FinishError, SendStreamStats, SetDscpError, StreamId, Written};
 use thiserror::Error;
 use tokio::sync::oneshot;
 
@@ -98,6 +98,8 @@ impl SendStream {
             Ok(result) => result,
             Err(Blocked) => {
                 conn.blocked_writers.insert(self.stream, cx.waker().clone());
+                // Let the driver report that we're blocked
+                conn.wake();
                 return Poll::Pending;
             }
             Err(Stopped(error_code)) => {
@@ -196,6 +198,71 @@ impl SendStream {
         Ok(conn.inner.send_stream(self.stream).priority()?)
     }
 
+    /// Set the DSCP to mark packets carrying data of this stream with
+    ///
+    /// Overrides [`TransportConfig::dscp()`](crate::TransportConfig::dscp) for this stream, e.g. to
+    /// mark bulk transfers as CS1 on an otherwise interactive connection. Data from streams with
+    /// different DSCPs is never sent in the same packet. Must be less than 64.
+    pub fn set_dscp(&self, dscp: Option<u8>) -> Result<(), SetDscpError> {
+        let mut conn = self.conn.state.lock("SendStream::set_dscp");
+        conn.inner.send_stream(self.stream).set_dscp(dscp)
+    }
+
+    /// Get the DSCP override of the send stream
+    pub fn dscp(&self) -> Result<Option<u8>, UnknownStream> {
+        let mut conn = self.conn.state.lock("SendStream::dscp");
+        Ok(conn.inner.send_stream(self.stream).dscp()?)
+    }
+
+    /// Limit the rate at which data of this stream is sent to `rate` bytes per second
+    ///
+    /// Applies in addition to any limit set by
+    /// [`Connection::set_send_rate_limit()`](crate::Connection::set_send_rate_limit). A rate of 0
+    /// pauses the stream.
+    pub fn set_rate_limit(&self, rate: Option<u64>) -> Result<(), UnknownStream> {
+        let mut conn = self.conn.state.lock("SendStream::set_rate_limit");
+        conn.inner.send_stream(self.stream).set_rate_limit(rate)?;
+        conn.wake();
+        Ok(())
+    }
+
+    /// Get the rate limit of the send stream, in bytes per second
+    pub fn rate_limit(&self) -> Result<Option<u64>, UnknownStream> {
+        let mut conn = self.conn.state.lock("SendStream::rate_limit");
+        Ok(conn.inner.send_stream(self.stream).rate_limit()?)
+    }
+
+    /// Limit the amount of unacknowledged data buffered for this stream to `limit` bytes
+    ///
+    /// Writes wait while the limit is reached, in addition to the connection-wide
+    /// [`TransportConfig::send_window()`](crate::TransportConfig::send_window), so that one bulk
+    /// transfer can't take up all of it.
+    pub fn set_send_buffer_limit(&self, limit: Option<u64>) -> Result<(), UnknownStream> {
+        let mut conn = self.conn.state.lock("SendStream::set_send_buffer_limit");
+        conn.inner
+            .send_stream(self.stream)
+            .set_send_buffer_limit(limit)?;
+        conn.wake();
+        Ok(())
+    }
+
+    /// Get the send buffer limit of the send stream, in bytes
+    pub fn send_buffer_limit(&self) -> Result<Option<u64>, UnknownStream> {
+        let mut conn = self.conn.state.lock("SendStream::send_buffer_limit");
+        Ok(conn.inner.send_stream(self.stream).send_buffer_limit()?)
+    }
+
+    /// Get statistics about the send stream
+    ///
+    /// Remain available once [`finish()`](Self::finish) has completed.
+    pub fn stats(&self) -> Result<SendStreamStats, UnknownStream> {
+        let mut conn = self.conn.state.lock("SendStream::stats");
+        if let Some(stats) = conn.finished_stats.get(&self.stream) {
+            return Ok(*stats);
+        }
+        Ok(conn.inner.send_stream(self.stream).stats()?)
+    }
+
     /// Completes if/when the peer stops the stream, yielding the error code
     pub async fn stopped(&mut self) -> Result<VarInt, StoppedError> {
         Stopped { stream: self }.await
@@ -266,6 +333,7 @@ impl Drop for SendStream {
 
         // clean up any previously registered wakers
         conn.finishing.remove(&self.stream);
+        conn.finished_stats.remove(&self.stream);
         conn.stopped.remove(&self.stream);
         conn.blocked_writers.remove(&self.stream);
 
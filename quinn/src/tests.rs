@@ -739,3 +739,235 @@ async fn two_datagram_readers() {
     assert!(*a == *b"one" || *b == *b"one");
     assert!(*a == *b"two" || *b == *b"two");
 }
+
+#[tokio::test]
+async fn max_egress_rate() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+    // The scheduling itself is covered by `egress`'s unit tests, so the rate is high enough to
+    // keep this quick
+    endpoint.set_max_egress_rate(Some(50_000_000));
+    assert_eq!(endpoint.max_egress_rate(), Some(50_000_000));
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+    client.set_egress_weight(4);
+    assert_eq!(client.egress_weight(), 4);
+
+    let (_, data) = tokio::join!(
+        async {
+            let mut send = client.open_uni().await.unwrap();
+            send.write_all(&[0; 500_000]).await.unwrap();
+            send.finish().await.unwrap();
+        },
+        async {
+            let mut recv = server.accept_uni().await.unwrap();
+            recv.read_to_end(usize::MAX).await.unwrap()
+        }
+    );
+    assert_eq!(data.len(), 500_000);
+}
+
+#[tokio::test]
+async fn peer_blocked() {
+    let _guard = subscribe();
+    let mut transport_config = TransportConfig::default();
+    transport_config.stream_receive_window(10u32.into());
+    let endpoint = endpoint_with_config(transport_config);
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    // Block more streams than there is room for reports
+    const STREAMS: u64 = 40;
+    for _ in 0..STREAMS {
+        let mut send = client.open_uni().await.unwrap();
+        tokio::spawn(async move {
+            let _ = send.write_all(&[0; 20]).await;
+        });
+    }
+    while server.stats().frame_rx.stream_data_blocked < STREAMS {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // Only the most recent reports are kept
+    for _ in 0..32 {
+        match server.peer_blocked().await.unwrap() {
+            crate::PeerBlocked::StreamData { limit: 10, .. } => {}
+            x => panic!("unexpected report: {x:?}"),
+        }
+    }
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), server.peer_blocked())
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn endpoint_connections() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    let mut handles = endpoint.connections();
+    handles.sort_by_key(|x| x.stable_id() != server.stable_id());
+    let ids = handles.iter().map(|x| x.stable_id()).collect::<Vec<_>>();
+    assert_eq!(ids, [server.stable_id(), client.stable_id()]);
+    assert_eq!(handles[0].remote_address(), Some(server.remote_address()));
+    assert!(handles[0].handshake_data().is_some());
+    assert!(handles[0].stats().unwrap().udp_rx.datagrams > 0);
+
+    assert!(!endpoint.close_connection(usize::MAX, 0u32.into(), b""));
+    assert!(endpoint.close_connection(server.stable_id(), 42u32.into(), b"evicted"));
+    match client.closed().await {
+        crate::ConnectionError::ApplicationClosed(close) => {
+            assert_eq!(close.error_code, 42u32.into());
+            assert_eq!(&close.reason[..], b"evicted");
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+
+    drop((client, server));
+    endpoint.wait_idle().await;
+    assert!(endpoint.connections().is_empty());
+    assert!(!handles[0].is_alive());
+    assert!(handles[0].stats().is_none());
+}
+
+#[tokio::test]
+async fn drain() {
+    let _guard = subscribe();
+    let server = endpoint();
+    let server_addr = server.local_addr().unwrap();
+    let mut client = Endpoint::client(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap();
+    client.set_default_client_config(server.default_client_config.clone().unwrap());
+
+    let mut connections = Vec::new();
+    for _ in 0..2 {
+        let (client_conn, server_conn) =
+            tokio::join!(client.connect(server_addr, "localhost").unwrap(), async {
+                server.accept().await.unwrap().await
+            });
+        connections.push((client_conn.unwrap(), server_conn.unwrap()));
+    }
+    let (straggler, graceful) = (connections.pop().unwrap(), connections.pop().unwrap());
+
+    let deadline = Instant::now() + Duration::from_millis(200);
+    let (_, graceful_err, straggler_err) = tokio::join!(
+        server.drain(deadline.into_std(), 7u32.into(), b"deadline"),
+        async {
+            // The application tells the peer to go away, which closes its connection promptly
+            graceful.1.endpoint_draining().await.unwrap();
+            graceful.1.close(0u32.into(), b"goaway");
+            let err = graceful.0.closed().await;
+            assert!(Instant::now() < deadline);
+            err
+        },
+        straggler.0.closed()
+    );
+    assert!(Instant::now() >= deadline);
+    match graceful_err {
+        crate::ConnectionError::ApplicationClosed(close) => {
+            assert_eq!(&close.reason[..], b"goaway")
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+    match straggler_err {
+        crate::ConnectionError::ApplicationClosed(close) => {
+            assert_eq!(close.error_code, 7u32.into());
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+    assert!(matches!(
+        server.connect(server_addr, "localhost"),
+        Err(crate::ConnectError::EndpointStopping)
+    ));
+    assert!(server.accept().await.is_none());
+}
+
+#[tokio::test]
+#[cfg(feature = "metrics")]
+async fn metrics() {
+    use metrics_util::{
+        debugging::{DebugValue, DebuggingRecorder},
+        CompositeKey, MetricKind,
+    };
+
+    let _guard = subscribe();
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    recorder.install().unwrap();
+
+    let endpoint = endpoint();
+    let mut config = crate::MetricsConfig::new();
+    config.interval(Duration::ZERO).label("test", "metrics");
+    endpoint.set_metrics(Some(config));
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+    let mut send = client.open_uni().await.unwrap();
+    send.write_all(&[0; 10_000]).await.unwrap();
+    send.finish().await.unwrap();
+    let mut recv = server.accept_uni().await.unwrap();
+    recv.read_to_end(usize::MAX).await.unwrap();
+    drop((send, recv));
+    client.close(0u32.into(), b"");
+    drop((client, server));
+    endpoint.wait_idle().await;
+    endpoint.set_metrics(None);
+
+    let labels = [("test", "metrics")];
+    let snapshot = snapshotter.snapshot().into_vec();
+    let get = |kind, name| {
+        let key = CompositeKey::new(kind, metrics::Key::from_parts(name, &labels));
+        snapshot
+            .iter()
+            .find(|(k, _, _, _)| *k == key)
+            .map(|(_, _, _, value)| value)
+    };
+    let counter = |name| match get(MetricKind::Counter, name) {
+        Some(DebugValue::Counter(x)) => *x,
+        x => panic!("unexpected {name}: {x:?}"),
+    };
+    let histogram = |name| match get(MetricKind::Histogram, name) {
+        Some(DebugValue::Histogram(x)) => x.len(),
+        x => panic!("unexpected {name}: {x:?}"),
+    };
+    let gauge = |name| match get(MetricKind::Gauge, name) {
+        Some(DebugValue::Gauge(x)) => x.into_inner(),
+        x => panic!("unexpected {name}: {x:?}"),
+    };
+
+    assert_eq!(counter("quinn.endpoint.handshakes_accepted"), 1);
+    assert!(counter("quinn.connection.bytes_sent") > 10_000);
+    assert!(counter("quinn.connection.bytes_received") > 10_000);
+    assert_eq!(histogram("quinn.connection.handshake_duration"), 2);
+    assert!(histogram("quinn.connection.rtt") > 0);
+    assert!(histogram("quinn.connection.cwnd") > 0);
+    assert_eq!(gauge("quinn.endpoint.connections"), 0.0);
+}